@@ -0,0 +1,261 @@
+//! Reply, reply-all and forward drafts built from a received message.
+//!
+//! Each builder returns an [`OutgoingEmail`] ready for the composer: subject,
+//! recipients, threading headers and a quoted body. The frontend still picks
+//! the `from` identity and lets the user edit everything before sending.
+
+use crate::mime::eml_file_name;
+use crate::models::{AttachmentData, EmailAddress, MessageSummary};
+use crate::smtp::OutgoingEmail;
+use crate::threading::strip_subject_prefixes;
+
+const REPLY_PREFIXES: &[&str] = &["re", "aw", "sv"];
+const FORWARD_PREFIXES: &[&str] = &["fwd", "fw", "wg"];
+
+/// `Re: <subject>`, without stacking prefixes (`Re: Re: ...`, `AW: ...`).
+pub fn reply_subject(subject: &str) -> String {
+    format!("Re: {}", strip_subject_prefixes(subject, REPLY_PREFIXES).0)
+}
+
+/// `Fwd: <subject>`, without stacking forward prefixes.
+pub fn forward_subject(subject: &str) -> String {
+    format!(
+        "Fwd: {}",
+        strip_subject_prefixes(subject, FORWARD_PREFIXES).0
+    )
+}
+
+/// Reply to the sender, or to Reply-To when the message has one. `body` is
+/// the original's rendered text, quoted below an attribution line.
+/// `own_addresses` are the account's `email_addresses`: replying to your own
+/// message goes to its recipients instead.
+pub fn reply(
+    original: &MessageSummary,
+    body: &str,
+    from: &str,
+    own_addresses: &[String],
+) -> OutgoingEmail {
+    let to = if is_own(&original.addresses.from, own_addresses) {
+        original.addresses.to.clone()
+    } else {
+        reply_targets(original)
+    };
+    OutgoingEmail {
+        from: from.to_string(),
+        to,
+        subject: reply_subject(&original.subject),
+        body: quote(original, body),
+        ..threading_headers(original)
+    }
+}
+
+/// Reply to the sender (or Reply-To) and everyone in To and Cc, minus
+/// `own_addresses` and duplicates.
+pub fn reply_all(
+    original: &MessageSummary,
+    body: &str,
+    from: &str,
+    own_addresses: &[String],
+) -> OutgoingEmail {
+    let mut draft = reply(original, body, from, own_addresses);
+    let mut seen: Vec<String> = own_addresses.iter().map(|a| a.to_lowercase()).collect();
+    let mut keep = |addr: &EmailAddress| {
+        let email = addr.email.to_lowercase();
+        let new = !seen.contains(&email);
+        seen.push(email);
+        new
+    };
+    let to: Vec<EmailAddress> = draft
+        .to
+        .iter()
+        .chain(&original.addresses.to)
+        .filter(|addr| keep(addr))
+        .cloned()
+        .collect();
+    let cc = original
+        .addresses
+        .cc
+        .iter()
+        .filter(|addr| keep(addr))
+        .cloned()
+        .collect();
+    draft.to = to;
+    draft.cc = cc;
+    draft
+}
+
+/// Forward inline: the original's headers and text in the body.
+pub fn forward(original: &MessageSummary, body: &str, from: &str) -> OutgoingEmail {
+    let mut text = String::from("\n\n---------- Forwarded message ----------\n");
+    text.push_str(&format!("From: {}\n", original.from));
+    text.push_str(&format!("Date: {}\n", original.date));
+    text.push_str(&format!("Subject: {}\n", original.subject));
+    if !original.to.is_empty() {
+        text.push_str(&format!("To: {}\n", original.to));
+    }
+    text.push('\n');
+    text.push_str(body);
+    OutgoingEmail {
+        from: from.to_string(),
+        subject: forward_subject(&original.subject),
+        body: text,
+        ..Default::default()
+    }
+}
+
+/// Forward the original untouched as a `message/rfc822` attachment. `raw`
+/// is its source, e.g. from `ImapSession::fetch_raw`.
+pub fn forward_as_attachment(original: &MessageSummary, raw: &[u8], from: &str) -> OutgoingEmail {
+    OutgoingEmail {
+        from: from.to_string(),
+        subject: forward_subject(&original.subject),
+        attachments: vec![AttachmentData {
+            filename: eml_file_name(original),
+            mime_type: "message/rfc822".into(),
+            data: raw.to_vec(),
+            content_id: None,
+        }],
+        ..Default::default()
+    }
+}
+
+/// Reply-To if present, else From.
+fn reply_targets(original: &MessageSummary) -> Vec<EmailAddress> {
+    if !original.addresses.reply_to.is_empty() {
+        return original.addresses.reply_to.clone();
+    }
+    if let Some(list) = original
+        .reply_to
+        .as_deref()
+        .and_then(|header| EmailAddress::parse_list(header).ok())
+        .filter(|list| !list.is_empty())
+    {
+        return list;
+    }
+    original.addresses.from.clone()
+}
+
+fn is_own(addresses: &[EmailAddress], own_addresses: &[String]) -> bool {
+    addresses.iter().any(|addr| {
+        own_addresses
+            .iter()
+            .any(|own| own.eq_ignore_ascii_case(&addr.email))
+    })
+}
+
+/// In-Reply-To is the original; References is its References (or, lacking
+/// those, its In-Reply-To) followed by the original.
+fn threading_headers(original: &MessageSummary) -> OutgoingEmail {
+    let id = bracketed(&original.message_id);
+    let mut chain: Vec<String> = if original.references.is_empty() {
+        original
+            .in_reply_to
+            .iter()
+            .filter_map(|id| bracketed(id))
+            .collect()
+    } else {
+        original
+            .references
+            .iter()
+            .filter_map(|id| bracketed(id))
+            .collect()
+    };
+    chain.extend(id.clone());
+    OutgoingEmail {
+        in_reply_to: id,
+        references: (!chain.is_empty()).then(|| chain.join(" ")),
+        ..Default::default()
+    }
+}
+
+fn bracketed(id: &str) -> Option<String> {
+    let id = id.trim().trim_start_matches('<').trim_end_matches('>');
+    (!id.is_empty()).then(|| format!("<{id}>"))
+}
+
+/// `On <date>, <sender> wrote:` and the body with every line quoted.
+fn quote(original: &MessageSummary, body: &str) -> String {
+    let mut text = format!("\n\nOn {}, {} wrote:\n", original.date, original.from);
+    for line in body.trim_end().lines() {
+        if line.starts_with('>') || line.is_empty() {
+            text.push('>');
+        } else {
+            text.push_str("> ");
+        }
+        text.push_str(line);
+        text.push('\n');
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{forward_as_attachment, forward_subject, reply, reply_all, reply_subject};
+    use crate::models::{EmailAddress, MessageAddresses, MessageSummary};
+
+    fn addresses(list: &str) -> Vec<EmailAddress> {
+        EmailAddress::parse_list(list).expect("addresses")
+    }
+
+    fn original() -> MessageSummary {
+        MessageSummary {
+            subject: "AW: Re: Plans".into(),
+            from: "Ann <ann@x.org>".into(),
+            to: "me@example.com, Bob <bob@y.org>".into(),
+            date: "Mon, 5 Oct 2026 10:00:00 +0000".into(),
+            addresses: MessageAddresses {
+                from: addresses("Ann <ann@x.org>"),
+                to: addresses("Me@Example.com, Bob <bob@y.org>"),
+                cc: addresses("carol@z.org, ann@x.org"),
+                reply_to: addresses("list@x.org"),
+                ..Default::default()
+            },
+            message_id: "c@x.org".into(),
+            in_reply_to: Some("b@x.org".into()),
+            references: vec!["a@x.org".into(), "b@x.org".into()],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn replies_thread_quote_and_drop_own_addresses() {
+        assert_eq!(reply_subject("Re: RE[2]: Plans"), "Re: Plans");
+        assert_eq!(forward_subject("Fwd: FW: Plans"), "Fwd: Plans");
+        assert_eq!(forward_subject("Re: Plans"), "Fwd: Re: Plans");
+
+        let own = vec!["me@example.com".to_string()];
+        let draft = reply(&original(), "Hi\n\n> earlier\n", "me@example.com", &own);
+        assert_eq!(draft.subject, "Re: Plans");
+        assert_eq!(draft.to, addresses("list@x.org"));
+        assert_eq!(draft.in_reply_to.as_deref(), Some("<c@x.org>"));
+        assert_eq!(
+            draft.references.as_deref(),
+            Some("<a@x.org> <b@x.org> <c@x.org>")
+        );
+        assert_eq!(
+            draft.body,
+            "\n\nOn Mon, 5 Oct 2026 10:00:00 +0000, Ann <ann@x.org> wrote:\n> Hi\n>\n>> earlier\n"
+        );
+
+        let all = reply_all(&original(), "Hi", "me@example.com", &own);
+        assert_eq!(all.to, addresses("list@x.org, Bob <bob@y.org>"));
+        assert_eq!(all.cc, addresses("carol@z.org, ann@x.org"));
+
+        let mut mine = original();
+        mine.addresses.from = addresses("me@example.com");
+        let draft = reply(&mine, "Hi", "me@example.com", &own);
+        assert_eq!(draft.to, mine.addresses.to);
+    }
+
+    #[test]
+    fn forward_as_attachment_carries_the_raw_source() {
+        let raw = b"From: ann@x.org\r\nSubject: Plans\r\n\r\nHi\r\n";
+        let draft = forward_as_attachment(&original(), raw, "me@example.com");
+        assert_eq!(draft.subject, "Fwd: AW: Re: Plans");
+        assert!(draft.in_reply_to.is_none());
+        let attachment = &draft.attachments[0];
+        assert_eq!(attachment.mime_type, "message/rfc822");
+        assert_eq!(attachment.data, raw);
+        assert!(attachment.filename.ends_with(".eml"));
+    }
+}
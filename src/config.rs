@@ -17,6 +17,96 @@ pub fn new_account_id() -> AccountId {
 /// Synthetic ID for env-var-based accounts (stable across restarts).
 pub const ENV_ACCOUNT_ID: &str = "env-account";
 
+// ---------------------------------------------------------------------------
+// Transport security
+// ---------------------------------------------------------------------------
+
+/// How a connection to the server is secured.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransportSecurity {
+    /// TLS from the first byte (IMAP 993, SMTP 465).
+    Implicit,
+    /// Plaintext greeting upgraded with STARTTLS (IMAP 143, SMTP 587).
+    Starttls,
+    /// No encryption at all. Only for local test servers.
+    None,
+}
+
+impl std::str::FromStr for TransportSecurity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "implicit" | "tls" | "ssl" => Ok(Self::Implicit),
+            "starttls" => Ok(Self::Starttls),
+            "none" | "plain" | "plaintext" => Ok(Self::None),
+            other => Err(format!("Unknown transport security '{other}'")),
+        }
+    }
+}
+
+/// Per-server TLS settings. Every field is optional; the defaults are the
+/// legacy `starttls` flag and the system trust store.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TlsSettings {
+    /// Overrides the `starttls` / `use_starttls` flag when set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub security: Option<TransportSecurity>,
+    /// PEM bundle of extra CA certificates to trust, e.g. an internal CA.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ca_file: Option<PathBuf>,
+    /// SHA-256 fingerprint of the server's own certificate (hex, colons
+    /// optional). When set, only that certificate is accepted and the CA
+    /// chain and hostname are not checked.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cert_sha256: Option<String>,
+}
+
+impl TlsSettings {
+    /// The effective security, falling back to the legacy STARTTLS flag.
+    pub fn security_or(&self, use_starttls: bool) -> TransportSecurity {
+        self.security.unwrap_or(if use_starttls {
+            TransportSecurity::Starttls
+        } else {
+            TransportSecurity::Implicit
+        })
+    }
+
+    /// Overlay `overrides` field by field.
+    pub fn merged(&self, overrides: &TlsSettings) -> TlsSettings {
+        TlsSettings {
+            security: overrides.security.or(self.security),
+            ca_file: overrides.ca_file.clone().or_else(|| self.ca_file.clone()),
+            cert_sha256: overrides
+                .cert_sha256
+                .clone()
+                .or_else(|| self.cert_sha256.clone()),
+        }
+    }
+
+    /// Read TLS settings from `{prefix}SECURITY`, `{prefix}CA_FILE` and
+    /// `{prefix}CERT_SHA256`.
+    fn from_env(prefix: &str) -> Self {
+        let var = |name: &str| {
+            std::env::var(format!("{prefix}{name}"))
+                .ok()
+                .filter(|v| !v.trim().is_empty())
+        };
+        TlsSettings {
+            security: var("SECURITY").and_then(|v| match v.parse() {
+                Ok(security) => Some(security),
+                Err(e) => {
+                    log::warn!("Ignoring {prefix}SECURITY: {e}");
+                    None
+                }
+            }),
+            ca_file: var("CA_FILE").map(PathBuf::from),
+            cert_sha256: var("CERT_SHA256"),
+        }
+    }
+}
+
 // ---------------------------------------------------------------------------
 // SMTP overrides — per-field optional, merged onto IMAP defaults
 // ---------------------------------------------------------------------------
@@ -33,6 +123,10 @@ pub struct SmtpOverrides {
     pub password: Option<PasswordBackend>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub use_starttls: Option<bool>,
+    /// Overlaid field by field on the account's `tls`, except `security`,
+    /// which falls back to `use_starttls`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls: Option<TlsSettings>,
 }
 
 // ---------------------------------------------------------------------------
@@ -52,6 +146,8 @@ pub struct FileAccountConfig {
     pub email_addresses: Vec<String>,
     #[serde(default)]
     pub smtp: SmtpOverrides,
+    #[serde(default)]
+    pub tls: TlsSettings,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -61,6 +157,25 @@ pub enum PasswordBackend {
     Keyring,
     #[serde(rename = "plaintext")]
     Plaintext { value: String },
+    /// No password: sign in with OAuth2 (XOAUTH2). The refresh token lives
+    /// in the keyring after [`crate::oauth::authorize`].
+    #[serde(rename = "oauth2")]
+    OAuth2(OAuth2Settings),
+}
+
+/// An OAuth2 client registration at the mail provider.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OAuth2Settings {
+    /// Authorization endpoint the browser is sent to.
+    pub auth_url: String,
+    /// Token endpoint for code exchange and refresh.
+    pub token_url: String,
+    pub client_id: String,
+    /// Only for providers that require one from installed apps.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_secret: Option<String>,
+    #[serde(default)]
+    pub scopes: Vec<String>,
 }
 
 // ---------------------------------------------------------------------------
@@ -98,6 +213,9 @@ pub struct SmtpConfig {
     pub username: String,
     pub password: String,
     pub use_starttls: bool,
+    pub tls: TlsSettings,
+    /// Sign in with an OAuth2 access token instead of `password`.
+    pub oauth2: Option<OAuth2Settings>,
 }
 
 impl SmtpConfig {
@@ -106,6 +224,8 @@ impl SmtpConfig {
         imap_server: &str,
         imap_username: &str,
         imap_password: &str,
+        imap_oauth2: Option<&OAuth2Settings>,
+        imap_tls: &TlsSettings,
         overrides: &SmtpOverrides,
         account_id: &str,
     ) -> Self {
@@ -118,14 +238,23 @@ impl SmtpConfig {
             .username
             .clone()
             .unwrap_or_else(|| imap_username.to_string());
-        let password = match &overrides.password {
-            Some(PasswordBackend::Plaintext { value }) => value.clone(),
-            Some(PasswordBackend::Keyring) => {
-                keyring::get_smtp_password(account_id).unwrap_or_else(|_| imap_password.to_string())
-            }
-            None => imap_password.to_string(),
+        let (password, oauth2) = match &overrides.password {
+            Some(PasswordBackend::Plaintext { value }) => (value.clone(), None),
+            Some(PasswordBackend::Keyring) => (
+                keyring::get_smtp_password(account_id)
+                    .unwrap_or_else(|_| imap_password.to_string()),
+                None,
+            ),
+            Some(PasswordBackend::OAuth2(settings)) => (String::new(), Some(settings.clone())),
+            None => (imap_password.to_string(), imap_oauth2.cloned()),
         };
         let use_starttls = overrides.use_starttls.unwrap_or(true);
+        // The IMAP security mode doesn't carry over: the ports differ.
+        let tls = TlsSettings {
+            security: None,
+            ..imap_tls.clone()
+        }
+        .merged(overrides.tls.as_ref().unwrap_or(&TlsSettings::default()));
 
         SmtpConfig {
             server,
@@ -133,9 +262,16 @@ impl SmtpConfig {
             username,
             password,
             use_starttls,
+            tls,
+            oauth2,
         }
     }
 
+    /// The effective transport security.
+    pub fn security(&self) -> TransportSecurity {
+        self.tls.security_or(self.use_starttls)
+    }
+
     /// Legacy: build from IMAP config with env var overrides (for env-var accounts).
     pub fn from_imap_config(config: &Config) -> Self {
         let server = std::env::var("NEVERLIGHT_MAIL_SMTP_SERVER")
@@ -144,12 +280,19 @@ impl SmtpConfig {
             .ok()
             .and_then(|p| p.parse().ok())
             .unwrap_or(587);
+        let tls = TlsSettings {
+            security: None,
+            ..config.tls.clone()
+        }
+        .merged(&TlsSettings::from_env("NEVERLIGHT_MAIL_SMTP_"));
         SmtpConfig {
             server,
             port,
             username: config.username.clone(),
             password: config.password.clone(),
             use_starttls: true,
+            tls,
+            oauth2: config.oauth2.clone(),
         }
     }
 }
@@ -166,6 +309,18 @@ pub struct Config {
     pub password: String,
     pub use_starttls: bool,
     pub email_addresses: Vec<String>,
+    #[serde(default)]
+    pub tls: TlsSettings,
+    /// Sign in with an OAuth2 access token instead of `password`.
+    #[serde(default)]
+    pub oauth2: Option<OAuth2Settings>,
+}
+
+impl Config {
+    /// The effective transport security.
+    pub fn security(&self) -> TransportSecurity {
+        self.tls.security_or(self.use_starttls)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -178,6 +333,8 @@ pub struct AccountConfig {
     pub password: String,
     pub use_starttls: bool,
     pub email_addresses: Vec<String>,
+    pub tls: TlsSettings,
+    pub oauth2: Option<OAuth2Settings>,
     pub smtp: SmtpConfig,
     pub smtp_overrides: SmtpOverrides,
 }
@@ -185,7 +342,19 @@ pub struct AccountConfig {
 impl AccountConfig {
     /// Build an AccountConfig from a FileAccountConfig + resolved password.
     pub fn from_file_account(fac: &FileAccountConfig, password: String) -> Self {
-        let smtp = SmtpConfig::resolve(&fac.server, &fac.username, &password, &fac.smtp, &fac.id);
+        let oauth2 = match &fac.password {
+            PasswordBackend::OAuth2(settings) => Some(settings.clone()),
+            _ => None,
+        };
+        let smtp = SmtpConfig::resolve(
+            &fac.server,
+            &fac.username,
+            &password,
+            oauth2.as_ref(),
+            &fac.tls,
+            &fac.smtp,
+            &fac.id,
+        );
         AccountConfig {
             id: fac.id.clone(),
             label: fac.label.clone(),
@@ -195,6 +364,8 @@ impl AccountConfig {
             password,
             use_starttls: fac.starttls,
             email_addresses: fac.email_addresses.clone(),
+            tls: fac.tls.clone(),
+            oauth2,
             smtp,
             smtp_overrides: fac.smtp.clone(),
         }
@@ -209,6 +380,8 @@ impl AccountConfig {
             password: self.password.clone(),
             use_starttls: self.use_starttls,
             email_addresses: self.email_addresses.clone(),
+            tls: self.tls.clone(),
+            oauth2: self.oauth2.clone(),
         }
     }
 }
@@ -329,6 +502,7 @@ impl MultiAccountFileConfig {
                     password: legacy.password,
                     email_addresses: legacy.email_addresses,
                     smtp: SmtpOverrides::default(),
+                    tls: TlsSettings::default(),
                 }],
             };
             // Write back migrated format
@@ -386,6 +560,8 @@ impl Config {
             password,
             use_starttls,
             email_addresses,
+            tls: TlsSettings::from_env("NEVERLIGHT_MAIL_"),
+            oauth2: None,
         })
     }
 
@@ -404,6 +580,8 @@ impl Config {
                 password: config.password.clone(),
                 use_starttls: config.use_starttls,
                 email_addresses: config.email_addresses.clone(),
+                tls: config.tls.clone(),
+                oauth2: config.oauth2.clone(),
                 smtp,
                 smtp_overrides: SmtpOverrides::default(),
             }]);
@@ -470,5 +648,48 @@ fn resolve_password(
     match backend {
         PasswordBackend::Plaintext { value } => Ok(value.clone()),
         PasswordBackend::Keyring => keyring::get_password(username, server),
+        // Access tokens are fetched at connect time.
+        PasswordBackend::OAuth2(_) => Ok(String::new()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FileAccountConfig, SmtpConfig, SmtpOverrides, TlsSettings, TransportSecurity};
+
+    #[test]
+    fn tls_settings_default_from_legacy_flags_and_overlay_for_smtp() {
+        let fac: FileAccountConfig = serde_json::from_str(
+            r#"{"id":"a","label":"a","server":"mail.corp","port":993,"username":"u",
+                "starttls":false,"password":{"backend":"plaintext","value":"p"},
+                "tls":{"ca_file":"/etc/corp-ca.pem"},
+                "smtp":{"use_starttls":false,"tls":{"cert_sha256":"AB:CD"}}}"#,
+        )
+        .expect("parse account");
+        assert_eq!(
+            fac.tls.security_or(fac.starttls),
+            TransportSecurity::Implicit
+        );
+
+        let smtp = SmtpConfig::resolve("mail.corp", "u", "p", None, &fac.tls, &fac.smtp, "a");
+        assert_eq!(smtp.security(), TransportSecurity::Implicit);
+        assert_eq!(smtp.tls.ca_file, fac.tls.ca_file);
+        assert_eq!(smtp.tls.cert_sha256.as_deref(), Some("AB:CD"));
+
+        let plain = TlsSettings {
+            security: Some(TransportSecurity::None),
+            ..Default::default()
+        };
+        let smtp = SmtpConfig::resolve(
+            "localhost",
+            "u",
+            "p",
+            None,
+            &plain,
+            &SmtpOverrides::default(),
+            "a",
+        );
+        assert_eq!(smtp.security(), TransportSecurity::Starttls);
+        assert_eq!("plaintext".parse(), Ok(TransportSecurity::None));
     }
 }
@@ -1,27 +1,77 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::sync::Arc;
+use std::time::Duration;
 
 use futures::StreamExt;
 use indexmap::IndexMap;
-use tokio::sync::Mutex;
+use tokio::sync::{mpsc, watch, Mutex, Notify};
+use tokio::task::JoinHandle;
 
 use melib::backends::{
-    BackendEventConsumer, EnvelopeHashBatch, FlagOp, IsSubscribedFn, MailBackend,
+    BackendEvent, BackendEventConsumer, BackendMailbox, EnvelopeHashBatch, FlagOp, IsSubscribedFn,
+    MailBackend, Mailbox, RefreshEvent, RefreshEventKind, SpecialUsageMailbox,
 };
 use melib::conf::AccountSettings;
 use melib::email::address::MessageID;
-use melib::email::attachment_types::{ContentType, Text};
-use melib::imap::ImapType;
-use melib::{AccountHash, EnvelopeHash, Mail, MailboxHash};
+use melib::email::attachment_types::{ContentTransferEncoding, ContentType, Text};
+use melib::email::{Address, Envelope, Flag, HeaderName};
+use melib::imap::email::common_attributes;
+use melib::imap::imap_codec::imap_types::command::CommandBody;
+use melib::imap::imap_codec::imap_types::sequence::SequenceSet;
+use melib::imap::{
+    fetch_responses, generate_envelope_hash, search_results, ConnectionMutex, FetchResponse,
+    ImapConnection, ImapType, RequiredResponses, UIDStore, UID,
+};
+use melib::{AccountHash, EnvelopeHash, Mail, MailboxHash, TagHash};
+
+use crate::config::{Config, TransportSecurity};
+use crate::mime::RemoteContent;
+use crate::models::{
+    apply_role_heuristics, sort_folders, AttachmentData, AttachmentInfo, ConnectionState,
+    EmailAddress, FetchCursor, Folder, FolderRole, MailEvent, MailboxDelta, MailboxSyncState,
+    MessageAddresses, MessagePage, MessageSummary,
+};
+use crate::oauth::{TokenError, TokenSource};
+use crate::store::{imap_flags_to_u8, set_summary_flags, user_keywords};
+use crate::tls::{Protocol, Relay, ServerCheck};
+
+mod bodystructure;
 
-use crate::config::Config;
-use crate::models::{AttachmentData, Folder, MessageSummary};
+use bodystructure::{attribute, body_parts, decode_transfer, fetch_attributes, BodyPart, Value};
+
+/// Raw melib events from the backend's event consumer, forwarded to the
+/// running watch task (if any).
+type EventSink = Arc<std::sync::Mutex<Option<mpsc::UnboundedSender<BackendEvent>>>>;
 
 /// A live IMAP session backed by melib.
 pub struct ImapSession {
     backend: Arc<Mutex<Box<ImapType>>>,
     /// Map from mailbox hash to folder path (for lookups).
     mailbox_paths: Mutex<HashMap<MailboxHash, String>>,
+    /// Sorted UIDs from the first page of each paged fetch, by the
+    /// cursor's snapshot token, so later pages don't search again.
+    page_uids: Mutex<PageSnapshots>,
+    event_sink: EventSink,
+    watch_task: Mutex<Option<JoinHandle<()>>>,
+    state: Arc<watch::Sender<ConnectionState>>,
+    /// Wakes the supervisor for an immediate health check.
+    health: Arc<Notify>,
+    supervisor: JoinHandle<()>,
+    /// Checks the certificate on every connection melib opens, when the
+    /// account has a CA file or pin.
+    _relay: Option<Arc<Relay>>,
+}
+
+/// How often the supervisor checks an idle, healthy connection.
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+/// Paged fetches whose UID list is kept; the oldest goes first.
+const MAX_PAGE_SNAPSHOTS: usize = 16;
+
+/// Exponential backoff before reconnect attempt `attempt` (1-based):
+/// 1s, 2s, 4s, ... capped at five minutes.
+fn backoff_delay(attempt: u32) -> Duration {
+    Duration::from_secs(1u64 << attempt.saturating_sub(1).min(16)).min(MAX_BACKOFF)
 }
 
 fn map_mailbox_counts(counts: (usize, usize)) -> (u32, u32) {
@@ -35,20 +85,67 @@ impl std::fmt::Debug for ImapSession {
     }
 }
 
+impl Drop for ImapSession {
+    fn drop(&mut self) {
+        self.supervisor.abort();
+        if let Some(task) = self.watch_task.get_mut().take() {
+            task.abort();
+        }
+    }
+}
+
 impl ImapSession {
     /// Connect to the IMAP server using the given config.
     pub async fn connect(config: Config) -> Result<Arc<Self>, String> {
+        let security = config.security();
+        // melib can't take extra roots or a pin: with either configured it
+        // talks plaintext to a loopback relay that checks each connection.
+        let relay = match ServerCheck::needed(
+            &config.imap_server,
+            config.imap_port,
+            security,
+            &config.tls,
+            Protocol::Imap,
+        ) {
+            Some(check) => Some(Arc::new(check.relay().await?)),
+            None => None,
+        };
         let mut extra = IndexMap::new();
-        extra.insert("server_hostname".into(), config.imap_server.clone());
+        let (host, port) = match &relay {
+            Some(relay) => (Relay::HOST.to_string(), relay.port()),
+            None => (config.imap_server.clone(), config.imap_port),
+        };
+        extra.insert("server_hostname".into(), host);
         extra.insert("server_username".into(), config.username.clone());
-        extra.insert("server_password".into(), config.password.clone());
-        extra.insert("server_port".into(), config.imap_port.to_string());
-        extra.insert("use_tls".into(), "true".into());
+        let tokens = config
+            .oauth2
+            .clone()
+            .map(|settings| Arc::new(TokenSource::new(settings, &config.username)));
+        let password = match &tokens {
+            Some(tokens) => tokens
+                .xoauth2_base64()
+                .await
+                .map_err(|e| format!("OAuth2 sign-in failed: {e}"))?,
+            None => config.password.clone(),
+        };
+        extra.insert("server_password".into(), password);
+        if tokens.is_some() {
+            extra.insert("use_oauth2".into(), "true".into());
+            // Pooled connections keep the token they were opened with; the
+            // main connection's is swapped before each reconnect.
+            extra.insert("use_connection_pool".into(), "false".into());
+        }
+        extra.insert("server_port".into(), port.to_string());
+        let flag = |on: bool| if on { "true" } else { "false" }.to_string();
+        let direct = relay.is_none();
+        extra.insert(
+            "use_tls".into(),
+            flag(direct && security != TransportSecurity::None),
+        );
         extra.insert(
             "use_starttls".into(),
-            if config.use_starttls { "true" } else { "false" }.into(),
+            flag(direct && security == TransportSecurity::Starttls),
         );
-        extra.insert("danger_accept_invalid_certs".into(), "false".into());
 
         let account_settings = AccountSettings {
             name: config.username.clone(),
@@ -59,37 +156,81 @@ impl ImapSession {
             ..Default::default()
         };
 
+        // Let the server's LSUB response decide subscription state; forcing
+        // `true` here would mask unsubscribes on the next mailbox listing.
         let is_subscribed: IsSubscribedFn =
-            (Arc::new(|_: &str| true) as Arc<dyn Fn(&str) -> bool + Send + Sync>).into();
+            (Arc::new(|_: &str| false) as Arc<dyn Fn(&str) -> bool + Send + Sync>).into();
 
+        let event_sink = EventSink::default();
+        let consumer_sink = Arc::clone(&event_sink);
         let event_consumer = BackendEventConsumer::new(Arc::new(
-            |_account_hash: AccountHash, event: melib::backends::BackendEvent| {
+            move |_account_hash: AccountHash, event: BackendEvent| {
                 log::debug!("IMAP backend event: {:?}", event);
+                if let Some(sink) = consumer_sink.lock().unwrap().as_ref() {
+                    let _ = sink.send(event);
+                }
             },
         ));
 
         let backend = ImapType::new(&account_settings, is_subscribed, event_consumer)
             .map_err(|e| format!("Failed to create IMAP backend: {}", e))?;
 
+        let backend = Arc::new(Mutex::new(backend));
+        let (state, _) = watch::channel(ConnectionState::Connecting);
+        let state = Arc::new(state);
+
+        // Verify we can connect
+        probe(&backend, relay.as_deref(), tokens.as_deref())
+            .await
+            .map_err(|e| format!("IMAP connection failed: {}", e))?;
+        state.send_replace(ConnectionState::Online);
+
+        let health = Arc::new(Notify::new());
+        let supervisor = tokio::spawn(supervise(
+            Arc::clone(&backend),
+            Arc::clone(&state),
+            Arc::clone(&health),
+            relay.clone(),
+            tokens,
+        ));
+
         let session = ImapSession {
-            backend: Arc::new(Mutex::new(backend)),
+            backend,
             mailbox_paths: Mutex::new(HashMap::new()),
+            page_uids: Mutex::new(PageSnapshots::default()),
+            event_sink,
+            watch_task: Mutex::new(None),
+            state,
+            health,
+            supervisor,
+            _relay: relay,
         };
 
-        // Verify we can connect
-        {
-            let backend = session.backend.lock().await;
-            let online_future = backend
-                .is_online()
-                .map_err(|e| format!("IMAP is_online failed: {}", e))?;
-            online_future
-                .await
-                .map_err(|e| format!("IMAP connection failed: {}", e))?;
-        }
-
         Ok(Arc::new(session))
     }
 
+    /// Subscribe to connection state changes. The receiver starts with the
+    /// current state.
+    pub fn connection_state(&self) -> watch::Receiver<ConnectionState> {
+        self.state.subscribe()
+    }
+
+    /// Check the connection now instead of waiting for the next backoff
+    /// step or health check. This is also the only way out of
+    /// [`ConnectionState::AuthFailed`].
+    pub fn reconnect(&self) {
+        self.health.notify_one();
+    }
+
+    /// Format a backend error, waking the supervisor if it looks like the
+    /// connection dropped.
+    fn backend_error(&self, context: &str, e: melib::error::Error) -> String {
+        if e.kind.is_network() || e.kind.is_timeout() {
+            self.health.notify_one();
+        }
+        format!("{}: {}", context, e)
+    }
+
     /// Fetch the list of folders (mailboxes) from the server.
     pub async fn fetch_folders(self: &Arc<Self>) -> Result<Vec<Folder>, String> {
         let future = {
@@ -101,44 +242,171 @@ impl ImapSession {
 
         let mailboxes = future
             .await
-            .map_err(|e| format!("Failed to fetch mailboxes: {}", e))?;
+            .map_err(|e| self.backend_error("Failed to fetch mailboxes", e))?;
 
         let mut folders: Vec<Folder> = Vec::with_capacity(mailboxes.len());
         let mut path_map = HashMap::new();
 
         for (hash, mailbox) in &mailboxes {
-            let counts = mailbox
-                .count()
-                .map_err(|e| format!("Failed to get mailbox count: {}", e))?;
-            let (unseen, total) = map_mailbox_counts(counts);
-
             path_map.insert(*hash, mailbox.path().to_string());
-
-            folders.push(Folder {
-                name: mailbox.name().to_string(),
-                path: mailbox.path().to_string(),
-                unread_count: unseen,
-                total_count: total,
-                mailbox_hash: hash.0,
-            });
+            folders.push(folder_from_mailbox(*hash, mailbox)?);
         }
 
-        // Sort: INBOX first, then alphabetical
-        folders.sort_by(|a, b| {
-            if a.path == "INBOX" {
-                std::cmp::Ordering::Less
-            } else if b.path == "INBOX" {
-                std::cmp::Ordering::Greater
-            } else {
-                a.path.cmp(&b.path)
-            }
-        });
+        apply_role_heuristics(&mut folders);
+        sort_folders(&mut folders);
 
         *self.mailbox_paths.lock().await = path_map;
 
         Ok(folders)
     }
 
+    /// Create a new folder at `path` (using `/` as the hierarchy separator)
+    /// and subscribe to it. Returns the new folder for
+    /// [`CacheHandle::upsert_folder`](crate::store::CacheHandle::upsert_folder).
+    pub async fn create_folder(self: &Arc<Self>, path: &str) -> Result<Folder, String> {
+        let future = {
+            let mut backend = self.backend.lock().await;
+            backend
+                .create_mailbox(path.to_string())
+                .map_err(|e| format!("Failed to request create folder: {}", e))?
+        };
+
+        let (new_hash, mailboxes) = future
+            .await
+            .map_err(|e| self.backend_error("Failed to create folder", e))?;
+
+        self.replace_mailbox_paths(&mailboxes).await;
+        folder_among(new_hash, &mailboxes)?
+            .ok_or_else(|| format!("Folder '{path}' missing after create"))
+    }
+
+    /// Rename a folder. Returns the renamed folder, which has a new mailbox
+    /// hash; pass both to
+    /// [`CacheHandle::rename_folder`](crate::store::CacheHandle::rename_folder).
+    pub async fn rename_folder(
+        self: &Arc<Self>,
+        mailbox_hash: MailboxHash,
+        new_path: &str,
+    ) -> Result<Folder, String> {
+        self.ensure_known_mailbox(mailbox_hash).await?;
+        let future = {
+            let mut backend = self.backend.lock().await;
+            backend
+                .rename_mailbox(mailbox_hash, new_path.to_string())
+                .map_err(|e| format!("Failed to request rename folder: {}", e))?
+        };
+
+        let mailbox = future
+            .await
+            .map_err(|e| self.backend_error("Failed to rename folder", e))?;
+
+        // melib relisted after the rename, so this is served from its cache.
+        let future = {
+            let backend = self.backend.lock().await;
+            backend
+                .mailboxes()
+                .map_err(|e| format!("Failed to request mailboxes: {}", e))?
+        };
+        let mailboxes = future
+            .await
+            .map_err(|e| self.backend_error("Failed to fetch mailboxes", e))?;
+        self.replace_mailbox_paths(&mailboxes).await;
+        folder_among(mailbox.hash(), &mailboxes)?
+            .ok_or_else(|| format!("Folder '{new_path}' missing after rename"))
+    }
+
+    /// Delete a folder and everything in it.
+    pub async fn delete_folder(self: &Arc<Self>, mailbox_hash: MailboxHash) -> Result<(), String> {
+        self.ensure_known_mailbox(mailbox_hash).await?;
+        let future = {
+            let mut backend = self.backend.lock().await;
+            backend
+                .delete_mailbox(mailbox_hash)
+                .map_err(|e| format!("Failed to request delete folder: {}", e))?
+        };
+
+        let mailboxes = future
+            .await
+            .map_err(|e| self.backend_error("Failed to delete folder", e))?;
+
+        self.replace_mailbox_paths(&mailboxes).await;
+        Ok(())
+    }
+
+    /// Subscribe to or unsubscribe from a folder.
+    pub async fn set_folder_subscribed(
+        self: &Arc<Self>,
+        mailbox_hash: MailboxHash,
+        subscribed: bool,
+    ) -> Result<(), String> {
+        self.ensure_known_mailbox(mailbox_hash).await?;
+        let future = {
+            let mut backend = self.backend.lock().await;
+            backend
+                .set_mailbox_subscription(mailbox_hash, subscribed)
+                .map_err(|e| format!("Failed to request subscription change: {}", e))?
+        };
+
+        future
+            .await
+            .map_err(|e| self.backend_error("Failed to change subscription", e))?;
+        Ok(())
+    }
+
+    /// melib indexes its mailbox map directly, so make sure the backend has
+    /// listed mailboxes and knows this hash before issuing a folder command.
+    async fn ensure_known_mailbox(
+        self: &Arc<Self>,
+        mailbox_hash: MailboxHash,
+    ) -> Result<(), String> {
+        if self.mailbox_paths.lock().await.contains_key(&mailbox_hash) {
+            return Ok(());
+        }
+        self.fetch_folders().await?;
+        if self.mailbox_paths.lock().await.contains_key(&mailbox_hash) {
+            Ok(())
+        } else {
+            Err(format!("Unknown folder {}", mailbox_hash.0))
+        }
+    }
+
+    /// melib's connection and UID maps for a mailbox, for commands the
+    /// `MailBackend` trait doesn't cover.
+    async fn raw_mailbox(
+        self: &Arc<Self>,
+        mailbox_hash: MailboxHash,
+    ) -> Result<RawMailbox, String> {
+        self.ensure_known_mailbox(mailbox_hash).await?;
+        let (connection, uid_store) = {
+            let backend = self.backend.lock().await;
+            (
+                Arc::clone(&backend.connection),
+                Arc::clone(&backend.uid_store),
+            )
+        };
+        let (imap_path, no_select) = {
+            let mailboxes = uid_store.mailboxes.lock().await;
+            let mailbox = mailboxes
+                .get(&mailbox_hash)
+                .ok_or_else(|| format!("Unknown folder {}", mailbox_hash.0))?;
+            (mailbox.imap_path().to_string(), mailbox.no_select)
+        };
+        Ok(RawMailbox {
+            hash: mailbox_hash,
+            imap_path,
+            no_select,
+            connection,
+            uid_store,
+        })
+    }
+
+    async fn replace_mailbox_paths(&self, mailboxes: &HashMap<MailboxHash, Mailbox>) {
+        *self.mailbox_paths.lock().await = mailboxes
+            .iter()
+            .map(|(hash, mailbox)| (*hash, mailbox.path().to_string()))
+            .collect();
+    }
+
     /// Fetch message summaries (envelopes) for a mailbox.
     pub async fn fetch_messages(
         self: &Arc<Self>,
@@ -148,67 +416,303 @@ impl ImapSession {
             let mut backend = self.backend.lock().await;
             backend
                 .fetch(mailbox_hash)
-                .map_err(|e| format!("Failed to start fetch: {}", e))?
+                .map_err(|e| self.backend_error("Failed to start fetch", e))?
         };
 
         let mut stream = std::pin::pin!(stream);
-        let mut messages = Vec::new();
+        let mut envelopes = Vec::new();
 
         while let Some(batch_result) = stream.next().await {
-            let envelopes = batch_result.map_err(|e| format!("Error fetching envelopes: {}", e))?;
-
-            for envelope in envelopes {
-                let from_str = envelope
-                    .from()
-                    .iter()
-                    .map(|a| a.to_string())
-                    .collect::<Vec<_>>()
-                    .join(", ");
-
-                let to_str = envelope
-                    .to()
-                    .iter()
-                    .map(|a| a.to_string())
-                    .collect::<Vec<_>>()
-                    .join(", ");
-
-                let msg_id = envelope.message_id().to_string();
-                let refs = envelope.references();
-                let thread_id = Some(compute_thread_id(&msg_id, refs));
-                let thread_depth = refs.len() as u32;
-                let in_reply_to = envelope
-                    .in_reply_to()
-                    .and_then(|r| r.refs().last().map(|id| id.to_string()));
-
-                let reply_to = envelope
-                    .other_headers()
-                    .get("Reply-To")
-                    .map(|s| s.to_string());
-
-                messages.push(MessageSummary {
-                    uid: envelope.hash().0,
-                    subject: envelope.subject().to_string(),
-                    from: from_str,
-                    to: to_str,
-                    date: envelope.date_as_str().to_string(),
-                    is_read: envelope.is_seen(),
-                    is_starred: envelope.flags().is_flagged(),
-                    has_attachments: envelope.has_attachments,
-                    thread_id,
-                    envelope_hash: envelope.hash().0,
-                    timestamp: envelope.timestamp as i64,
-                    mailbox_hash: mailbox_hash.0,
-                    message_id: msg_id,
-                    in_reply_to,
-                    reply_to,
-                    thread_depth,
-                });
+            envelopes.extend(
+                batch_result.map_err(|e| self.backend_error("Error fetching envelopes", e))?,
+            );
+        }
+
+        // Keywords arrive as tag hashes; resolve their names once the fetch
+        // has registered them all.
+        let tag_index = Arc::clone(&self.backend.lock().await.uid_store.collection.tag_index);
+        let mut messages: Vec<MessageSummary> = {
+            let tag_index = tag_index.read().unwrap();
+            envelopes
+                .iter()
+                .map(|envelope| {
+                    let keywords = envelope_keywords(envelope, &tag_index);
+                    summary_from_envelope(envelope, mailbox_hash, &keywords)
+                })
+                .collect()
+        };
+
+        if !messages.is_empty() {
+            match self.fetch_mailbox_reply_headers(mailbox_hash).await {
+                Ok(mut headers) => {
+                    for summary in &mut messages {
+                        if let Some(h) = headers.remove(&summary.envelope_hash) {
+                            set_reply_headers(summary, h);
+                        }
+                    }
+                }
+                Err(e) => log::warn!("{}", e),
             }
         }
 
         Ok(messages)
     }
 
+    /// Reply-To and Sender for every message in a mailbox, by envelope hash.
+    async fn fetch_mailbox_reply_headers(
+        self: &Arc<Self>,
+        mailbox_hash: MailboxHash,
+    ) -> Result<HashMap<u64, MessageAddresses>, String> {
+        let mailbox = self.raw_mailbox(mailbox_hash).await?;
+        let mut conn = mailbox
+            .connection
+            .lock()
+            .await
+            .map_err(|e| self.backend_error("IMAP connection error", e))?;
+        let mut response = Vec::with_capacity(8 * 1024);
+        conn.examine_mailbox(mailbox_hash, &mut response, true)
+            .await
+            .map_err(|e| self.backend_error("Failed to examine folder", e))?;
+        let headers = fetch_reply_headers(&mut conn, "1:*").await?;
+        Ok(headers
+            .into_iter()
+            .map(|(uid, h)| (generate_envelope_hash(&mailbox.imap_path, &uid).0, h))
+            .collect())
+    }
+
+    /// Fetch one window of a mailbox, newest first: the `count` highest UIDs,
+    /// or the `count` highest below `cursor` when continuing to older mail.
+    ///
+    /// Unlike [`fetch_messages`](Self::fetch_messages) this returns as soon
+    /// as one window is in. Store pages with
+    /// [`CacheHandle::merge_messages`](crate::store::CacheHandle::merge_messages),
+    /// which keeps messages outside the window. A cursor from before a
+    /// UIDVALIDITY change is rejected; start again without one.
+    pub async fn fetch_messages_page(
+        self: &Arc<Self>,
+        mailbox_hash: MailboxHash,
+        count: usize,
+        cursor: Option<FetchCursor>,
+    ) -> Result<MessagePage, String> {
+        let mailbox = self.raw_mailbox(mailbox_hash).await?;
+        if mailbox.no_select || count == 0 {
+            return Ok(MessagePage {
+                messages: Vec::new(),
+                next: None,
+            });
+        }
+
+        let mut conn = mailbox
+            .connection
+            .lock()
+            .await
+            .map_err(|e| self.backend_error("IMAP connection error", e))?;
+        let mut response = Vec::with_capacity(8 * 1024);
+        let select = conn
+            .examine_mailbox(mailbox_hash, &mut response, true)
+            .await
+            .map_err(|e| self.backend_error("Failed to examine folder", e))?;
+        let uidvalidity = select.uidvalidity as u64;
+
+        // The first page searches once; later pages with its cursor slice
+        // that UID list. Without one (say, after a reconnect) search below
+        // the cursor once and keep the result for the pages after it.
+        let before = match cursor {
+            Some(cursor) if cursor.uidvalidity != uidvalidity => {
+                return Err("Fetch cursor is stale: folder UIDVALIDITY changed".into());
+            }
+            Some(cursor) => Some(cursor.before_uid),
+            None => None,
+        };
+        let cached = match cursor {
+            Some(cursor) => self
+                .page_uids
+                .lock()
+                .await
+                .take(cursor.snapshot, mailbox_hash, uidvalidity)
+                .map(|uids| (cursor.snapshot, uids)),
+            None => None,
+        };
+        let (snapshot, uids) = match (cached, before) {
+            (Some(cached), _) => cached,
+            (None, Some(before)) if before <= 1 => (0, Vec::new()),
+            (None, Some(before)) => (
+                self.page_uids.lock().await.new_token(),
+                sorted_uids(
+                    search_uids(&mut conn, &format!("UID SEARCH UID 1:{}", before - 1)).await?,
+                ),
+            ),
+            (None, None) if select.exists == 0 => (0, Vec::new()),
+            (None, None) => (
+                self.page_uids.lock().await.new_token(),
+                sorted_uids(search_uids(&mut conn, "UID SEARCH ALL").await?),
+            ),
+        };
+
+        let bounds = page_bounds(&uids, before, count);
+        if let Some((_, _, Some(_))) = bounds {
+            self.page_uids.lock().await.keep(
+                snapshot,
+                PageSnapshot {
+                    mailbox: mailbox_hash,
+                    uidvalidity,
+                    uids,
+                },
+            );
+        }
+        let Some((first, last, next_before)) = bounds else {
+            return Ok(MessagePage {
+                messages: Vec::new(),
+                next: None,
+            });
+        };
+        let mut messages = fetch_uid_range(&mut conn, &mailbox, first, last).await?;
+        messages.sort_by_key(|m| std::cmp::Reverse(m.timestamp));
+
+        Ok(MessagePage {
+            messages,
+            next: next_before.map(|before_uid| FetchCursor {
+                uidvalidity,
+                before_uid,
+                snapshot,
+            }),
+        })
+    }
+
+    /// Bring a mailbox up to date relative to the sync state saved by the
+    /// previous sync, without refetching envelopes the cache already has.
+    ///
+    /// With no prior state, or when the server's UIDVALIDITY has changed, this
+    /// falls back to a full [`fetch_messages`](Self::fetch_messages) and
+    /// returns a reset delta. Otherwise it fetches envelopes only for UIDs
+    /// assigned since the saved UIDNEXT, fetches flags with `CHANGEDSINCE`
+    /// when both sides report a HIGHESTMODSEQ (CONDSTORE), and finds expunged
+    /// messages with `UID SEARCH ALL`. melib has no QRESYNC support, so
+    /// `VANISHED` responses are not used.
+    ///
+    /// Apply the result with
+    /// [`CacheHandle::apply_mailbox_delta`](crate::store::CacheHandle::apply_mailbox_delta).
+    pub async fn sync_mailbox(
+        self: &Arc<Self>,
+        mailbox_hash: MailboxHash,
+        prior: Option<MailboxSyncState>,
+    ) -> Result<MailboxDelta, String> {
+        let mailbox = self.raw_mailbox(mailbox_hash).await?;
+        if mailbox.no_select {
+            return Ok(MailboxDelta {
+                state: prior.unwrap_or_default(),
+                reset: false,
+                new_messages: Vec::new(),
+                flag_updates: Vec::new(),
+                present: None,
+            });
+        }
+
+        let mut conn = mailbox
+            .connection
+            .lock()
+            .await
+            .map_err(|e| self.backend_error("IMAP connection error", e))?;
+        let mut response = Vec::with_capacity(8 * 1024);
+        let select = conn
+            .examine_mailbox(mailbox_hash, &mut response, true)
+            .await
+            .map_err(|e| self.backend_error("Failed to examine folder", e))?;
+        let state = MailboxSyncState {
+            uidvalidity: select.uidvalidity as u64,
+            uidnext: select.uidnext as u64,
+            highestmodseq: select
+                .highestmodseq
+                .and_then(|modseq| modseq.ok())
+                .map(|modseq| modseq.0.get()),
+        };
+
+        let prior = match prior {
+            Some(prior) if prior.uidvalidity == state.uidvalidity && state.uidnext > 0 => prior,
+            _ => {
+                drop(conn);
+                let new_messages = self.fetch_messages(mailbox_hash).await?;
+                return Ok(MailboxDelta {
+                    state,
+                    reset: true,
+                    new_messages,
+                    flag_updates: Vec::new(),
+                    present: None,
+                });
+            }
+        };
+
+        // 1. Envelopes for UIDs assigned since the last sync.
+        let new_messages = if state.uidnext > prior.uidnext {
+            fetch_uid_range(&mut conn, &mailbox, prior.uidnext, state.uidnext - 1).await?
+        } else {
+            Vec::new()
+        };
+
+        // 2. Flags of messages we already had. Skipped entirely when
+        //    HIGHESTMODSEQ hasn't moved.
+        let mut flag_updates = Vec::new();
+        let modseq_unchanged = matches!(
+            (prior.highestmodseq, state.highestmodseq),
+            (Some(old), Some(new)) if old == new
+        );
+        if prior.uidnext > 1 && !modseq_unchanged {
+            let last_uid = prior.uidnext - 1;
+            // imap-codec has no CONDSTORE support, so these go out raw.
+            let command = match (prior.highestmodseq, state.highestmodseq) {
+                (Some(modseq), Some(_)) => {
+                    format!("UID FETCH 1:{last_uid} (FLAGS) (CHANGEDSINCE {modseq})")
+                }
+                _ => format!("UID FETCH 1:{last_uid} (FLAGS)"),
+            };
+            conn.send_command_raw(command.as_bytes())
+                .await
+                .map_err(|e| self.backend_error("Failed to request flags", e))?;
+            conn.read_response(
+                &mut response,
+                RequiredResponses::FETCH_FLAGS | RequiredResponses::FETCH_MODSEQ,
+            )
+            .await
+            .map_err(|e| self.backend_error("Failed to fetch flags", e))?;
+            let (_, fetched, _) =
+                fetch_responses(&response).map_err(|e| format!("Failed to parse flags: {}", e))?;
+            for FetchResponse { uid, flags, .. } in fetched {
+                if let (Some(uid), Some((flags, keywords))) = (uid, flags) {
+                    let envelope_hash = generate_envelope_hash(&mailbox.imap_path, &uid);
+                    flag_updates.push((
+                        envelope_hash.0,
+                        imap_flags_to_u8(flags, &keywords),
+                        user_keywords(&keywords),
+                    ));
+                }
+            }
+        }
+
+        // 3. Every UID still on the server, to find expunges. This also
+        //    teaches melib the UID behind each cached envelope hash so that
+        //    flag and move commands work without a full fetch.
+        let mut present = Vec::new();
+        if select.exists > 0 {
+            let uids = search_uids(&mut conn, "UID SEARCH ALL").await?;
+            let mut hash_index = mailbox.uid_store.hash_index.lock().unwrap();
+            let mut uid_index = mailbox.uid_store.uid_index.lock().unwrap();
+            for uid in uids {
+                let envelope_hash = generate_envelope_hash(&mailbox.imap_path, &uid);
+                hash_index.insert(envelope_hash, (uid, mailbox_hash));
+                uid_index.insert((mailbox_hash, uid), envelope_hash);
+                present.push(envelope_hash.0);
+            }
+        }
+
+        Ok(MailboxDelta {
+            state,
+            reset: false,
+            new_messages,
+            flag_updates,
+            present: Some(present),
+        })
+    }
+
     /// Set or unset flags on a single message.
     pub async fn set_flags(
         self: &Arc<Self>,
@@ -225,7 +729,7 @@ impl ImapSession {
 
         future
             .await
-            .map_err(|e| format!("Failed to set flags: {}", e))?;
+            .map_err(|e| self.backend_error("Failed to set flags", e))?;
         Ok(())
     }
 
@@ -250,16 +754,108 @@ impl ImapSession {
 
         future
             .await
-            .map_err(|e| format!("Failed to move message: {}", e))?;
+            .map_err(|e| self.backend_error("Failed to move message", e))?;
         Ok(())
     }
 
-    /// Fetch and render the body of a single message, extracting attachments.
-    /// Returns (markdown_body, plain_body, attachments).
-    pub async fn fetch_body(
+    /// Set or unset flags on many messages at once.
+    ///
+    /// Envelopes are grouped by mailbox and each group is sent as a single
+    /// IMAP command. Returns one result per input envelope, in input order;
+    /// every envelope in a group shares that group's outcome.
+    pub async fn set_flags_batch(
+        self: &Arc<Self>,
+        envelopes: &[(EnvelopeHash, MailboxHash)],
+        flags: Vec<FlagOp>,
+    ) -> Vec<(EnvelopeHash, Result<(), String>)> {
+        let mut outcomes = HashMap::new();
+        for (mailbox_hash, batch) in group_by_mailbox(envelopes) {
+            let future = {
+                let mut backend = self.backend.lock().await;
+                backend
+                    .set_flags(batch.clone(), mailbox_hash, flags.clone())
+                    .map_err(|e| format!("Failed to request set_flags: {}", e))
+            };
+            let result = match future {
+                Ok(future) => future
+                    .await
+                    .map_err(|e| self.backend_error("Failed to set flags", e)),
+                Err(e) => Err(e),
+            };
+            for hash in batch.iter() {
+                outcomes.insert(hash, result.clone());
+            }
+        }
+        collect_outcomes(envelopes, outcomes)
+    }
+
+    /// Move many messages to one destination mailbox.
+    ///
+    /// Envelopes are grouped by source mailbox and each group is moved with a
+    /// single IMAP command. Returns one result per input envelope, in input order.
+    pub async fn move_messages_batch(
+        self: &Arc<Self>,
+        envelopes: &[(EnvelopeHash, MailboxHash)],
+        destination_mailbox_hash: MailboxHash,
+    ) -> Vec<(EnvelopeHash, Result<(), String>)> {
+        let mut outcomes = HashMap::new();
+        for (source_mailbox_hash, batch) in group_by_mailbox(envelopes) {
+            let future = {
+                let mut backend = self.backend.lock().await;
+                backend
+                    .copy_messages(
+                        batch.clone(),
+                        source_mailbox_hash,
+                        destination_mailbox_hash,
+                        true, // move = true
+                    )
+                    .map_err(|e| format!("Failed to request move: {}", e))
+            };
+            let result = match future {
+                Ok(future) => future
+                    .await
+                    .map_err(|e| self.backend_error("Failed to move messages", e)),
+                Err(e) => Err(e),
+            };
+            for hash in batch.iter() {
+                outcomes.insert(hash, result.clone());
+            }
+        }
+        collect_outcomes(envelopes, outcomes)
+    }
+
+    /// Append a raw RFC 5322 message to a mailbox with the given initial
+    /// flags — e.g. a Sent copy with `Flag::SEEN`, or a draft with
+    /// `Flag::SEEN | Flag::DRAFT`.
+    pub async fn append_message(
+        self: &Arc<Self>,
+        mailbox_hash: MailboxHash,
+        bytes: Vec<u8>,
+        flags: Flag,
+    ) -> Result<(), String> {
+        self.ensure_known_mailbox(mailbox_hash).await?;
+        let future = {
+            let backend = self.backend.lock().await;
+            backend
+                .save(bytes, mailbox_hash, Some(flags))
+                .map_err(|e| format!("Failed to request append: {}", e))?
+        };
+
+        future
+            .await
+            .map_err(|e| self.backend_error("Failed to append message", e))?;
+        Ok(())
+    }
+
+    /// Fetch the complete RFC 5322 source of a message, exactly as the
+    /// server stores it. Cache it with [`CacheHandle::save_raw`] and write
+    /// it out with [`crate::mime::export_eml`].
+    ///
+    /// [`CacheHandle::save_raw`]: crate::store::CacheHandle::save_raw
+    pub async fn fetch_raw(
         self: &Arc<Self>,
         envelope_hash: EnvelopeHash,
-    ) -> Result<(String, String, Vec<AttachmentData>), String> {
+    ) -> Result<Vec<u8>, String> {
         let future = {
             let backend = self.backend.lock().await;
             backend
@@ -267,55 +863,929 @@ impl ImapSession {
                 .map_err(|e| format!("Failed to request message bytes: {}", e))?
         };
 
-        let bytes = future
+        future
             .await
-            .map_err(|e| format!("Failed to fetch message bytes: {}", e))?;
+            .map_err(|e| self.backend_error("Failed to fetch message bytes", e))
+    }
 
-        let mail = Mail::new(bytes, None).map_err(|e| format!("Failed to parse message: {}", e))?;
+    /// Fetch and render the text of a single message and list its
+    /// attachments, without downloading them. Returns (markdown_body,
+    /// plain_body, attachments); get an attachment's bytes with
+    /// [`fetch_attachment`](Self::fetch_attachment). Inline images show up
+    /// in the markdown as `attachment:N`, an index into that list; remote
+    /// images only as `remote` allows. Like opening it in any client, this
+    /// marks the message `\Seen`.
+    pub async fn fetch_body(
+        self: &Arc<Self>,
+        envelope_hash: EnvelopeHash,
+        remote: RemoteContent,
+    ) -> Result<(String, String, Vec<AttachmentInfo>), String> {
+        let attrs = self
+            .fetch_message_items(
+                envelope_hash,
+                "BODYSTRUCTURE",
+                RequiredResponses::FETCH_BODYSTRUCTURE,
+            )
+            .await?;
+        let structure = attribute(&attrs, "BODYSTRUCTURE")
+            .ok_or_else(|| "Server sent no BODYSTRUCTURE".to_string())?;
+        let (text_parts, attachments): (Vec<BodyPart>, Vec<BodyPart>) = body_parts(structure)
+            .into_iter()
+            .partition(BodyPart::is_body_text);
 
-        let body_attachment = mail.body();
-        let (text_plain, text_html, attachments) = extract_body(&body_attachment);
+        let mut text_plain: Option<String> = None;
+        let mut text_html: Option<String> = None;
+        if !text_parts.is_empty() {
+            let sections = text_parts
+                .iter()
+                .map(|p| format!("BODY.PEEK[{}]", p.part))
+                .collect::<Vec<_>>()
+                .join(" ");
+            let attrs = self
+                .fetch_message_items(envelope_hash, &sections, RequiredResponses::empty())
+                .await?;
+            for part in &text_parts {
+                let Some(Value::Str(body)) = attribute(&attrs, &format!("BODY[{}]", part.part))
+                else {
+                    continue;
+                };
+                let bytes = decode_transfer(body, &part.encoding);
+                let param = |name: &str| {
+                    part.params
+                        .iter()
+                        .find(|(n, _)| n == name)
+                        .map(|(_, value)| value.clone())
+                };
+                let text = crate::mime::decode_text(&bytes, param("charset").as_deref());
+                let text = match part.mime_type.as_str() {
+                    "text/plain" => unflow(text, param("format"), param("delsp")),
+                    _ => text,
+                };
+                if text.trim().is_empty() {
+                    continue;
+                }
+                let slot = if part.mime_type == "text/html" {
+                    &mut text_html
+                } else {
+                    &mut text_plain
+                };
+                *slot = Some(slot.take().unwrap_or_default() + &text);
+            }
+        }
+        // The parts were fetched with BODY.PEEK, so set \Seen ourselves. A
+        // folder we may not write to still shows the message.
+        let (_, mailbox_hash) = self.locate(envelope_hash).await?;
+        if let Err(e) = self
+            .set_flags(envelope_hash, mailbox_hash, vec![FlagOp::Set(Flag::SEEN)])
+            .await
+        {
+            log::warn!("{}", e);
+        }
 
+        let attachments: Vec<AttachmentInfo> = attachments.iter().map(BodyPart::info).collect();
+        let content_ids: Vec<Option<String>> =
+            attachments.iter().map(|a| a.content_id.clone()).collect();
         let plain_rendered = crate::mime::render_body(text_plain.as_deref(), text_html.as_deref());
-        let markdown_rendered =
-            crate::mime::render_body_markdown(text_plain.as_deref(), text_html.as_deref());
+        let markdown_rendered = crate::mime::render_body_markdown_inline(
+            text_plain.as_deref(),
+            text_html.as_deref(),
+            &content_ids,
+            remote,
+        );
 
         Ok((markdown_rendered, plain_rendered, attachments))
     }
 
-    /// Start watching for backend events (IMAP IDLE or poll fallback).
-    /// Returns a `'static` stream — safe to hold after releasing the lock.
-    pub async fn watch(
+    /// Download and decode one attachment listed by
+    /// [`fetch_body`](Self::fetch_body). Cache the bytes with
+    /// [`CacheHandle::save_attachment`](crate::store::CacheHandle::save_attachment).
+    pub async fn fetch_attachment(
         self: &Arc<Self>,
-    ) -> Result<
-        impl futures::Stream<Item = melib::error::Result<melib::backends::BackendEvent>>,
-        String,
-    > {
-        let stream = {
+        envelope_hash: EnvelopeHash,
+        attachment: &AttachmentInfo,
+    ) -> Result<AttachmentData, String> {
+        let attrs = self
+            .fetch_message_items(
+                envelope_hash,
+                &format!("BODY.PEEK[{}]", attachment.part),
+                RequiredResponses::empty(),
+            )
+            .await?;
+        let Some(Value::Str(body)) = attribute(&attrs, &format!("BODY[{}]", attachment.part))
+        else {
+            return Err(format!("Server sent no data for part {}", attachment.part));
+        };
+        Ok(AttachmentData {
+            filename: attachment.filename.clone(),
+            mime_type: attachment.mime_type.clone(),
+            data: decode_transfer(body, &attachment.encoding),
+            content_id: attachment.content_id.clone(),
+        })
+    }
+
+    /// The UID and folder of a fetched message.
+    async fn locate(
+        self: &Arc<Self>,
+        envelope_hash: EnvelopeHash,
+    ) -> Result<(UID, MailboxHash), String> {
+        let backend = self.backend.lock().await;
+        let hash_index = backend.uid_store.hash_index.lock().unwrap();
+        hash_index.get(&envelope_hash).copied().ok_or_else(|| {
+            "Message not found; it may have been deleted since the folder was fetched".to_string()
+        })
+    }
+
+    /// `UID FETCH` `items` for one message and return its FETCH attributes.
+    /// `required` keeps melib from swallowing responses it can parse.
+    async fn fetch_message_items(
+        self: &Arc<Self>,
+        envelope_hash: EnvelopeHash,
+        items: &str,
+        required: RequiredResponses,
+    ) -> Result<Vec<Value>, String> {
+        let (uid, mailbox_hash) = self.locate(envelope_hash).await?;
+        let mailbox = self.raw_mailbox(mailbox_hash).await?;
+        let mut conn = mailbox
+            .connection
+            .lock()
+            .await
+            .map_err(|e| self.backend_error("IMAP connection error", e))?;
+        let mut response = Vec::with_capacity(8 * 1024);
+        conn.examine_mailbox(mailbox_hash, &mut response, false)
+            .await
+            .map_err(|e| self.backend_error("Failed to examine folder", e))?;
+        conn.send_command_raw(format!("UID FETCH {} (UID {})", uid, items).as_bytes())
+            .await
+            .map_err(|e| self.backend_error("Failed to request message parts", e))?;
+        conn.read_response(&mut response, required)
+            .await
+            .map_err(|e| self.backend_error("Failed to fetch message parts", e))?;
+
+        let uid = uid.to_string();
+        fetch_attributes(&response)
+            .into_iter()
+            .find(|attrs| matches!(attribute(attrs, "UID"), Some(Value::Str(u)) if *u == uid.as_bytes()))
+            .ok_or_else(|| format!("Message with UID {} was not found", uid))
+    }
+
+    /// Start watching for server changes (IMAP IDLE or poll fallback) and
+    /// return a channel of [`MailEvent`]s. Calling this again replaces the
+    /// previous watch; its channel closes.
+    ///
+    /// The channel survives connection drops: it yields
+    /// [`MailEvent::ConnectionLost`], waits for the supervisor to get back
+    /// [`Online`](ConnectionState::Online), restarts the watch and yields
+    /// [`MailEvent::ConnectionRestored`]. Events that happened while offline
+    /// are not replayed; run [`sync_mailbox`](Self::sync_mailbox) after a
+    /// restore.
+    pub async fn watch(self: &Arc<Self>) -> Result<mpsc::UnboundedReceiver<MailEvent>, String> {
+        let (stream, uid_store) = {
             let backend = self.backend.lock().await;
-            backend
+            let stream = backend
                 .watch()
-                .map_err(|e| format!("Failed to start watch: {}", e))?
+                .map_err(|e| self.backend_error("Failed to start watch", e))?;
+            (stream, Arc::clone(&backend.uid_store))
         };
-        Ok(stream)
+
+        let (raw_tx, mut raw_rx) = mpsc::unbounded_channel();
+        *self.event_sink.lock().unwrap() = Some(raw_tx);
+
+        let (tx, rx) = mpsc::unbounded_channel();
+        let backend = Arc::clone(&self.backend);
+        let state = Arc::clone(&self.state);
+        let health = Arc::clone(&self.health);
+        let task = tokio::spawn(async move {
+            let mut stream = stream;
+            let mut failures = 0;
+            loop {
+                // Forward events until the watch fails.
+                let mut delivered = false;
+                let reason = 'watch: loop {
+                    let event = tokio::select! {
+                        item = stream.next() => match item {
+                            Some(Ok(event)) => event,
+                            Some(Err(e)) => break 'watch e.to_string(),
+                            None => break 'watch "Watch ended".to_string(),
+                        },
+                        Some(event) = raw_rx.recv() => event,
+                    };
+                    for mail_event in translate_backend_event(event, &uid_store).await {
+                        if let MailEvent::ConnectionLost { reason } = mail_event {
+                            break 'watch reason;
+                        }
+                        if tx.send(mail_event).is_err() {
+                            return;
+                        }
+                        delivered = true;
+                    }
+                };
+                if tx.send(MailEvent::ConnectionLost { reason }).is_err() {
+                    return;
+                }
+
+                // Hand the reconnect to the supervisor, then resume. Back off
+                // if the watch keeps dying without delivering anything.
+                failures = if delivered { 1 } else { failures + 1 };
+                loop {
+                    state.send_replace(ConnectionState::Connecting);
+                    health.notify_one();
+                    let mut state_rx = state.subscribe();
+                    if state_rx
+                        .wait_for(|s| *s == ConnectionState::Online)
+                        .await
+                        .is_err()
+                    {
+                        return;
+                    }
+                    if failures > 1 {
+                        tokio::time::sleep(backoff_delay(failures - 1)).await;
+                    }
+                    let restarted = backend.lock().await.watch();
+                    match restarted {
+                        Ok(restarted) => {
+                            stream = restarted;
+                            break;
+                        }
+                        Err(e) => {
+                            log::warn!("Failed to restart watch: {}", e);
+                            failures += 1;
+                        }
+                    }
+                }
+                if tx.send(MailEvent::ConnectionRestored).is_err() {
+                    return;
+                }
+            }
+        });
+
+        if let Some(previous) = self.watch_task.lock().await.replace(task) {
+            previous.abort();
+        }
+        Ok(rx)
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::map_mailbox_counts;
+/// Check (and if needed re-establish) the main connection, handing it a
+/// fresh access token for OAuth2 accounts. A certificate the relay refused
+/// is reported instead of melib's dropped-connection error.
+async fn probe(
+    backend: &Mutex<Box<ImapType>>,
+    relay: Option<&Relay>,
+    tokens: Option<&TokenSource>,
+) -> melib::error::Result<()> {
+    if let Some(relay) = relay {
+        relay.take_error();
+    }
+    if let Some(tokens) = tokens {
+        let password = tokens.xoauth2_base64().await.map_err(|e| match e {
+            TokenError::Rejected(message) => {
+                melib::error::Error::new(message).set_kind(melib::error::ErrorKind::Authentication)
+            }
+            TokenError::Failed(message) => melib::error::Error::new(message),
+        })?;
+        let connection = {
+            let mut backend = backend.lock().await;
+            if backend.server_conf.server_password == password {
+                None
+            } else {
+                backend.server_conf.server_password = password.clone();
+                Some(Arc::clone(&backend.connection))
+            }
+        };
+        if let Some(connection) = connection {
+            connection.inner.lock().await.server_conf.server_password = password;
+        }
+    }
+    let future = backend.lock().await.is_online()?;
+    future
+        .await
+        .map_err(|e| match relay.and_then(Relay::take_error) {
+            Some(refused) => melib::error::Error::new(refused),
+            None => e,
+        })
+}
 
-    #[test]
-    fn mailbox_count_tuple_maps_to_unread_then_total() {
-        let (unread, total) = map_mailbox_counts((3, 10));
-        assert_eq!(unread, 3);
-        assert_eq!(total, 10);
+/// Keep `state` current: probe on a timer or when kicked, and reconnect
+/// with exponential backoff after failures. Authentication failures park
+/// until [`ImapSession::reconnect`] instead of retrying.
+async fn supervise(
+    backend: Arc<Mutex<Box<ImapType>>>,
+    state: Arc<watch::Sender<ConnectionState>>,
+    health: Arc<Notify>,
+    relay: Option<Arc<Relay>>,
+    tokens: Option<Arc<TokenSource>>,
+) {
+    let mut attempt = 0;
+    loop {
+        let wait = match &*state.borrow() {
+            ConnectionState::Online => Some(HEALTH_CHECK_INTERVAL),
+            ConnectionState::Connecting => Some(Duration::ZERO),
+            ConnectionState::BackingOff { retry_in, .. } => Some(*retry_in),
+            ConnectionState::AuthFailed { .. } => None,
+        };
+        match wait {
+            Some(wait) => {
+                tokio::select! {
+                    _ = health.notified() => {}
+                    _ = tokio::time::sleep(wait) => {}
+                }
+            }
+            None => health.notified().await,
+        }
+
+        if *state.borrow() != ConnectionState::Online {
+            state.send_replace(ConnectionState::Connecting);
+        }
+        match probe(&backend, relay.as_deref(), tokens.as_deref()).await {
+            Ok(()) => {
+                attempt = 0;
+                state.send_if_modified(|s| {
+                    let changed = *s != ConnectionState::Online;
+                    *s = ConnectionState::Online;
+                    changed
+                });
+            }
+            Err(e) if e.kind.is_authentication() => {
+                attempt = 0;
+                log::warn!("IMAP authentication failed: {}", e);
+                state.send_replace(ConnectionState::AuthFailed {
+                    reason: e.to_string(),
+                });
+            }
+            Err(e) => {
+                attempt += 1;
+                let retry_in = backoff_delay(attempt);
+                log::info!("IMAP connection down (attempt {}): {}", attempt, e);
+                state.send_replace(ConnectionState::BackingOff { attempt, retry_in });
+            }
+        }
+    }
+}
+
+/// Translate a melib event into ours, then append fresh unread/total counts
+/// for every mailbox whose messages changed.
+async fn translate_backend_event(event: BackendEvent, uid_store: &UIDStore) -> Vec<MailEvent> {
+    let refreshes = match event {
+        BackendEvent::Refresh(refresh) => vec![refresh],
+        BackendEvent::RefreshBatch(refreshes) => refreshes,
+        BackendEvent::Notice {
+            description,
+            content,
+            ..
+        } => {
+            log::debug!("IMAP notice: {} {:?}", description, content);
+            return Vec::new();
+        }
+        BackendEvent::AccountStateChange { message } => {
+            log::debug!("IMAP account state: {}", message);
+            return Vec::new();
+        }
+    };
+
+    let mut events = Vec::new();
+    let mut touched: Vec<MailboxHash> = Vec::new();
+    for refresh in refreshes {
+        let mailbox_hash = refresh.mailbox_hash;
+        let translated =
+            translate_refresh(refresh, &uid_store.collection.tag_index.read().unwrap());
+        let changes_messages = translated.iter().any(|e| {
+            matches!(
+                e,
+                MailEvent::NewMessage(_)
+                    | MailEvent::FlagsChanged { .. }
+                    | MailEvent::MessageRemoved { .. }
+            )
+        });
+        if changes_messages && !touched.contains(&mailbox_hash) {
+            touched.push(mailbox_hash);
+        }
+        events.extend(translated);
+    }
+
+    let mailboxes = uid_store.mailboxes.lock().await;
+    for mailbox_hash in touched {
+        if let Some(Ok(counts)) = mailboxes.get(&mailbox_hash).map(|m| m.count()) {
+            let (unread_count, total_count) = map_mailbox_counts(counts);
+            events.push(MailEvent::MailboxCountsChanged {
+                mailbox_hash: mailbox_hash.0,
+                unread_count,
+                total_count,
+            });
+        }
+    }
+    events
+}
+
+/// Map one melib refresh event onto zero or more [`MailEvent`]s.
+/// `tag_index` resolves the keyword hashes on created envelopes.
+fn translate_refresh(
+    refresh: RefreshEvent,
+    tag_index: &BTreeMap<TagHash, String>,
+) -> Vec<MailEvent> {
+    let mailbox_hash = refresh.mailbox_hash;
+    let flags_changed = |envelope_hash: EnvelopeHash, flags: Flag, keywords: &[String]| {
+        let mut summary = MessageSummary::default();
+        set_summary_flags(&mut summary, imap_flags_to_u8(flags, keywords));
+        MailEvent::FlagsChanged {
+            mailbox_hash: mailbox_hash.0,
+            envelope_hash: envelope_hash.0,
+            is_read: summary.is_read,
+            is_starred: summary.is_starred,
+            is_answered: summary.is_answered,
+            is_draft: summary.is_draft,
+            is_deleted: summary.is_deleted,
+            is_forwarded: summary.is_forwarded,
+            keywords: user_keywords(keywords),
+        }
+    };
+    let new_message = |envelope: &Envelope| {
+        let keywords = envelope_keywords(envelope, tag_index);
+        MailEvent::NewMessage(summary_from_envelope(envelope, mailbox_hash, &keywords))
+    };
+    let removed = |envelope_hash: EnvelopeHash| MailEvent::MessageRemoved {
+        mailbox_hash: mailbox_hash.0,
+        envelope_hash: envelope_hash.0,
+    };
+    match refresh.kind {
+        RefreshEventKind::Create(envelope) => vec![new_message(&envelope)],
+        RefreshEventKind::Update(old_hash, envelope) if old_hash == envelope.hash() => {
+            let keywords = envelope_keywords(&envelope, tag_index);
+            vec![flags_changed(old_hash, envelope.flags(), &keywords)]
+        }
+        RefreshEventKind::Update(old_hash, envelope) => {
+            vec![removed(old_hash), new_message(&envelope)]
+        }
+        RefreshEventKind::NewFlags(envelope_hash, (flags, keywords)) => {
+            vec![flags_changed(envelope_hash, flags, &keywords)]
+        }
+        RefreshEventKind::Remove(envelope_hash) => vec![removed(envelope_hash)],
+        RefreshEventKind::Rename(old_hash, _new_hash) => vec![
+            removed(old_hash),
+            MailEvent::MailboxNeedsResync {
+                mailbox_hash: mailbox_hash.0,
+            },
+        ],
+        RefreshEventKind::Rescan => vec![MailEvent::MailboxNeedsResync {
+            mailbox_hash: mailbox_hash.0,
+        }],
+        RefreshEventKind::Failure(e) => vec![MailEvent::ConnectionLost {
+            reason: e.to_string(),
+        }],
+        RefreshEventKind::MailboxCreate(_)
+        | RefreshEventKind::MailboxDelete(_)
+        | RefreshEventKind::MailboxRename { .. }
+        | RefreshEventKind::MailboxSubscribe(_)
+        | RefreshEventKind::MailboxUnsubscribe(_) => vec![MailEvent::FoldersChanged],
+    }
+}
+
+/// A mailbox plus the melib internals needed to talk to it directly.
+struct RawMailbox {
+    hash: MailboxHash,
+    imap_path: String,
+    no_select: bool,
+    connection: Arc<ConnectionMutex>,
+    uid_store: Arc<UIDStore>,
+}
+
+/// The UIDs a paged fetch found on its first page.
+struct PageSnapshot {
+    mailbox: MailboxHash,
+    uidvalidity: u64,
+    uids: Vec<UID>,
+}
+
+/// UID lists of paged fetches in progress, by [`FetchCursor::snapshot`].
+#[derive(Default)]
+struct PageSnapshots {
+    kept: BTreeMap<u64, PageSnapshot>,
+    last_token: u64,
+}
+
+impl PageSnapshots {
+    /// A token no other pager in this session has.
+    fn new_token(&mut self) -> u64 {
+        self.last_token += 1;
+        self.last_token
+    }
+
+    /// Take the UIDs kept under `token`, if they're for this mailbox and
+    /// UIDVALIDITY.
+    fn take(&mut self, token: u64, mailbox: MailboxHash, uidvalidity: u64) -> Option<Vec<UID>> {
+        self.kept
+            .remove(&token)
+            .filter(|kept| kept.mailbox == mailbox && kept.uidvalidity == uidvalidity)
+            .map(|kept| kept.uids)
+    }
+
+    /// Keep `snapshot` for the next page, dropping the oldest pagers past
+    /// [`MAX_PAGE_SNAPSHOTS`].
+    fn keep(&mut self, token: u64, snapshot: PageSnapshot) {
+        while self.kept.len() >= MAX_PAGE_SNAPSHOTS {
+            self.kept.pop_first();
+        }
+        self.kept.insert(token, snapshot);
+    }
+}
+
+/// `UID FETCH first:last` with the usual envelope attributes. Also records
+/// each UID in melib's indexes so flag and move commands can find it.
+async fn fetch_uid_range(
+    conn: &mut ImapConnection,
+    mailbox: &RawMailbox,
+    first: u64,
+    last: u64,
+) -> Result<Vec<MessageSummary>, String> {
+    let sequence_set = SequenceSet::try_from(first as usize..=last as usize)
+        .map_err(|e| format!("Invalid UID range: {:?}", e))?;
+    let (required_responses, macro_or_item_names) = common_attributes();
+    conn.send_command(CommandBody::Fetch {
+        sequence_set,
+        macro_or_item_names,
+        uid: true,
+    })
+    .await
+    .map_err(|e| format!("Failed to request envelopes: {}", e))?;
+    let mut response = Vec::with_capacity(8 * 1024);
+    conn.read_response(&mut response, required_responses)
+        .await
+        .map_err(|e| format!("Failed to fetch envelopes: {}", e))?;
+    let (_, fetched, _) =
+        fetch_responses(&response).map_err(|e| format!("Failed to parse envelopes: {}", e))?;
+
+    let mut messages = Vec::with_capacity(fetched.len());
+    for FetchResponse {
+        uid,
+        envelope,
+        flags,
+        references,
+        ..
+    } in fetched
+    {
+        let (Some(uid), Some(mut envelope)) = (uid, envelope) else {
+            continue;
+        };
+        let envelope_hash = generate_envelope_hash(&mailbox.imap_path, &uid);
+        envelope.set_hash(envelope_hash);
+        if let Some(references) = references {
+            envelope.set_references(references);
+        }
+        let keywords = match flags {
+            Some((flags, keywords)) => {
+                envelope.set_flags(flags);
+                keywords
+            }
+            None => Vec::new(),
+        };
+        mailbox
+            .uid_store
+            .hash_index
+            .lock()
+            .unwrap()
+            .insert(envelope_hash, (uid, mailbox.hash));
+        mailbox
+            .uid_store
+            .uid_index
+            .lock()
+            .unwrap()
+            .insert((mailbox.hash, uid), envelope_hash);
+        messages.push((
+            uid,
+            summary_from_envelope(&envelope, mailbox.hash, &keywords),
+        ));
+    }
+
+    let mut reply_headers = fetch_reply_headers(conn, &format!("{}:{}", first, last))
+        .await
+        .unwrap_or_else(|e| {
+            log::warn!("{}", e);
+            HashMap::new()
+        });
+    Ok(messages
+        .into_iter()
+        .map(|(uid, mut summary)| {
+            if let Some(headers) = reply_headers.remove(&uid) {
+                set_reply_headers(&mut summary, headers);
+            }
+            summary
+        })
+        .collect())
+}
+
+/// Run a `UID SEARCH` command and return the matching UIDs.
+async fn search_uids(conn: &mut ImapConnection, command: &str) -> Result<Vec<UID>, String> {
+    conn.send_command_raw(command.as_bytes())
+        .await
+        .map_err(|e| format!("Failed to request UID list: {}", e))?;
+    let mut response = Vec::with_capacity(8 * 1024);
+    conn.read_response(&mut response, RequiredResponses::SEARCH)
+        .await
+        .map_err(|e| format!("Failed to fetch UID list: {}", e))?;
+    let (_, uids) =
+        search_results(&response).map_err(|e| format!("Failed to parse UID list: {}", e))?;
+    Ok(uids)
+}
+
+/// Sort and dedup a `UID SEARCH` result for [`page_bounds`].
+fn sorted_uids(mut uids: Vec<UID>) -> Vec<UID> {
+    uids.sort_unstable();
+    uids.dedup();
+    uids
+}
+
+/// Pick the newest `count` of the sorted `uids`, below `before` when given.
+/// Returns the UID range to fetch and, if older messages remain, the UID to
+/// resume below.
+fn page_bounds(uids: &[UID], before: Option<u64>, count: usize) -> Option<(u64, u64, Option<u64>)> {
+    let end = before.map_or(uids.len(), |before| {
+        uids.partition_point(|&uid| (uid as u64) < before)
+    });
+    let uids = &uids[..end];
+    let last = *uids.last()?;
+    let start = uids.len().saturating_sub(count);
+    let first = uids[start];
+    let next_before = (start > 0).then_some(first as u64);
+    Some((first as u64, last as u64, next_before))
+}
+
+/// Names of an envelope's keywords, as registered in melib's tag index.
+fn envelope_keywords(envelope: &Envelope, tag_index: &BTreeMap<TagHash, String>) -> Vec<String> {
+    envelope
+        .tags()
+        .iter()
+        .filter_map(|hash| tag_index.get(hash).cloned())
+        .collect()
+}
+
+/// Build a `MessageSummary` from a fetched envelope and its IMAP keywords.
+fn summary_from_envelope(
+    envelope: &Envelope,
+    mailbox_hash: MailboxHash,
+    keywords: &[String],
+) -> MessageSummary {
+    let from_str = envelope
+        .from()
+        .iter()
+        .map(|a| a.to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    let to_str = envelope
+        .to()
+        .iter()
+        .map(|a| a.to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    // Reply-To and Sender are filled in by `set_reply_headers` when fetched.
+    let addresses = MessageAddresses {
+        from: email_addresses(envelope.from()),
+        to: email_addresses(envelope.to()),
+        cc: email_addresses(envelope.cc()),
+        bcc: email_addresses(envelope.bcc()),
+        ..Default::default()
+    };
+
+    let msg_id = envelope.message_id().to_string();
+    let refs = envelope.references();
+    let thread_id = Some(compute_thread_id(&msg_id, refs));
+    let thread_depth = refs.len() as u32;
+    let in_reply_to = envelope
+        .in_reply_to()
+        .and_then(|r| r.refs().last().map(|id| id.to_string()));
+
+    let reply_to = envelope
+        .other_headers()
+        .get("Reply-To")
+        .map(|s| s.to_string());
+
+    let mut summary = MessageSummary {
+        uid: envelope.hash().0,
+        subject: envelope.subject().to_string(),
+        from: from_str,
+        to: to_str,
+        addresses,
+        date: envelope.date_as_str().to_string(),
+        keywords: user_keywords(keywords),
+        has_attachments: envelope.has_attachments,
+        thread_id,
+        envelope_hash: envelope.hash().0,
+        timestamp: envelope.timestamp as i64,
+        mailbox_hash: mailbox_hash.0,
+        message_id: msg_id,
+        in_reply_to,
+        references: refs.iter().map(ToString::to_string).collect(),
+        reply_to,
+        thread_depth,
+        ..Default::default()
+    };
+    set_summary_flags(&mut summary, imap_flags_to_u8(envelope.flags(), keywords));
+    summary
+}
+
+/// Flatten melib addresses (expanding groups) into [`EmailAddress`]es.
+fn email_addresses(list: &[Address]) -> Vec<EmailAddress> {
+    list.iter()
+        .flat_map(|address| match address {
+            Address::Group(group) => email_addresses(&group.mailbox_list),
+            Address::Mailbox(_) => vec![EmailAddress {
+                name: address.get_display_name(),
+                email: address.get_email(),
+            }],
+        })
+        .collect()
+}
+
+/// Copy fetched Reply-To and Sender onto a summary. The flat `reply_to`
+/// string is only replaced when the header was present.
+fn set_reply_headers(summary: &mut MessageSummary, headers: MessageAddresses) {
+    if !headers.reply_to.is_empty() {
+        summary.reply_to = Some(
+            headers
+                .reply_to
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", "),
+        );
+    }
+    summary.addresses.reply_to = headers.reply_to;
+    summary.addresses.sender = headers.sender;
+}
+
+/// Parse Reply-To and Sender out of a raw header block.
+fn reply_headers(raw: &[u8]) -> MessageAddresses {
+    let mut addresses = MessageAddresses::default();
+    let Ok((_, headers)) = melib::email::parser::headers::headers(raw) else {
+        return addresses;
+    };
+    for (name, value) in headers {
+        let target = if name == HeaderName::REPLY_TO {
+            &mut addresses.reply_to
+        } else if name == HeaderName::SENDER {
+            &mut addresses.sender
+        } else {
+            continue;
+        };
+        if let Ok((_, list)) = melib::email::parser::address::rfc2822address_list(value) {
+            target.extend(email_addresses(&list));
+        }
+    }
+    addresses
+}
+
+/// Fetch Reply-To and Sender for `uid_set` in the selected mailbox. melib's
+/// ENVELOPE parser drops both, so they come from a header-fields fetch.
+async fn fetch_reply_headers(
+    conn: &mut ImapConnection,
+    uid_set: &str,
+) -> Result<HashMap<UID, MessageAddresses>, String> {
+    let command = format!(
+        "UID FETCH {} (UID BODY.PEEK[HEADER.FIELDS (REPLY-TO SENDER)])",
+        uid_set
+    );
+    conn.send_command_raw(command.as_bytes())
+        .await
+        .map_err(|e| format!("Failed to request reply headers: {}", e))?;
+    let mut response = Vec::with_capacity(8 * 1024);
+    conn.read_response(&mut response, RequiredResponses::empty())
+        .await
+        .map_err(|e| format!("Failed to fetch reply headers: {}", e))?;
+    Ok(reply_header_responses(&response))
+}
+
+/// Reply-To and Sender by UID from a [`fetch_reply_headers`] response.
+/// Items may come in any order, and the header section as the server
+/// spells it back; only `UID` and a `BODY[...]` item are looked at.
+fn reply_header_responses(response: &[u8]) -> HashMap<UID, MessageAddresses> {
+    fetch_attributes(response)
+        .iter()
+        .filter_map(|items| {
+            let uid = match attribute(items, "UID")? {
+                Value::Str(uid) => std::str::from_utf8(uid).ok()?.parse().ok()?,
+                _ => return None,
+            };
+            let headers = items.chunks(2).find_map(|pair| match pair {
+                [Value::Str(key), value] if key.to_ascii_uppercase().starts_with(b"BODY[") => {
+                    Some(value)
+                }
+                _ => None,
+            });
+            let addresses = match headers {
+                Some(Value::Str(raw)) => reply_headers(raw),
+                _ => MessageAddresses::default(),
+            };
+            Some((uid, addresses))
+        })
+        .collect()
+}
+
+/// Split a `{n}\r\n` literal off the front of `input`.
+fn split_literal(input: &[u8]) -> Option<(&[u8], &[u8])> {
+    let rest = input.strip_prefix(b"{")?;
+    let close = rest.iter().position(|&b| b == b'}')?;
+    let len: usize = std::str::from_utf8(&rest[..close]).ok()?.parse().ok()?;
+    let rest = rest[close + 1..].strip_prefix(b"\r\n")?;
+    (rest.len() >= len).then(|| rest.split_at(len))
+}
+
+/// Build a `Folder` from a melib mailbox.
+fn folder_from_mailbox(hash: MailboxHash, mailbox: &Mailbox) -> Result<Folder, String> {
+    let counts = mailbox
+        .count()
+        .map_err(|e| format!("Failed to get mailbox count: {}", e))?;
+    let (unseen, total) = map_mailbox_counts(counts);
+    Ok(Folder {
+        name: mailbox.name().to_string(),
+        path: mailbox.path().to_string(),
+        unread_count: unseen,
+        total_count: total,
+        mailbox_hash: hash.0,
+        is_subscribed: mailbox.is_subscribed(),
+        role: role_from_special_usage(mailbox.special_usage()),
+    })
+}
+
+/// The folder for `hash`, with name-based roles worked out across every
+/// mailbox so one that's already taken (by SPECIAL-USE or another folder's
+/// name) isn't handed out twice.
+fn folder_among(
+    hash: MailboxHash,
+    mailboxes: &HashMap<MailboxHash, Mailbox>,
+) -> Result<Option<Folder>, String> {
+    let folders = mailboxes
+        .iter()
+        .map(|(hash, mailbox)| folder_from_mailbox(*hash, mailbox))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(pick_folder(folders, hash))
+}
+
+fn pick_folder(mut folders: Vec<Folder>, hash: MailboxHash) -> Option<Folder> {
+    apply_role_heuristics(&mut folders);
+    folders.into_iter().find(|f| f.mailbox_hash == hash.0)
+}
+
+/// Map melib's RFC 6154 SPECIAL-USE parse onto our role enum.
+fn role_from_special_usage(usage: SpecialUsageMailbox) -> Option<FolderRole> {
+    match usage {
+        SpecialUsageMailbox::Normal => None,
+        SpecialUsageMailbox::Inbox => Some(FolderRole::Inbox),
+        SpecialUsageMailbox::Archive => Some(FolderRole::Archive),
+        SpecialUsageMailbox::Drafts => Some(FolderRole::Drafts),
+        SpecialUsageMailbox::Flagged => Some(FolderRole::Flagged),
+        SpecialUsageMailbox::Junk => Some(FolderRole::Junk),
+        SpecialUsageMailbox::Sent => Some(FolderRole::Sent),
+        SpecialUsageMailbox::Trash => Some(FolderRole::Trash),
+    }
+}
+
+/// Group envelopes by mailbox, preserving first-seen mailbox order.
+fn group_by_mailbox(
+    envelopes: &[(EnvelopeHash, MailboxHash)],
+) -> Vec<(MailboxHash, EnvelopeHashBatch)> {
+    let mut groups: Vec<(MailboxHash, Vec<EnvelopeHash>)> = Vec::new();
+    for &(envelope_hash, mailbox_hash) in envelopes {
+        match groups.iter_mut().find(|(m, _)| *m == mailbox_hash) {
+            Some((_, hashes)) => {
+                if !hashes.contains(&envelope_hash) {
+                    hashes.push(envelope_hash);
+                }
+            }
+            None => groups.push((mailbox_hash, vec![envelope_hash])),
+        }
     }
+    groups
+        .into_iter()
+        .filter_map(|(mailbox_hash, hashes)| {
+            EnvelopeHashBatch::try_from(hashes.as_slice())
+                .ok()
+                .map(|batch| (mailbox_hash, batch))
+        })
+        .collect()
 }
 
-/// Compute a deterministic thread ID from the root message-ID in the References chain.
+/// Map per-envelope outcomes back onto the caller's input order. An
+/// envelope listed twice was sent once and gets that outcome both times.
+fn collect_outcomes(
+    envelopes: &[(EnvelopeHash, MailboxHash)],
+    outcomes: HashMap<EnvelopeHash, Result<(), String>>,
+) -> Vec<(EnvelopeHash, Result<(), String>)> {
+    envelopes
+        .iter()
+        .map(|(hash, _)| {
+            let result = outcomes
+                .get(hash)
+                .cloned()
+                .unwrap_or_else(|| Err("Envelope was not sent".into()));
+            (*hash, result)
+        })
+        .collect()
+}
+
+/// Compute a provisional thread ID from the root message-ID in the References chain.
 /// If references exist, the root is references[0] (the original message).
 /// Otherwise, this message IS the root and we hash its own message-ID.
+/// [`crate::threading`] replaces it once the message is cached.
 fn compute_thread_id(message_id: &str, references: &[MessageID]) -> u64 {
     use std::hash::{Hash, Hasher};
     let mut hasher = std::collections::hash_map::DefaultHasher::new();
@@ -327,6 +1797,36 @@ fn compute_thread_id(message_id: &str, references: &[MessageID]) -> u64 {
     hasher.finish()
 }
 
+/// Render raw message source, for messages already on hand (e.g. from
+/// [`CacheHandle::load_raw`]). Attachments are decoded in full.
+/// Returns (markdown_body, plain_body, attachments); inline images are
+/// referenced from the markdown as `attachment:N`, an index into the list,
+/// and remote images kept only as `remote` allows.
+///
+/// [`CacheHandle::load_raw`]: crate::store::CacheHandle::load_raw
+pub fn render_message(
+    bytes: &[u8],
+    remote: RemoteContent,
+) -> Result<(String, String, Vec<AttachmentData>), String> {
+    let mail =
+        Mail::new(bytes.to_vec(), None).map_err(|e| format!("Failed to parse message: {}", e))?;
+
+    let body_attachment = mail.body();
+    let (text_plain, text_html, attachments) = extract_body(&body_attachment);
+
+    let content_ids: Vec<Option<String>> =
+        attachments.iter().map(|a| a.content_id.clone()).collect();
+    let plain_rendered = crate::mime::render_body(text_plain.as_deref(), text_html.as_deref());
+    let markdown_rendered = crate::mime::render_body_markdown_inline(
+        text_plain.as_deref(),
+        text_html.as_deref(),
+        &content_ids,
+        remote,
+    );
+
+    Ok((markdown_rendered, plain_rendered, attachments))
+}
+
 /// Walk the MIME tree and extract text/plain, text/html, and attachments.
 fn extract_body(
     att: &melib::email::attachments::Attachment,
@@ -348,8 +1848,7 @@ fn extract_parts(
         ContentType::Text {
             kind: Text::Plain, ..
         } if !att.content_disposition.kind.is_attachment() => {
-            let bytes = att.decode(Default::default());
-            let text = String::from_utf8_lossy(&bytes);
+            let text = part_text(att);
             if !text.trim().is_empty() {
                 let combined = plain.take().unwrap_or_default() + &text;
                 *plain = Some(combined);
@@ -358,8 +1857,7 @@ fn extract_parts(
         ContentType::Text {
             kind: Text::Html, ..
         } if !att.content_disposition.kind.is_attachment() => {
-            let bytes = att.decode(Default::default());
-            let text = String::from_utf8_lossy(&bytes);
+            let text = part_text(att);
             if !text.trim().is_empty() {
                 let combined = html.take().unwrap_or_default() + &text;
                 *html = Some(combined);
@@ -381,7 +1879,331 @@ fn extract_parts(
                 filename,
                 mime_type: att.content_type.to_string(),
                 data: att.decode(Default::default()),
+                content_id: crate::mime::source_headers(att.raw())
+                    .into_iter()
+                    .find(|(name, _)| name.eq_ignore_ascii_case("Content-ID"))
+                    .map(|(_, id)| id.trim_start_matches('<').trim_end_matches('>').to_string())
+                    .filter(|id| !id.is_empty()),
             });
         }
     }
 }
+
+/// A text part as a string, decoded by its own charset parameter rather
+/// than melib's, which knows fewer charsets and can't fall back to detection.
+fn part_text(att: &melib::email::attachments::Attachment) -> String {
+    let ContentType::Text {
+        kind, parameters, ..
+    } = &att.content_type
+    else {
+        return String::new();
+    };
+    let param = |name: &str| {
+        parameters
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name.as_bytes()))
+            .map(|(_, value)| String::from_utf8_lossy(value).into_owned())
+    };
+    let encoding = match &att.content_transfer_encoding {
+        ContentTransferEncoding::Other { .. } => String::new(),
+        encoding => encoding.to_string(),
+    };
+    let text = crate::mime::decode_text(
+        &decode_transfer(att.body(), &encoding),
+        param("charset").as_deref(),
+    );
+    match kind {
+        Text::Plain => unflow(text, param("format"), param("delsp")),
+        _ => text,
+    }
+}
+
+/// Join the soft line breaks of a `format=flowed` text/plain part.
+fn unflow(text: String, format: Option<String>, delsp: Option<String>) -> String {
+    if format.is_some_and(|f| f.trim_matches('"').eq_ignore_ascii_case("flowed")) {
+        let delsp = delsp.is_some_and(|d| d.trim_matches('"').eq_ignore_ascii_case("yes"));
+        crate::mime::decode_flowed(&text, delsp)
+    } else {
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, HashMap};
+    use std::time::Duration;
+
+    use melib::backends::{RefreshEvent, RefreshEventKind};
+    use melib::email::Flag;
+    use melib::{AccountHash, EnvelopeHash, MailboxHash};
+
+    use super::{
+        backoff_delay, collect_outcomes, extract_body, group_by_mailbox, map_mailbox_counts,
+        page_bounds, pick_folder, reply_header_responses, sorted_uids, translate_refresh,
+        PageSnapshot, PageSnapshots, MAX_BACKOFF, MAX_PAGE_SNAPSHOTS, UID,
+    };
+    use crate::models::{EmailAddress, Folder, FolderRole, MailEvent, MessageAddresses};
+
+    #[test]
+    fn mailbox_count_tuple_maps_to_unread_then_total() {
+        let (unread, total) = map_mailbox_counts((3, 10));
+        assert_eq!(unread, 3);
+        assert_eq!(total, 10);
+    }
+
+    #[test]
+    fn new_folder_gets_no_role_another_folder_already_holds() {
+        let folder = |hash: u64, path: &str, role: Option<FolderRole>| Folder {
+            name: path.to_string(),
+            path: path.to_string(),
+            unread_count: 0,
+            total_count: 0,
+            mailbox_hash: hash,
+            is_subscribed: true,
+            role,
+        };
+        // "Sent Messages" is \Sent via SPECIAL-USE; a new "Sent" is just a folder.
+        let folders = vec![
+            folder(1, "INBOX", Some(FolderRole::Inbox)),
+            folder(2, "Sent Messages", Some(FolderRole::Sent)),
+            folder(3, "Sent", None),
+            folder(4, "Trash", None),
+        ];
+        let created = pick_folder(folders.clone(), MailboxHash(3)).expect("created");
+        assert_eq!(created.role, None);
+        let trash = pick_folder(folders, MailboxHash(4)).expect("trash");
+        assert_eq!(trash.role, Some(FolderRole::Trash));
+    }
+
+    #[test]
+    fn batch_groups_envelopes_per_mailbox_in_first_seen_order() {
+        let input = [
+            (EnvelopeHash(1), MailboxHash(10)),
+            (EnvelopeHash(2), MailboxHash(20)),
+            (EnvelopeHash(3), MailboxHash(10)),
+            (EnvelopeHash(3), MailboxHash(10)),
+        ];
+        let groups = group_by_mailbox(&input);
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].0, MailboxHash(10));
+        assert_eq!(
+            groups[0].1.iter().collect::<Vec<_>>(),
+            vec![EnvelopeHash(1), EnvelopeHash(3)]
+        );
+        assert_eq!(groups[1].0, MailboxHash(20));
+        assert_eq!(groups[1].1.len(), 1);
+    }
+
+    #[test]
+    fn pagers_on_one_folder_keep_their_own_uid_lists() {
+        let mut snapshots = PageSnapshots::default();
+        let snapshot = |uids: Vec<UID>| PageSnapshot {
+            mailbox: MailboxHash(7),
+            uidvalidity: 1,
+            uids,
+        };
+        let list = snapshots.new_token();
+        let search = snapshots.new_token();
+        assert_ne!(list, search);
+        snapshots.keep(list, snapshot(vec![1, 2, 3]));
+        snapshots.keep(search, snapshot(vec![5, 6]));
+
+        assert_eq!(snapshots.take(list, MailboxHash(7), 1), Some(vec![1, 2, 3]));
+        assert_eq!(snapshots.take(list, MailboxHash(7), 1), None);
+        assert_eq!(snapshots.take(search, MailboxHash(7), 2), None);
+
+        for _ in 0..MAX_PAGE_SNAPSHOTS + 1 {
+            let token = snapshots.new_token();
+            snapshots.keep(token, snapshot(Vec::new()));
+        }
+        assert_eq!(snapshots.kept.len(), MAX_PAGE_SNAPSHOTS);
+    }
+
+    #[test]
+    fn batch_outcomes_follow_input_order() {
+        let input = [
+            (EnvelopeHash(2), MailboxHash(20)),
+            (EnvelopeHash(1), MailboxHash(10)),
+        ];
+        let mut outcomes = HashMap::new();
+        outcomes.insert(EnvelopeHash(1), Ok(()));
+        outcomes.insert(EnvelopeHash(2), Err("boom".to_string()));
+        let results = collect_outcomes(&input, outcomes.clone());
+        assert_eq!(results[0].0, EnvelopeHash(2));
+        assert!(results[0].1.is_err());
+        assert_eq!(results[1].0, EnvelopeHash(1));
+        assert!(results[1].1.is_ok());
+
+        // A repeated envelope shares the outcome of its single send.
+        let repeated = [
+            (EnvelopeHash(1), MailboxHash(10)),
+            (EnvelopeHash(2), MailboxHash(20)),
+            (EnvelopeHash(1), MailboxHash(10)),
+        ];
+        let results = collect_outcomes(&repeated, outcomes);
+        assert!(results[0].1.is_ok());
+        assert!(results[1].1.is_err());
+        assert!(results[2].1.is_ok());
+    }
+
+    #[test]
+    fn backoff_doubles_per_attempt_up_to_the_cap() {
+        assert_eq!(backoff_delay(1), Duration::from_secs(1));
+        assert_eq!(backoff_delay(2), Duration::from_secs(2));
+        assert_eq!(backoff_delay(4), Duration::from_secs(8));
+        assert_eq!(backoff_delay(9), Duration::from_secs(256));
+        assert_eq!(backoff_delay(10), MAX_BACKOFF);
+        assert_eq!(backoff_delay(u32::MAX), MAX_BACKOFF);
+    }
+
+    #[test]
+    fn page_bounds_take_newest_uids_and_resume_below_them() {
+        let uids = sorted_uids(vec![12, 3, 7, 40, 41, 9, 12]);
+        assert_eq!(page_bounds(&uids, None, 2), Some((40, 41, Some(40))));
+        assert_eq!(page_bounds(&uids, None, 6), Some((3, 41, None)));
+        assert_eq!(page_bounds(&uids, None, 100), Some((3, 41, None)));
+        assert_eq!(page_bounds(&[], None, 10), None);
+
+        // Later pages slice the same list below the cursor.
+        assert_eq!(page_bounds(&uids, Some(40), 2), Some((9, 12, Some(9))));
+        assert_eq!(page_bounds(&uids, Some(9), 2), Some((3, 7, None)));
+        assert_eq!(page_bounds(&uids, Some(3), 2), None);
+        assert_eq!(page_bounds(&uids, Some(1), 2), None);
+    }
+
+    fn refresh(kind: RefreshEventKind) -> RefreshEvent {
+        RefreshEvent {
+            account_hash: AccountHash::default(),
+            mailbox_hash: MailboxHash(7),
+            kind,
+        }
+    }
+
+    #[test]
+    fn refresh_events_translate_to_mail_events() {
+        let tags = BTreeMap::new();
+        let events = translate_refresh(
+            refresh(RefreshEventKind::NewFlags(
+                EnvelopeHash(3),
+                (
+                    Flag::SEEN | Flag::REPLIED,
+                    vec!["$Forwarded".to_string(), "$Label1".to_string()],
+                ),
+            )),
+            &tags,
+        );
+        assert!(matches!(
+            events.as_slice(),
+            [MailEvent::FlagsChanged {
+                mailbox_hash: 7,
+                envelope_hash: 3,
+                is_read: true,
+                is_starred: false,
+                is_answered: true,
+                is_draft: false,
+                is_deleted: false,
+                is_forwarded: true,
+                keywords,
+            }] if keywords == &["$Label1"]
+        ));
+
+        let events = translate_refresh(refresh(RefreshEventKind::Remove(EnvelopeHash(4))), &tags);
+        assert!(matches!(
+            events.as_slice(),
+            [MailEvent::MessageRemoved {
+                mailbox_hash: 7,
+                envelope_hash: 4,
+            }]
+        ));
+
+        let events = translate_refresh(refresh(RefreshEventKind::Rescan), &tags);
+        assert!(matches!(
+            events.as_slice(),
+            [MailEvent::MailboxNeedsResync { mailbox_hash: 7 }]
+        ));
+
+        let events = translate_refresh(
+            refresh(RefreshEventKind::MailboxDelete(MailboxHash(9))),
+            &tags,
+        );
+        assert!(matches!(events.as_slice(), [MailEvent::FoldersChanged]));
+    }
+
+    #[test]
+    fn reply_headers_parse_in_any_item_order() {
+        let headers = b"Reply-To: \"Team\" <team@example.com>, bob@example.com\r\n\
+                        Sender: list@example.com\r\n\r\n";
+        let response = [
+            format!(
+                "* 1 FETCH (UID 11 BODY[HEADER.FIELDS (REPLY-TO SENDER)] {{{}}}\r\n",
+                headers.len()
+            )
+            .as_bytes(),
+            headers,
+            // Header item first, field names quoted, an unasked-for FLAGS.
+            b")\r\n* 2 FETCH (BODY[HEADER.FIELDS (\"REPLY-TO\" \"SENDER\")] {2}\r\n\r\n \
+              FLAGS (\\Seen) UID 12)\r\n",
+            b"* 3 FETCH (FLAGS () BODY[HEADER.FIELDS (REPLY-TO SENDER)] \"\" UID 13)\r\n",
+            b"M5 OK Fetch completed\r\n",
+        ]
+        .concat();
+
+        let mut fetched = reply_header_responses(&response);
+        assert_eq!(fetched.len(), 3);
+        assert_eq!(fetched.remove(&12), Some(MessageAddresses::default()));
+        assert_eq!(fetched.remove(&13), Some(MessageAddresses::default()));
+
+        let addresses = fetched.remove(&11).expect("uid 11");
+        assert_eq!(
+            addresses.reply_to,
+            vec![
+                EmailAddress {
+                    name: Some("Team".into()),
+                    email: "team@example.com".into(),
+                },
+                EmailAddress {
+                    name: None,
+                    email: "bob@example.com".into(),
+                },
+            ]
+        );
+        assert_eq!(addresses.sender[0].email, "list@example.com");
+    }
+
+    #[test]
+    fn text_parts_decode_by_charset_or_detection() {
+        let cases: [(&[u8], &str); 6] = [
+            (
+                include_bytes!("../tests/fixtures/charset_latin1_qp.eml"),
+                "Grüße aus Köln, café à la carte.",
+            ),
+            (
+                include_bytes!("../tests/fixtures/charset_windows1252.eml"),
+                "Das kostet 20 € – „günstig“.",
+            ),
+            (
+                include_bytes!("../tests/fixtures/charset_shift_jis.eml"),
+                "こんにちは、世界。お元気ですか。",
+            ),
+            (
+                include_bytes!("../tests/fixtures/charset_gb2312_html.eml"),
+                "你好，世界。欢迎使用邮件。",
+            ),
+            // No charset parameter, and a utf-8 label on Windows-1252 bytes.
+            (
+                include_bytes!("../tests/fixtures/charset_missing.eml"),
+                "Grüße aus Düsseldorf und schöne Größe.",
+            ),
+            (
+                include_bytes!("../tests/fixtures/charset_mislabeled.eml"),
+                "Lieferung für Müller erfolgt übermorgen.",
+            ),
+        ];
+        for (raw, expected) in cases {
+            let mail = melib::Mail::new(raw.to_vec(), None).expect("parse fixture");
+            let (plain, html, _) = extract_body(&mail.body());
+            let text = plain.or(html).expect("text part");
+            assert!(text.contains(expected), "{expected:?} not in {text:?}");
+        }
+    }
+}
@@ -0,0 +1,357 @@
+//! `BODYSTRUCTURE` (RFC 3501 §7.4.2) and `BODY[<part>]` fetch responses,
+//! neither of which melib's FETCH parser returns.
+
+use melib::email::attachments::{Attachment, AttachmentBuilder};
+
+use crate::models::AttachmentInfo;
+
+use super::split_literal;
+
+/// One parsed IMAP value.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) enum Value {
+    Nil,
+    /// An atom, quoted string or literal.
+    Str(Vec<u8>),
+    List(Vec<Value>),
+}
+
+impl Value {
+    fn text(&self) -> Option<String> {
+        match self {
+            Value::Str(s) => Some(String::from_utf8_lossy(s).into_owned()),
+            _ => None,
+        }
+    }
+
+    fn list(&self) -> &[Value] {
+        match self {
+            Value::List(items) => items,
+            _ => &[],
+        }
+    }
+}
+
+/// Parse one value. Atoms may carry a bracketed section, as in `BODY[1.2]`.
+pub(super) fn parse_value(input: &[u8]) -> Option<(Value, &[u8])> {
+    let input = input.trim_ascii_start();
+    match *input.first()? {
+        b'(' => {
+            let mut rest = &input[1..];
+            let mut items = Vec::new();
+            loop {
+                rest = rest.trim_ascii_start();
+                if let Some(tail) = rest.strip_prefix(b")") {
+                    return Some((Value::List(items), tail));
+                }
+                let (item, tail) = parse_value(rest)?;
+                items.push(item);
+                rest = tail;
+            }
+        }
+        b'"' => {
+            let mut out = Vec::new();
+            let mut i = 1;
+            loop {
+                match *input.get(i)? {
+                    b'\\' => {
+                        out.push(*input.get(i + 1)?);
+                        i += 2;
+                    }
+                    b'"' => return Some((Value::Str(out), &input[i + 1..])),
+                    b => {
+                        out.push(b);
+                        i += 1;
+                    }
+                }
+            }
+        }
+        b'{' => {
+            let (literal, rest) = split_literal(input)?;
+            Some((Value::Str(literal.to_vec()), rest))
+        }
+        _ => {
+            let mut i = 0;
+            while let Some(&b) = input.get(i) {
+                match b {
+                    b'[' => i += input[i..].iter().position(|&c| c == b']')? + 1,
+                    b' ' | b'(' | b')' | b'\r' | b'\n' => break,
+                    _ => i += 1,
+                }
+            }
+            if i == 0 {
+                return None;
+            }
+            let atom = &input[..i];
+            let value = if atom.eq_ignore_ascii_case(b"NIL") {
+                Value::Nil
+            } else {
+                Value::Str(atom.to_vec())
+            };
+            Some((value, &input[i..]))
+        }
+    }
+}
+
+/// The attribute list of every untagged FETCH response in `input`.
+pub(super) fn fetch_attributes(mut input: &[u8]) -> Vec<Vec<Value>> {
+    const FETCH: &[u8] = b" FETCH (";
+    let mut out = Vec::new();
+    while let Some(start) = input.windows(FETCH.len()).position(|w| w == FETCH) {
+        // Parse from the opening parenthesis.
+        match parse_value(&input[start + FETCH.len() - 1..]) {
+            Some((Value::List(items), rest)) => {
+                out.push(items);
+                input = rest;
+            }
+            _ => input = &input[start + FETCH.len()..],
+        }
+    }
+    out
+}
+
+/// A FETCH attribute by name, e.g. `UID`, `BODYSTRUCTURE` or `BODY[1.2]`.
+pub(super) fn attribute<'a>(items: &'a [Value], name: &str) -> Option<&'a Value> {
+    items.chunks(2).find_map(|pair| match pair {
+        [Value::Str(key), value] if key.eq_ignore_ascii_case(name.as_bytes()) => Some(value),
+        _ => None,
+    })
+}
+
+/// A leaf of the MIME tree. Nested `message/rfc822` parts are leaves too.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(super) struct BodyPart {
+    /// Section number, e.g. `1` or `2.1`.
+    pub part: String,
+    /// Lowercase `type/subtype`.
+    pub mime_type: String,
+    pub params: Vec<(String, String)>,
+    pub id: Option<String>,
+    pub encoding: String,
+    pub size: u64,
+    /// Lowercase disposition (`inline`, `attachment`) and its parameters.
+    pub disposition: Option<String>,
+    pub disposition_params: Vec<(String, String)>,
+}
+
+/// The leaves of a `BODYSTRUCTURE`, in order.
+pub(super) fn body_parts(structure: &Value) -> Vec<BodyPart> {
+    let mut out = Vec::new();
+    walk(structure, "", &mut out);
+    out
+}
+
+fn walk(value: &Value, path: &str, out: &mut Vec<BodyPart>) {
+    let items = value.list();
+    if matches!(items.first(), Some(Value::List(_))) {
+        let children = items.iter().take_while(|v| matches!(v, Value::List(_)));
+        for (i, child) in children.enumerate() {
+            let part = if path.is_empty() {
+                (i + 1).to_string()
+            } else {
+                format!("{path}.{}", i + 1)
+            };
+            walk(child, &part, out);
+        }
+        return;
+    }
+
+    let field = |i: usize| {
+        items
+            .get(i)
+            .and_then(Value::text)
+            .unwrap_or_default()
+            .to_ascii_lowercase()
+    };
+    let mime_type = format!("{}/{}", field(0), field(1));
+    // Extension data starts after the type-specific fields: line count for
+    // text, envelope + body + line count for messages.
+    let disposition_at = match mime_type.as_str() {
+        t if t.starts_with("text/") => 9,
+        "message/rfc822" | "message/global" => 11,
+        _ => 8,
+    };
+    let disposition = items
+        .get(disposition_at)
+        .map(Value::list)
+        .unwrap_or_default();
+    out.push(BodyPart {
+        part: if path.is_empty() { "1" } else { path }.to_string(),
+        mime_type,
+        params: params(items.get(2)),
+        id: items.get(3).and_then(Value::text),
+        encoding: field(5),
+        size: field(6).parse().unwrap_or(0),
+        disposition: disposition
+            .first()
+            .and_then(Value::text)
+            .map(|d| d.to_ascii_lowercase()),
+        disposition_params: params(disposition.get(1)),
+    });
+}
+
+fn params(value: Option<&Value>) -> Vec<(String, String)> {
+    value
+        .map(Value::list)
+        .unwrap_or_default()
+        .chunks(2)
+        .filter_map(|pair| {
+            Some((
+                pair.first()?.text()?.to_ascii_lowercase(),
+                pair.get(1)?.text()?,
+            ))
+        })
+        .collect()
+}
+
+impl BodyPart {
+    /// Inline text/plain or text/html, which `fetch_body` renders. The same
+    /// split `extract_parts` makes on a fully downloaded message.
+    pub(super) fn is_body_text(&self) -> bool {
+        matches!(self.mime_type.as_str(), "text/plain" | "text/html")
+            && self.disposition.as_deref() != Some("attachment")
+    }
+
+    /// A stand-in MIME entity with this part's headers and `body`, so melib
+    /// applies the transfer encoding, charset and RFC 2231/2047 names.
+    pub(super) fn entity(&self, body: &[u8]) -> Attachment {
+        let mut headers = format!("Content-Type: {}", self.mime_type);
+        push_params(&mut headers, &self.params);
+        if let Some(disposition) = &self.disposition {
+            headers.push_str("\r\nContent-Disposition: ");
+            headers.push_str(disposition);
+            push_params(&mut headers, &self.disposition_params);
+        }
+        if !self.encoding.is_empty() {
+            headers.push_str("\r\nContent-Transfer-Encoding: ");
+            headers.push_str(&self.encoding);
+        }
+        headers.push_str("\r\n\r\n");
+        let mut raw = headers.into_bytes();
+        raw.extend_from_slice(body);
+        AttachmentBuilder::new(&raw).build()
+    }
+
+    /// This part as a not-yet-downloaded attachment.
+    pub(super) fn info(&self) -> AttachmentInfo {
+        let entity = self.entity(b"");
+        let content_id = self
+            .id
+            .as_deref()
+            .map(|id| id.trim().trim_start_matches('<').trim_end_matches('>'))
+            .filter(|id| !id.is_empty())
+            .map(str::to_string);
+        AttachmentInfo {
+            part: self.part.clone(),
+            filename: entity
+                .filename()
+                .or_else(|| entity.content_disposition.filename.clone())
+                .unwrap_or_else(|| "unnamed".into()),
+            mime_type: self.mime_type.clone(),
+            size: self.size,
+            is_inline: match self.disposition.as_deref() {
+                Some(disposition) => disposition == "inline",
+                None => content_id.is_some(),
+            },
+            content_id,
+            encoding: self.encoding.clone(),
+        }
+    }
+}
+
+fn push_params(headers: &mut String, params: &[(String, String)]) {
+    for (name, value) in params {
+        let is_token = !value.is_empty()
+            && value
+                .bytes()
+                .all(|b| b.is_ascii_graphic() && !b"()<>@,;:\\\"/[]?=".contains(&b));
+        if is_token {
+            headers.push_str(&format!("; {name}={value}"));
+        } else {
+            let quoted = value.replace('\\', "\\\\").replace('"', "\\\"");
+            headers.push_str(&format!("; {name}=\"{quoted}\""));
+        }
+    }
+}
+
+/// Undo a part's Content-Transfer-Encoding without touching its charset.
+pub(super) fn decode_transfer(body: &[u8], encoding: &str) -> Vec<u8> {
+    BodyPart {
+        mime_type: "application/octet-stream".into(),
+        encoding: encoding.to_string(),
+        ..Default::default()
+    }
+    .entity(body)
+    .decode(Default::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{attribute, body_parts, decode_transfer, fetch_attributes, Value};
+
+    const RESPONSE: &[u8] = b"* 12 FETCH (UID 7 BODYSTRUCTURE (((\"text\" \"plain\" (\"charset\" \"iso-8859-1\") NIL NIL \"quoted-printable\" 20 1 NIL NIL NIL NIL)(\"text\" \"html\" (\"charset\" \"utf-8\") NIL NIL \"7bit\" 40 2 NIL NIL NIL NIL) \"alternative\" (\"boundary\" \"b2\") NIL NIL)(\"image\" \"png\" (\"name\" \"logo.png\") \"<logo@x>\" NIL \"base64\" 1918 NIL (\"inline\" (\"filename\" \"logo.png\")) NIL NIL)(\"application\" \"pdf\" (\"name\" \"=?utf-8?q?Rechnung_M=C3=A4rz.pdf?=\") NIL NIL \"base64\" 88012 NIL (\"attachment\" NIL) NIL NIL) \"mixed\" (\"boundary\" \"b1\") NIL NIL))\r\nM3 OK done\r\n";
+
+    #[test]
+    fn bodystructure_yields_numbered_leaves() {
+        let responses = fetch_attributes(RESPONSE);
+        assert_eq!(responses.len(), 1);
+        let attrs = &responses[0];
+        assert_eq!(attribute(attrs, "UID"), Some(&Value::Str(b"7".to_vec())));
+
+        let parts = body_parts(attribute(attrs, "BODYSTRUCTURE").expect("bodystructure"));
+        let summary: Vec<_> = parts
+            .iter()
+            .map(|p| (p.part.as_str(), p.mime_type.as_str(), p.is_body_text()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("1.1", "text/plain", true),
+                ("1.2", "text/html", true),
+                ("2", "image/png", false),
+                ("3", "application/pdf", false),
+            ]
+        );
+        assert_eq!(parts[0].encoding, "quoted-printable");
+        assert_eq!(parts[3].size, 88012);
+
+        let logo = parts[2].info();
+        assert_eq!(logo.filename, "logo.png");
+        assert_eq!(logo.content_id.as_deref(), Some("logo@x"));
+        assert!(logo.is_inline);
+
+        let invoice = parts[3].info();
+        assert_eq!(invoice.filename, "Rechnung März.pdf");
+        assert!(!invoice.is_inline);
+    }
+
+    #[test]
+    fn single_part_message_is_section_one() {
+        let structure =
+            b"(\"TEXT\" \"PLAIN\" (\"CHARSET\" \"us-ascii\") NIL NIL \"7BIT\" 3 1 NIL NIL NIL NIL)";
+        let (value, _) = super::parse_value(structure).expect("parse");
+        let parts = body_parts(&value);
+        assert_eq!(parts.len(), 1);
+        assert_eq!(parts[0].part, "1");
+        assert_eq!(parts[0].mime_type, "text/plain");
+    }
+
+    #[test]
+    fn section_literals_decode_with_their_charset() {
+        let response = b"* 12 FETCH (UID 7 BODY[1.1] {20}\r\nGr=FC=DFe aus K=F6ln BODY[2] {8}\r\naGVsbG8=)\r\n";
+        let attrs = &fetch_attributes(response)[0];
+        let Some(Value::Str(text)) = attribute(attrs, "BODY[1.1]") else {
+            panic!("missing text section");
+        };
+        let structure = &fetch_attributes(RESPONSE)[0];
+        let plain =
+            body_parts(attribute(structure, "BODYSTRUCTURE").expect("bodystructure")).remove(0);
+        let decoded = plain.entity(text).decode(Default::default());
+        assert_eq!(String::from_utf8_lossy(&decoded), "Grüße aus Köln");
+
+        let Some(Value::Str(image)) = attribute(attrs, "BODY[2]") else {
+            panic!("missing image section");
+        };
+        assert_eq!(decode_transfer(image, "base64"), b"hello");
+    }
+}
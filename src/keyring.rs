@@ -69,3 +69,37 @@ pub fn set_smtp_password(account_id: &str, password: &str) -> Result<(), String>
         .set_password(password)
         .map_err(|e| format!("keyring set: {e}"))
 }
+
+fn refresh_token_key(username: &str, token_url: &str) -> String {
+    format!("oauth2-{username}@{token_url}")
+}
+
+/// Get the OAuth2 refresh token for `username` at a provider's token endpoint.
+pub fn get_refresh_token(username: &str, token_url: &str) -> Result<String, String> {
+    let key = refresh_token_key(username, token_url);
+    log::debug!("keyring GET oauth2: service={SERVICE:?} key={key:?}");
+    let entry = keyring::Entry::new(SERVICE, &key).map_err(|e| format!("keyring error: {e}"))?;
+    entry
+        .get_password()
+        .map_err(|e| format!("keyring get: {e}"))
+}
+
+/// Store the OAuth2 refresh token for `username` at a provider's token endpoint.
+pub fn set_refresh_token(username: &str, token_url: &str, token: &str) -> Result<(), String> {
+    let key = refresh_token_key(username, token_url);
+    log::debug!("keyring SET oauth2: service={SERVICE:?} key={key:?}");
+    let entry = keyring::Entry::new(SERVICE, &key).map_err(|e| format!("keyring error: {e}"))?;
+    entry
+        .set_password(token)
+        .map_err(|e| format!("keyring set: {e}"))
+}
+
+pub fn delete_refresh_token(username: &str, token_url: &str) -> Result<(), String> {
+    let key = refresh_token_key(username, token_url);
+    log::debug!("keyring DELETE oauth2: service={SERVICE:?} key={key:?}");
+    let entry = keyring::Entry::new(SERVICE, &key).map_err(|e| format!("keyring error: {e}"))?;
+    entry.delete_credential().map_err(|e| {
+        log::warn!("keyring delete failed for key={key:?}: {e}");
+        format!("keyring delete: {e}")
+    })
+}
@@ -1,13 +1,18 @@
+pub mod compose;
 pub mod config;
 pub mod imap;
 pub mod keyring;
 pub mod mime;
 pub mod models;
+pub mod oauth;
+pub mod outbox;
 pub mod setup;
 pub mod smtp;
 pub mod store;
+pub mod threading;
+mod tls;
 
 // Re-export melib types used by consumers
-pub use melib::backends::{BackendEvent, FlagOp, RefreshEventKind};
+pub use melib::backends::FlagOp;
 pub use melib::email::Flag;
 pub use melib::{EnvelopeHash, MailboxHash};
@@ -1,3 +1,7 @@
+use std::path::{Path, PathBuf};
+
+use crate::models::MessageSummary;
+
 /// Render an email body to plain text for display.
 ///
 /// Prefers text/plain when available; falls back to sanitized HTML conversion.
@@ -15,14 +19,837 @@ pub fn render_body_markdown(text_plain: Option<&str>, text_html: Option<&str>) -
     html_safe_md::render_email(text_plain, text_html)
 }
 
+/// Like [`render_body_markdown`], but keeps images in place. An
+/// `<img src="cid:...">` whose Content-ID is in `content_ids` becomes
+/// `![alt](attachment:N)`, where N is its index there, which is also its
+/// index in the attachment list that came with the body. Remote images are
+/// kept only when `remote` allows them; unknown Content-IDs are dropped.
+/// When the HTML part places any kept image it's rendered even if the
+/// plain part would do.
+pub fn render_body_markdown_inline(
+    text_plain: Option<&str>,
+    text_html: Option<&str>,
+    content_ids: &[Option<String>],
+    remote: RemoteContent,
+) -> String {
+    let Some((html, images)) = text_html
+        .map(|html| mark_images(html, content_ids, remote))
+        .filter(|(_, images)| !images.is_empty())
+    else {
+        return render_body_markdown(text_plain, text_html);
+    };
+    let mut markdown = render_body_markdown(None, Some(&html));
+    for (marker, image) in images.iter().enumerate() {
+        markdown = markdown.replace(&format!("{IMAGE_MARKER}{marker}X"), image);
+    }
+    markdown
+}
+
+/// Whether a body's remote images (tracking pixels included) are loaded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RemoteContent {
+    #[default]
+    Block,
+    Allow,
+}
+
+/// A sender, or a whole domain and its subdomains, whose remote content is
+/// always shown. Stored lowercase.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum RemoteContentRule {
+    Sender(String),
+    Domain(String),
+}
+
+impl RemoteContentRule {
+    pub fn sender(email: &str) -> Self {
+        Self::Sender(email.trim().to_lowercase())
+    }
+
+    pub fn domain(domain: &str) -> Self {
+        Self::Domain(domain.trim().trim_start_matches('@').to_lowercase())
+    }
+
+    /// Stable `(kind, value)` form used in the cache.
+    pub fn as_parts(&self) -> (&'static str, &str) {
+        match self {
+            Self::Sender(email) => ("sender", email),
+            Self::Domain(domain) => ("domain", domain),
+        }
+    }
+
+    /// Inverse of [`as_parts`](Self::as_parts).
+    pub fn from_parts(kind: &str, value: &str) -> Option<Self> {
+        match kind {
+            "sender" => Some(Self::sender(value)),
+            "domain" => Some(Self::domain(value)),
+            _ => None,
+        }
+    }
+
+    fn matches(&self, email: &str) -> bool {
+        let email = email.trim().to_lowercase();
+        match self {
+            Self::Sender(sender) => *sender == email,
+            Self::Domain(domain) => email.rsplit_once('@').is_some_and(|(_, host)| {
+                host == domain
+                    || host
+                        .strip_suffix(domain.as_str())
+                        .is_some_and(|sub| sub.ends_with('.'))
+            }),
+        }
+    }
+}
+
+/// An account's remote content allowlist, from
+/// [`CacheHandle::load_remote_content_rules`](crate::store::CacheHandle::load_remote_content_rules).
+/// Showing one message's content once needs no rule: render it with
+/// [`RemoteContent::Allow`].
+#[derive(Debug, Clone, Default)]
+pub struct RemoteContentPolicy {
+    rules: Vec<RemoteContentRule>,
+}
+
+impl RemoteContentPolicy {
+    pub fn new(rules: Vec<RemoteContentRule>) -> Self {
+        Self { rules }
+    }
+
+    /// `Allow` if any From address is listed, by itself or by domain. From
+    /// can be forged; the worst a forger gains is a loaded tracking pixel.
+    pub fn for_message(&self, message: &MessageSummary) -> RemoteContent {
+        let allowed = message
+            .addresses
+            .from
+            .iter()
+            .any(|addr| self.rules.iter().any(|rule| rule.matches(&addr.email)));
+        if allowed {
+            RemoteContent::Allow
+        } else {
+            RemoteContent::Block
+        }
+    }
+}
+
+/// Stands in for an image while the HTML goes through the sanitizer, which
+/// drops `<img>`. Plain letters so no stage escapes it.
+const IMAGE_MARKER: &str = "NLINLINEIMAGE";
+
+/// Replace each `<img>` that is kept (a known `cid:`, or `http(s):` when
+/// `remote` allows) with a marker. Returns the HTML and the Markdown image
+/// for each marker.
+fn mark_images(
+    html: &str,
+    content_ids: &[Option<String>],
+    remote: RemoteContent,
+) -> (String, Vec<String>) {
+    let lower = html.to_ascii_lowercase();
+    let mut out = String::with_capacity(html.len());
+    let mut images = Vec::new();
+    let mut rest = 0;
+    while let Some(start) = lower[rest..].find("<img").map(|i| rest + i) {
+        let Some(end) = tag_end(html, start) else {
+            break;
+        };
+        let tag = &html[start..end];
+        let target = tag_attribute(tag, "src").and_then(|src| {
+            let src = src.trim();
+            let scheme = src
+                .split_once(':')
+                .map(|(scheme, _)| scheme.to_ascii_lowercase());
+            match scheme.as_deref() {
+                Some("cid") => {
+                    let cid = src[4..].trim_start_matches('<').trim_end_matches('>');
+                    content_ids
+                        .iter()
+                        .position(|id| id.as_deref().is_some_and(|id| id.eq_ignore_ascii_case(cid)))
+                        .map(|index| format!("attachment:{index}"))
+                }
+                Some("http" | "https") if remote == RemoteContent::Allow => Some(
+                    src.replace(' ', "%20")
+                        .replace('(', "%28")
+                        .replace(')', "%29"),
+                ),
+                _ => None,
+            }
+        });
+        out.push_str(&html[rest..start]);
+        match target {
+            Some(target) => {
+                let alt: String = tag_attribute(tag, "alt")
+                    .unwrap_or_default()
+                    .chars()
+                    .filter(|c| !matches!(c, '[' | ']' | '(' | ')') && !c.is_control())
+                    .collect();
+                let alt = match alt.trim() {
+                    "" => "image",
+                    alt => alt,
+                };
+                out.push_str(&format!("{IMAGE_MARKER}{}X", images.len()));
+                images.push(format!("![{alt}]({target})"));
+            }
+            None => out.push_str(tag),
+        }
+        rest = end;
+    }
+    out.push_str(&html[rest..]);
+    (out, images)
+}
+
+/// Byte offset just past the `>` closing the tag at `start`, skipping
+/// quoted attribute values.
+fn tag_end(html: &str, start: usize) -> Option<usize> {
+    let mut quote = None;
+    for (i, c) in html[start..].char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '>') => return Some(start + i + 1),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// The value of attribute `name` in a start tag, unescaping the few
+/// entities that show up in URLs and alt text.
+fn tag_attribute(tag: &str, name: &str) -> Option<String> {
+    let lower = tag.to_ascii_lowercase();
+    let mut from = 0;
+    while let Some(at) = lower[from..].find(name).map(|i| from + i) {
+        from = at + name.len();
+        let boundary = lower[..at].ends_with(|c: char| c.is_ascii_whitespace());
+        let rest = lower[from..].trim_start();
+        if !boundary || !rest.starts_with('=') {
+            continue;
+        }
+        let value_at = tag.len() - rest.len() + 1;
+        let value = tag[value_at..].trim_start();
+        let value = match value.chars().next()? {
+            q @ ('"' | '\'') => value[1..].split(q).next()?,
+            _ => value
+                .split(|c: char| c.is_ascii_whitespace() || c == '>')
+                .next()?,
+        };
+        return Some(
+            value
+                .replace("&lt;", "<")
+                .replace("&gt;", ">")
+                .replace("&quot;", "\"")
+                .replace("&amp;", "&"),
+        );
+    }
+    None
+}
+
+/// What a stretch of a rendered body is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BodySectionKind {
+    /// What the sender wrote in this message.
+    Content,
+    /// Earlier mail: `>` lines, or everything below an "On ... wrote:" line
+    /// or an Outlook "From:/Sent:" header block.
+    Quote,
+    /// Everything after a `-- ` delimiter, up to the next quote.
+    Signature,
+}
+
+/// One section of a body split by [`body_sections`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BodySection {
+    pub kind: BodySectionKind,
+    /// For quotes: the "On ... wrote:" line or the Outlook header block.
+    pub attribution: Option<String>,
+    /// The section's lines as rendered, quote markers and all.
+    pub text: String,
+}
+
+/// Split a body from [`render_body`] or [`render_body_markdown`] into new
+/// content, quotes and signatures, in order, so UIs can collapse long
+/// reply chains. Signature delimiters and blank lines around sections are
+/// dropped.
+pub fn body_sections(body: &str) -> Vec<BodySection> {
+    let lines: Vec<&str> = body.lines().collect();
+    let mut sections: Vec<BodySection> = Vec::new();
+    let push = |sections: &mut Vec<BodySection>,
+                kind: BodySectionKind,
+                attribution: Option<String>,
+                line: &str| {
+        match sections.last_mut() {
+            Some(last) if last.kind == kind && attribution.is_none() => {
+                last.text.push('\n');
+                last.text.push_str(line);
+            }
+            _ => sections.push(BodySection {
+                kind,
+                attribution,
+                text: line.to_string(),
+            }),
+        }
+    };
+
+    let mut kind = BodySectionKind::Content;
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i];
+        if let Some(header_end) = outlook_header_block(&lines, i) {
+            let attribution = lines[i..header_end]
+                .join("\n")
+                .trim_matches('\n')
+                .to_string();
+            push(
+                &mut sections,
+                BodySectionKind::Quote,
+                Some(attribution),
+                &lines[header_end..].join("\n"),
+            );
+            break;
+        }
+        if let Some(after) = attribution_line(&lines, i) {
+            let attribution = lines[i..after].join("\n");
+            let next = (after..lines.len()).find(|&j| !lines[j].trim().is_empty());
+            match next {
+                Some(j) if is_quoted(lines[j]) => {
+                    push(&mut sections, BodySectionKind::Quote, Some(attribution), "");
+                    kind = BodySectionKind::Quote;
+                    i = j;
+                    continue;
+                }
+                // Followed by the original's headers: the rest is the
+                // quoted message.
+                Some(j) if outlook_header_block(&lines, j).is_some() => {
+                    push(
+                        &mut sections,
+                        BodySectionKind::Quote,
+                        Some(attribution),
+                        &lines[after..].join("\n"),
+                    );
+                    break;
+                }
+                // Just a sentence that happens to read like one.
+                _ => {}
+            }
+        }
+        if line == "-- " || line == "--" {
+            kind = BodySectionKind::Signature;
+            push(&mut sections, kind, Some(String::new()), "");
+            i += 1;
+            continue;
+        }
+        if is_quoted(line) {
+            kind = BodySectionKind::Quote;
+        } else if kind == BodySectionKind::Quote && !line.trim().is_empty() {
+            kind = BodySectionKind::Content;
+        }
+        push(&mut sections, kind, None, line);
+        i += 1;
+    }
+
+    for section in &mut sections {
+        section.text = section.text.trim_matches('\n').to_string();
+        if section.attribution.as_deref() == Some("") {
+            section.attribution = None;
+        }
+    }
+    sections.retain(|s| !s.text.trim().is_empty() || s.attribution.is_some());
+    sections
+}
+
+fn is_quoted(line: &str) -> bool {
+    line.trim_start().starts_with('>')
+}
+
+/// A Gmail/Apple style "On <date>, <name> wrote:" line, possibly wrapped
+/// over two lines. Returns the index just past it.
+fn attribution_line(lines: &[&str], i: usize) -> Option<usize> {
+    let line = lines[i].trim();
+    if !line.starts_with("On ") || is_quoted(line) {
+        return None;
+    }
+    if line.ends_with("wrote:") {
+        return Some(i + 1);
+    }
+    lines
+        .get(i + 1)
+        .filter(|next| next.trim().ends_with("wrote:") && !next.trim().is_empty())
+        .map(|_| i + 2)
+}
+
+/// An Outlook reply header: optionally a separator line, then `From:` with
+/// `Sent:` (or `Date:`) and `To:` or `Subject:` among the next lines.
+/// Markdown emphasis around the names is ignored. Returns the index just
+/// past the block.
+fn outlook_header_block(lines: &[&str], i: usize) -> Option<usize> {
+    let name = |line: &str| {
+        let line = line.trim().trim_start_matches(['*', '_']);
+        line.split_once(':').map(|(name, _)| {
+            name.trim_end_matches(['*', '_'])
+                .trim()
+                .to_ascii_lowercase()
+        })
+    };
+    let separator = |line: &str| {
+        let line = line.trim();
+        line.contains("Original Message")
+            || (line.len() >= 10 && line.chars().all(|c| c == '_' || c == '-'))
+    };
+    let start = if separator(lines[i]) { i + 1 } else { i };
+    let start = (start..lines.len()).find(|&j| !lines[j].trim().is_empty())?;
+    if name(lines[start]).as_deref() != Some("from") {
+        return None;
+    }
+    let mut end = start + 1;
+    let (mut sent, mut to) = (false, false);
+    while end < lines.len() && !lines[end].trim().is_empty() {
+        match name(lines[end]).as_deref() {
+            Some("sent" | "date") => sent = true,
+            Some("to" | "subject" | "cc") => to = true,
+            _ => {}
+        }
+        end += 1;
+    }
+    (sent && to).then_some(end)
+}
+
+/// Undo RFC 3676 `format=flowed`: join soft-broken lines (those ending in
+/// a space) of the same quote depth into paragraphs. With `delsp`, the
+/// trailing space was added by the sender and is removed. Quoted lines come
+/// back as `> `, `>> `, ... followed by the joined text.
+pub fn decode_flowed(text: &str, delsp: bool) -> String {
+    let mut out = String::with_capacity(text.len());
+    let finish = |out: &mut String, depth: usize, line: &str| {
+        if depth > 0 {
+            out.push_str(&">".repeat(depth));
+            out.push(' ');
+        }
+        out.push_str(line);
+        out.push('\n');
+    };
+    // Quote depth and text of the paragraph being joined.
+    let mut open: Option<(usize, String)> = None;
+    for raw in text.lines() {
+        let depth = raw.chars().take_while(|&c| c == '>').count();
+        let line = &raw[depth..];
+        let line = line.strip_prefix(' ').unwrap_or(line);
+        let separator = line == "-- ";
+        let mut paragraph = match open.take() {
+            Some((open_depth, paragraph)) if open_depth == depth && !separator => paragraph + line,
+            // A quote depth change or a signature separator ends a
+            // paragraph, soft break or not.
+            Some((open_depth, paragraph)) => {
+                finish(&mut out, open_depth, paragraph.trim_end());
+                line.to_string()
+            }
+            None => line.to_string(),
+        };
+        if line.ends_with(' ') && !separator {
+            if delsp {
+                paragraph.pop();
+            }
+            open = Some((depth, paragraph));
+        } else {
+            finish(&mut out, depth, &paragraph);
+        }
+    }
+    if let Some((depth, paragraph)) = open {
+        finish(&mut out, depth, paragraph.trim_end());
+    }
+    if !text.ends_with('\n') && out.ends_with('\n') {
+        out.pop();
+    }
+    out
+}
+
+/// Line length [`encode_flowed`] wraps at, leaving room under RFC 3676's 78.
+const FLOWED_WIDTH: usize = 72;
+
+/// Encode a plain-text body as RFC 3676 `format=flowed` (`delsp=no`): long
+/// lines are soft-broken after a space, quoted lines (`> `) keep their depth
+/// on every piece, and lines that would be misread are space-stuffed. Send
+/// it with `Content-Type: text/plain; format=flowed`.
+pub fn encode_flowed(text: &str) -> String {
+    let mut out = String::with_capacity(text.len() + text.len() / 32);
+    for raw in text.lines() {
+        if raw == "-- " {
+            out.push_str("-- \r\n");
+            continue;
+        }
+        let depth = raw.chars().take_while(|&c| c == '>').count();
+        let content = match depth {
+            0 => raw,
+            _ => raw[depth..].strip_prefix(' ').unwrap_or(&raw[depth..]),
+        };
+        // Trailing spaces on a hard line break would read as a soft one.
+        let content = content.trim_end_matches(' ');
+        let prefix = ">".repeat(depth);
+        let width = FLOWED_WIDTH.saturating_sub(depth + 1).max(20);
+
+        let mut pieces = Vec::new();
+        let mut rest = content;
+        while rest.chars().count() > width {
+            let cut = rest
+                .char_indices()
+                .take_while(|&(i, _)| i <= width)
+                .filter(|&(_, c)| c == ' ')
+                .last()
+                .map(|(i, _)| i + 1)
+                // One long word: break at the next space instead.
+                .or_else(|| rest.find(' ').map(|i| i + 1));
+            let Some(cut) = cut.filter(|&cut| cut < rest.len()) else {
+                break;
+            };
+            pieces.push(&rest[..cut]);
+            rest = &rest[cut..];
+        }
+        pieces.push(rest);
+
+        for piece in pieces {
+            out.push_str(&prefix);
+            let stuff = depth > 0
+                || piece.starts_with(' ')
+                || piece.starts_with('>')
+                || piece.starts_with("From ");
+            if stuff {
+                out.push(' ');
+            }
+            out.push_str(piece);
+            out.push_str("\r\n");
+        }
+    }
+    out
+}
+
+/// Render Markdown as a sanitized HTML document, for the text/html part of
+/// an outgoing message. Raw HTML in the source goes through the same
+/// allowlist; `cid:` image sources are kept for inline images.
+pub fn markdown_to_html(markdown: &str) -> String {
+    use pulldown_cmark::{html, Options, Parser};
+
+    let options = Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH;
+    let mut body = String::new();
+    html::push_html(&mut body, Parser::new_ext(markdown, options));
+    let body = ammonia::Builder::default()
+        .add_url_schemes(["cid"])
+        .clean(&body)
+        .to_string();
+    format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"></head><body>\n{body}</body></html>\n"
+    )
+}
+
+/// Decode a text part's bytes (transfer encoding already removed) to a
+/// string. The declared `charset` wins when it decodes cleanly; when it's
+/// missing, unknown, plain ASCII with 8-bit bytes, or the bytes are invalid
+/// in it, the encoding is detected instead.
+pub fn decode_text(bytes: &[u8], charset: Option<&str>) -> String {
+    use encoding_rs::Encoding;
+
+    let declared = charset
+        .map(|c| c.trim().trim_matches('"'))
+        .filter(|c| !(c.eq_ignore_ascii_case("us-ascii") || c.eq_ignore_ascii_case("ascii")))
+        .and_then(|c| Encoding::for_label(c.as_bytes()));
+    if let Some(text) =
+        declared.and_then(|e| e.decode_without_bom_handling_and_without_replacement(bytes))
+    {
+        return text.into_owned();
+    }
+    if let Ok(text) = std::str::from_utf8(bytes) {
+        return text.to_string();
+    }
+    let mut detector = chardetng::EncodingDetector::new();
+    detector.feed(bytes, true);
+    detector
+        .guess(None, true)
+        .decode_without_bom_handling(bytes)
+        .0
+        .into_owned()
+}
+
 /// Open a URL in the system browser.
 pub fn open_link(url: &str) {
     let _ = open::that(url);
 }
 
+/// Header fields of a raw message in order, with folded lines joined.
+/// Values are left undecoded, which is what you want when debugging
+/// delivery (`Received`, `Authentication-Results`, ...).
+pub fn source_headers(raw: &[u8]) -> Vec<(String, String)> {
+    let text = String::from_utf8_lossy(raw);
+    let mut headers: Vec<(String, String)> = Vec::new();
+    for line in text.lines() {
+        if line.is_empty() {
+            break;
+        }
+        if line.starts_with([' ', '\t']) {
+            if let Some((_, value)) = headers.last_mut() {
+                value.push(' ');
+                value.push_str(line.trim());
+            }
+        } else if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+    }
+    headers
+}
+
+/// A file name for a message export: `YYYY-MM-DD subject.eml` (UTC date),
+/// with characters that aren't safe in file names replaced.
+pub fn eml_file_name(message: &MessageSummary) -> String {
+    let date = if message.timestamp > 0 {
+        melib::utils::datetime::timestamp_to_string_utc(
+            message.timestamp as u64,
+            Some("%Y-%m-%d"),
+            true,
+        )
+    } else {
+        String::new()
+    };
+    let subject: String = message
+        .subject
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .take(80)
+        .collect();
+    let subject = subject.trim().trim_start_matches('.');
+    let stem = match (date.as_str(), subject.is_empty()) {
+        ("", true) => format!("message-{}", message.envelope_hash),
+        ("", false) => subject.to_string(),
+        (date, true) => date.to_string(),
+        (date, false) => format!("{date} {subject}"),
+    };
+    format!("{stem}.eml")
+}
+
+/// Write a message's raw source into `dir` as an `.eml` file named by
+/// [`eml_file_name`], adding ` (2)`, ` (3)` ... rather than overwriting.
+/// Returns the path written.
+pub fn export_eml(dir: &Path, message: &MessageSummary, raw: &[u8]) -> Result<PathBuf, String> {
+    std::fs::create_dir_all(dir).map_err(|e| format!("create export dir: {e}"))?;
+    let name = eml_file_name(message);
+    let stem = name.trim_end_matches(".eml");
+    let mut path = dir.join(&name);
+    let mut n = 1;
+    loop {
+        match std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+        {
+            Ok(mut file) => {
+                use std::io::Write;
+                file.write_all(raw)
+                    .map_err(|e| format!("write {}: {e}", path.display()))?;
+                return Ok(path);
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                n += 1;
+                path = dir.join(format!("{stem} ({n}).eml"));
+            }
+            Err(e) => return Err(format!("create {}: {e}", path.display())),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::models::MessageSummary;
+
+    // ── render_body_markdown_inline (cid: images) ─────────────────
+
+    #[test]
+    fn inline_images_point_at_local_parts_and_remote_ones_stay_blocked() {
+        let html = r#"<p>See the error:</p>
+            <p><IMG alt="Screenshot (1)" SRC="cid:shot@local" width=400></p>
+            <p><img src="https://tracker.example.com/open.gif"><img src='cid:missing@local'></p>"#;
+        let ids = vec![None, Some("shot@local".to_string())];
+        let plain = "See the error:\n\n[image: Screenshot (1)]\n\nThanks for looking into this.";
+        let result =
+            render_body_markdown_inline(Some(plain), Some(html), &ids, RemoteContent::Block);
+        assert!(result.contains("![Screenshot 1](attachment:1)"), "{result}");
+        assert!(!result.contains("tracker"));
+        assert!(!result.contains("missing"));
+        assert!(!result.contains(IMAGE_MARKER));
+
+        // No inline images: the plain part still wins.
+        let result = render_body_markdown_inline(
+            Some(plain),
+            Some("<p>HTML</p>"),
+            &ids,
+            RemoteContent::Block,
+        );
+        assert_eq!(result, plain);
+    }
+
+    // ── body_sections (quote and signature folding) ──────────────
+
+    #[test]
+    fn replies_split_into_content_quotes_and_signatures() {
+        let body = "Sounds good, see you then.\n\n-- \nAnn\nExample Corp\n\n\
+            On Mon, 5 Oct 2026 at 10:00, Bob <bob@example.com>\nwrote:\n\
+            > Lunch tomorrow?\n>\n>> Earlier thread\n\nInline answer.\n> more quote\n";
+        let sections = body_sections(body);
+        let kinds: Vec<_> = sections.iter().map(|s| s.kind).collect();
+        assert_eq!(
+            kinds,
+            [
+                BodySectionKind::Content,
+                BodySectionKind::Signature,
+                BodySectionKind::Quote,
+                BodySectionKind::Content,
+                BodySectionKind::Quote,
+            ]
+        );
+        assert_eq!(sections[0].text, "Sounds good, see you then.");
+        assert_eq!(sections[1].text, "Ann\nExample Corp");
+        assert_eq!(
+            sections[2].attribution.as_deref(),
+            Some("On Mon, 5 Oct 2026 at 10:00, Bob <bob@example.com>\nwrote:")
+        );
+        assert_eq!(sections[2].text, "> Lunch tomorrow?\n>\n>> Earlier thread");
+        assert_eq!(sections[3].text, "Inline answer.");
+
+        let outlook = "Approved.\n\n________________________________\n\
+            **From:** Bob Smith\n**Sent:** Monday, October 5, 2026 10:00 AM\n\
+            **To:** Ann\n**Subject:** Budget\n\nPlease approve the budget.\n";
+        let sections = body_sections(outlook);
+        assert_eq!(sections.len(), 2);
+        assert_eq!(sections[1].kind, BodySectionKind::Quote);
+        assert!(sections[1]
+            .attribution
+            .as_deref()
+            .is_some_and(|a| a.contains("**Sent:**")));
+        assert_eq!(sections[1].text, "Please approve the budget.");
+
+        let plain = "Just a note.\nFrom: my desk\nNothing quoted here.";
+        assert_eq!(body_sections(plain).len(), 1);
+
+        // No separator: the blank line before the header block isn't part
+        // of the attribution.
+        let bare = "Approved.\n\nFrom: Bob Smith\nSent: Monday\nTo: Ann\n\nPlease approve.\n";
+        let sections = body_sections(bare);
+        assert_eq!(sections.len(), 2);
+        assert_eq!(
+            sections[1].attribution.as_deref(),
+            Some("From: Bob Smith\nSent: Monday\nTo: Ann")
+        );
+
+        // An attribution followed by the original's headers folds the rest.
+        let forwarded = "FYI.\n\nOn Mon, Bob wrote:\n\nFrom: Bob Smith\nDate: Monday\n\
+            Subject: Budget\n\nPlease approve.\n";
+        let sections = body_sections(forwarded);
+        assert_eq!(sections.len(), 2);
+        assert_eq!(sections[1].kind, BodySectionKind::Quote);
+        assert!(sections[1].text.ends_with("Please approve."));
+    }
+
+    #[test]
+    fn sentences_that_read_like_attributions_stay_content() {
+        let body = "On second thought, here is what Bob wrote:\n\n\
+            Let's ship on Friday instead.\nThanks.\n";
+        let sections = body_sections(body);
+        assert_eq!(sections.len(), 1);
+        assert_eq!(sections[0].kind, BodySectionKind::Content);
+        assert!(sections[0].attribution.is_none());
+        assert!(sections[0].text.ends_with("Thanks."));
+    }
+
+    // ── format=flowed ────────────────────────────────────────────
+
+    #[test]
+    fn flowed_text_joins_soft_breaks_per_quote_depth() {
+        let flowed = "This paragraph was \r\nwrapped by the \r\nsender.\r\n\
+            > Quoted and \r\n> wrapped.\r\n>> Deeper \r\n> back out\r\n \
+            >From stuffed\r\n-- \r\nAnn\r\n";
+        assert_eq!(
+            decode_flowed(flowed, false),
+            "This paragraph was wrapped by the sender.\n\
+             > Quoted and wrapped.\n>> Deeper\n> back out\n>From stuffed\n-- \nAnn\n"
+        );
+        assert_eq!(
+            decode_flowed("Kon\r\nnichi \r\nwa\r\n", true),
+            "Kon\nnichiwa\n"
+        );
+        // The signature separator ends a soft-broken paragraph.
+        assert_eq!(
+            decode_flowed("Cheers, \r\n-- \r\nAnn\r\n", false),
+            "Cheers,\n-- \nAnn\n"
+        );
+        assert_eq!(
+            decode_flowed("Cheers, \r\n-- \r\nAnn\r\n", true),
+            "Cheers,\n-- \nAnn\n"
+        );
+
+        let long = format!(
+            "{}\n> {}\nFrom here on\n  indented\n-- \nAnn  \n",
+            "word ".repeat(40),
+            "quoted ".repeat(20)
+        );
+        let encoded = encode_flowed(&long);
+        assert!(encoded.lines().all(|line| line.len() <= 78), "{encoded}");
+        assert!(encoded.contains("\r\n From here on\r\n   indented\r\n-- \r\nAnn\r\n"));
+        assert_eq!(
+            decode_flowed(&encoded, false),
+            format!(
+                "{}\n> {}\nFrom here on\n  indented\n-- \nAnn\n",
+                "word ".repeat(40).trim_end(),
+                "quoted ".repeat(20).trim_end()
+            )
+        );
+    }
+
+    // ── remote content policy ────────────────────────────────────
+
+    #[test]
+    fn remote_images_need_the_policy_to_allow_them() {
+        let html = r#"<p>This week's issue</p><img src="https://cdn.news.example.com/hero image.png" alt="Hero">"#;
+        let plain = "This week's issue, with enough text to count as real content.\n\nRead on.";
+        let blocked =
+            render_body_markdown_inline(Some(plain), Some(html), &[], RemoteContent::Block);
+        assert_eq!(blocked, plain);
+        let allowed =
+            render_body_markdown_inline(Some(plain), Some(html), &[], RemoteContent::Allow);
+        assert!(
+            allowed.contains("![Hero](https://cdn.news.example.com/hero%20image.png)"),
+            "{allowed}"
+        );
+
+        let policy = RemoteContentPolicy::new(vec![
+            RemoteContentRule::domain("@Example.com"),
+            RemoteContentRule::sender("friend@other.org"),
+        ]);
+        let from = |email: &str| MessageSummary {
+            addresses: crate::models::MessageAddresses {
+                from: crate::models::EmailAddress::parse_list(email).expect("from"),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert_eq!(
+            policy.for_message(&from("news@mail.example.com")),
+            RemoteContent::Allow
+        );
+        assert_eq!(
+            policy.for_message(&from("Friend@Other.org")),
+            RemoteContent::Allow
+        );
+        assert_eq!(
+            policy.for_message(&from("x@notexample.com")),
+            RemoteContent::Block
+        );
+        assert_eq!(
+            policy.for_message(&from("stranger@other.org")),
+            RemoteContent::Block
+        );
+        assert_eq!(
+            RemoteContentRule::from_parts("domain", "example.com"),
+            Some(RemoteContentRule::domain("example.com"))
+        );
+    }
 
     // ── render_body (plain text output) ──────────────────────────
 
@@ -138,6 +965,20 @@ mod tests {
         assert!(!result.contains("alert"));
     }
 
+    // ── markdown_to_html (composition) ───────────────────────────
+
+    #[test]
+    fn composed_markdown_is_sanitized_html() {
+        let html = markdown_to_html(
+            "Hi **team**,\n\n![chart](cid:chart@local)\n\n<script>alert(1)</script>\n\n| a |\n|---|\n| 1 |\n",
+        );
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains("<strong>team</strong>"));
+        assert!(html.contains(r#"src="cid:chart@local""#));
+        assert!(html.contains("<table>"));
+        assert!(!html.contains("<script>"));
+    }
+
     // ── Real-world fixture: 1Password invoice ────────────────────
     //
     // Marketing HTML with nested layout tables, MSO conditionals,
@@ -198,4 +1039,57 @@ mod tests {
             result.len()
         );
     }
+
+    // ── raw source / .eml export ─────────────────────────────────
+
+    const RAW: &[u8] = b"Received: from mx.example.com\r\n\tby mail.example.net; Mon, 5 Jan 2026\r\nSubject: Hi\r\n\r\nBody: not a header\r\n";
+
+    #[test]
+    fn source_headers_unfold_and_stop_at_body() {
+        assert_eq!(
+            source_headers(RAW),
+            vec![
+                (
+                    "Received".to_string(),
+                    "from mx.example.com by mail.example.net; Mon, 5 Jan 2026".to_string()
+                ),
+                ("Subject".to_string(), "Hi".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn eml_file_name_is_dated_and_safe() {
+        let message = MessageSummary {
+            subject: "Re: invoice 1/2: \"final\"".into(),
+            timestamp: 1_767_607_200, // 2026-01-05 10:00 UTC
+            ..Default::default()
+        };
+        assert_eq!(
+            eml_file_name(&message),
+            "2026-01-05 Re_ invoice 1_2_ _final_.eml"
+        );
+
+        let bare = MessageSummary {
+            envelope_hash: 7,
+            ..Default::default()
+        };
+        assert_eq!(eml_file_name(&bare), "message-7.eml");
+    }
+
+    #[test]
+    fn export_eml_writes_bytes_without_overwriting() {
+        let dir = std::env::temp_dir().join(format!("nlm-eml-{}", uuid::Uuid::new_v4()));
+        let message = MessageSummary {
+            subject: "Hi".into(),
+            timestamp: 1_767_607_200,
+            ..Default::default()
+        };
+        let first = export_eml(&dir, &message, RAW).expect("first export");
+        let second = export_eml(&dir, &message, b"other").expect("second export");
+        assert_eq!(first, dir.join("2026-01-05 Hi.eml"));
+        assert_eq!(second, dir.join("2026-01-05 Hi (2).eml"));
+        assert_eq!(std::fs::read(&first).expect("read back"), RAW);
+        std::fs::remove_dir_all(&dir).expect("cleanup");
+    }
 }
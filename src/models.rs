@@ -1,5 +1,7 @@
 use serde::{Deserialize, Serialize};
 
+use crate::smtp::OutgoingEmail;
+
 /// A mail folder (IMAP mailbox).
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Folder {
@@ -8,35 +10,449 @@ pub struct Folder {
     pub unread_count: u32,
     pub total_count: u32,
     pub mailbox_hash: u64,
+    pub is_subscribed: bool,
+    /// Special-use role, from RFC 6154 attributes or a name heuristic.
+    pub role: Option<FolderRole>,
+}
+
+/// What a folder is for, independent of what the provider calls it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FolderRole {
+    Inbox,
+    Drafts,
+    Sent,
+    Archive,
+    Flagged,
+    Junk,
+    Trash,
+}
+
+impl FolderRole {
+    /// Stable string form used in the cache.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Inbox => "inbox",
+            Self::Drafts => "drafts",
+            Self::Sent => "sent",
+            Self::Archive => "archive",
+            Self::Flagged => "flagged",
+            Self::Junk => "junk",
+            Self::Trash => "trash",
+        }
+    }
+
+    /// Inverse of [`as_str`](Self::as_str).
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "inbox" => Some(Self::Inbox),
+            "drafts" => Some(Self::Drafts),
+            "sent" => Some(Self::Sent),
+            "archive" => Some(Self::Archive),
+            "flagged" => Some(Self::Flagged),
+            "junk" => Some(Self::Junk),
+            "trash" => Some(Self::Trash),
+            _ => None,
+        }
+    }
+
+    /// Guess a role from a folder path for servers without SPECIAL-USE.
+    /// Looks at the last path component, so "[Gmail]/Trash" and
+    /// "INBOX.Sent Items" both match.
+    pub fn from_name(path: &str) -> Option<Self> {
+        if path.eq_ignore_ascii_case("INBOX") {
+            return Some(Self::Inbox);
+        }
+        let leaf = path
+            .rsplit(['/', '.'])
+            .next()
+            .unwrap_or(path)
+            .trim()
+            .to_ascii_lowercase();
+        match leaf.as_str() {
+            "drafts" | "draft" => Some(Self::Drafts),
+            "sent" | "sent items" | "sent mail" | "sent messages" => Some(Self::Sent),
+            "archive" | "archives" | "all mail" => Some(Self::Archive),
+            "starred" | "flagged" => Some(Self::Flagged),
+            "junk" | "spam" | "junk e-mail" | "junk email" | "bulk mail" => Some(Self::Junk),
+            "trash" | "bin" | "deleted" | "deleted items" | "deleted messages" => Some(Self::Trash),
+            _ => None,
+        }
+    }
+}
+
+/// Fill in roles the server didn't advertise, using [`FolderRole::from_name`].
+/// A role already claimed by some folder is never assigned a second time.
+pub fn apply_role_heuristics(folders: &mut [Folder]) {
+    let mut claimed: Vec<FolderRole> = folders.iter().filter_map(|f| f.role).collect();
+    let mut order: Vec<usize> = (0..folders.len()).collect();
+    order.sort_by(|&a, &b| folders[a].path.cmp(&folders[b].path));
+    for i in order {
+        if folders[i].role.is_some() {
+            continue;
+        }
+        if let Some(role) = FolderRole::from_name(&folders[i].path) {
+            if !claimed.contains(&role) {
+                claimed.push(role);
+                folders[i].role = Some(role);
+            }
+        }
+    }
+}
+
+/// Find the folder with a given role, e.g. where "move to trash" should go.
+pub fn folder_for_role(folders: &[Folder], role: FolderRole) -> Option<&Folder> {
+    folders.iter().find(|f| f.role == Some(role))
+}
+
+/// Sort folders for display: role folders first (Inbox, Drafts, Sent, ...),
+/// then everything else alphabetically by path.
+pub fn sort_folders(folders: &mut [Folder]) {
+    folders.sort_by(|a, b| {
+        let rank = |f: &Folder| f.role.map_or(u8::MAX, |r| r as u8);
+        rank(a).cmp(&rank(b)).then_with(|| a.path.cmp(&b.path))
+    });
+}
+
+/// One mailbox from an address header.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct EmailAddress {
+    pub name: Option<String>,
+    pub email: String,
+}
+
+impl std::fmt::Display for EmailAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "{} <{}>", name, self.email),
+            None => f.write_str(&self.email),
+        }
+    }
+}
+
+impl EmailAddress {
+    /// Parse an RFC 5322 address list as typed into a To/Cc/Bcc field:
+    /// `"Doe, Jane" <jane@x.org>, bob@y.org`. Commas inside quotes, angle
+    /// brackets and comments don't split, groups (`team: a@x, b@x;`) are
+    /// flattened, and a trailing `(comment)` names a bare address.
+    pub fn parse_list(input: &str) -> Result<Vec<Self>, String> {
+        let mut items = Vec::new();
+        let mut current = String::new();
+        let mut chars = input.chars();
+        let (mut quoted, mut comment, mut angle) = (false, 0u32, false);
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' if quoted || comment > 0 => {
+                    current.push(c);
+                    current.extend(chars.next());
+                    continue;
+                }
+                '"' if comment == 0 => quoted = !quoted,
+                '(' if !quoted => comment += 1,
+                ')' if !quoted && comment > 0 => comment -= 1,
+                '<' if !quoted && comment == 0 => angle = true,
+                '>' if !quoted && comment == 0 => angle = false,
+                ',' | ';' if !quoted && comment == 0 && !angle => {
+                    items.push(std::mem::take(&mut current));
+                    continue;
+                }
+                // A group's display name; its members follow.
+                ':' if !quoted && comment == 0 && !angle => {
+                    current.clear();
+                    continue;
+                }
+                _ => {}
+            }
+            current.push(c);
+        }
+        if quoted || comment > 0 || angle {
+            return Err(format!(
+                "Unbalanced quotes or brackets in '{}'",
+                input.trim()
+            ));
+        }
+        items.push(current);
+
+        items
+            .iter()
+            .map(|item| item.trim())
+            .filter(|item| !item.is_empty())
+            .map(Self::parse_mailbox)
+            .collect()
+    }
+
+    fn parse_mailbox(item: &str) -> Result<Self, String> {
+        let (phrase, comments) = strip_comments(item);
+        let (name, email) = match (phrase.find('<'), phrase.rfind('>')) {
+            (Some(open), Some(close)) if open < close => (
+                unquote(&phrase[..open]),
+                phrase[open + 1..close].trim().to_string(),
+            ),
+            _ => (None, phrase.trim().to_string()),
+        };
+        let valid = match email.rsplit_once('@') {
+            Some((local, domain)) => {
+                !local.is_empty()
+                    && !domain.is_empty()
+                    && !email.contains(char::is_whitespace)
+                    && !domain.contains(['"', '<', '>'])
+            }
+            None => false,
+        };
+        if !valid {
+            return Err(format!("Invalid address '{item}'"));
+        }
+        let name = name.or_else(|| unquote(&comments));
+        Ok(EmailAddress { name, email })
+    }
+}
+
+/// Split `(comments)` out of an address, returning the rest and the
+/// comment text.
+fn strip_comments(item: &str) -> (String, String) {
+    let (mut rest, mut comments) = (String::new(), String::new());
+    let (mut quoted, mut depth) = (false, 0u32);
+    let mut chars = item.chars();
+    while let Some(c) = chars.next() {
+        let target = if depth > 0 { &mut comments } else { &mut rest };
+        match c {
+            '\\' if quoted || depth > 0 => {
+                target.push(c);
+                target.extend(chars.next());
+            }
+            '"' if depth == 0 => {
+                quoted = !quoted;
+                target.push(c);
+            }
+            '(' if !quoted => {
+                if depth > 0 {
+                    target.push(c);
+                } else if !comments.is_empty() {
+                    comments.push(' ');
+                }
+                depth += 1;
+            }
+            ')' if !quoted && depth > 0 => {
+                depth -= 1;
+                if depth > 0 {
+                    comments.push(c);
+                }
+            }
+            _ => target.push(c),
+        }
+    }
+    (rest, comments)
+}
+
+/// A display name without its quotes and escapes; `None` when empty.
+fn unquote(phrase: &str) -> Option<String> {
+    let phrase = phrase.trim();
+    let phrase = phrase
+        .strip_prefix('"')
+        .and_then(|p| p.strip_suffix('"'))
+        .unwrap_or(phrase);
+    let mut out = String::with_capacity(phrase.len());
+    let mut chars = phrase.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => out.extend(chars.next()),
+            _ => out.push(c),
+        }
+    }
+    let out = out.trim();
+    (!out.is_empty()).then(|| out.to_string())
+}
+
+/// A message's address headers. Groups are flattened into their members.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MessageAddresses {
+    pub from: Vec<EmailAddress>,
+    pub to: Vec<EmailAddress>,
+    pub cc: Vec<EmailAddress>,
+    pub bcc: Vec<EmailAddress>,
+    pub reply_to: Vec<EmailAddress>,
+    pub sender: Vec<EmailAddress>,
 }
 
 /// Summary of a message for the list view (no body).
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct MessageSummary {
     pub uid: u64,
     pub subject: String,
+    /// Display form of `addresses.from`, for list views.
     pub from: String,
+    /// Display form of `addresses.to`, for list views.
     pub to: String,
+    pub addresses: MessageAddresses,
     pub date: String,
     pub is_read: bool,
     pub is_starred: bool,
+    pub is_answered: bool,
+    pub is_draft: bool,
+    /// Marked `\Deleted`, awaiting expunge.
+    pub is_deleted: bool,
+    /// Has the `$Forwarded` keyword.
+    pub is_forwarded: bool,
+    /// Other IMAP keywords, e.g. `$Label1` or `$Junk`.
+    pub keywords: Vec<String>,
     pub has_attachments: bool,
+    /// Conversation this message belongs to. Provisional until
+    /// [`CacheHandle::rethread`](crate::store::CacheHandle::rethread) runs.
     pub thread_id: Option<u64>,
+    /// Envelope hash of the nearest cached ancestor in the conversation.
+    pub thread_parent: Option<u64>,
     pub envelope_hash: u64,
     pub timestamp: i64,
     pub mailbox_hash: u64,
     pub message_id: String,
     pub in_reply_to: Option<String>,
+    /// Message-IDs from the References header, oldest first.
+    pub references: Vec<String>,
     pub reply_to: Option<String>,
     pub thread_depth: u32,
 }
 
-/// Decoded attachment data for display and saving.
+/// Per-mailbox IMAP sync checkpoint, persisted by the cache between syncs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MailboxSyncState {
+    pub uidvalidity: u64,
+    pub uidnext: u64,
+    /// Only present when the server supports CONDSTORE.
+    pub highestmodseq: Option<u64>,
+}
+
+/// Changes to apply to the cached copy of one mailbox, produced by
+/// [`ImapSession::sync_mailbox`](crate::imap::ImapSession::sync_mailbox).
 #[derive(Debug, Clone)]
+pub struct MailboxDelta {
+    /// Server state this delta brings the cache up to.
+    pub state: MailboxSyncState,
+    /// No usable prior state (first sync or UIDVALIDITY changed): cached
+    /// messages are dropped and `new_messages` is the whole mailbox.
+    pub reset: bool,
+    pub new_messages: Vec<MessageSummary>,
+    /// `(envelope_hash, flags_server, keywords_server)` for existing messages
+    /// whose flags changed.
+    pub flag_updates: Vec<(u64, u8, Vec<String>)>,
+    /// Every envelope hash still on the server, when known. Cached messages
+    /// missing from this list were expunged.
+    pub present: Option<Vec<u64>>,
+}
+
+/// Where the next call to
+/// [`ImapSession::fetch_messages_page`](crate::imap::ImapSession::fetch_messages_page)
+/// should resume: everything older than (below) `before_uid`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FetchCursor {
+    /// A cursor is only valid while the mailbox keeps this UIDVALIDITY.
+    pub uidvalidity: u64,
+    pub before_uid: u64,
+    /// Which paged fetch this cursor continues, so the session can reuse
+    /// the UID list from its first page. Each pager has its own.
+    #[serde(default)]
+    pub snapshot: u64,
+}
+
+/// One window of a mailbox, newest first.
+#[derive(Debug, Clone)]
+pub struct MessagePage {
+    pub messages: Vec<MessageSummary>,
+    /// `None` once the oldest message has been fetched.
+    pub next: Option<FetchCursor>,
+}
+
+/// Health of an [`ImapSession`](crate::imap::ImapSession)'s server
+/// connection, observed via
+/// [`ImapSession::connection_state`](crate::imap::ImapSession::connection_state).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnectionState {
+    Connecting,
+    Online,
+    /// `attempt` consecutive attempts have failed; the next starts after `retry_in`.
+    BackingOff {
+        attempt: u32,
+        retry_in: std::time::Duration,
+    },
+    /// The server rejected the credentials. Not retried until
+    /// [`ImapSession::reconnect`](crate::imap::ImapSession::reconnect) is called.
+    AuthFailed {
+        reason: String,
+    },
+}
+
+/// A change pushed by the server, delivered by
+/// [`ImapSession::watch`](crate::imap::ImapSession::watch).
+#[derive(Debug, Clone)]
+#[allow(clippy::large_enum_variant)]
+pub enum MailEvent {
+    NewMessage(MessageSummary),
+    FlagsChanged {
+        mailbox_hash: u64,
+        envelope_hash: u64,
+        is_read: bool,
+        is_starred: bool,
+        is_answered: bool,
+        is_draft: bool,
+        is_deleted: bool,
+        is_forwarded: bool,
+        keywords: Vec<String>,
+    },
+    MessageRemoved {
+        mailbox_hash: u64,
+        envelope_hash: u64,
+    },
+    MailboxCountsChanged {
+        mailbox_hash: u64,
+        unread_count: u32,
+        total_count: u32,
+    },
+    /// The server changed a mailbox in a way that can't be applied
+    /// piecemeal; run a sync for it.
+    MailboxNeedsResync {
+        mailbox_hash: u64,
+    },
+    /// Folders were created, deleted, renamed or (un)subscribed elsewhere.
+    FoldersChanged,
+    ConnectionLost {
+        reason: String,
+    },
+    ConnectionRestored,
+}
+
+/// An attachment as listed by `BODYSTRUCTURE`, before its bytes are
+/// downloaded. Fetch them with `ImapSession::fetch_attachment`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AttachmentInfo {
+    /// IMAP section number of the MIME part, e.g. `2` or `1.3`.
+    pub part: String,
+    pub filename: String,
+    pub mime_type: String,
+    /// Encoded size in octets, as reported by the server.
+    pub size: u64,
+    /// Content-ID without angle brackets, as used in `cid:` URLs.
+    pub content_id: Option<String>,
+    /// Meant to be shown in the body rather than listed as a download.
+    pub is_inline: bool,
+    /// Content-Transfer-Encoding of the part, lowercase.
+    pub encoding: String,
+}
+
+impl AttachmentInfo {
+    pub fn is_image(&self) -> bool {
+        self.mime_type.to_ascii_lowercase().starts_with("image/")
+    }
+}
+
+/// Decoded attachment data for display and saving.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AttachmentData {
     pub filename: String,
     pub mime_type: String,
+    #[serde(with = "base64_bytes")]
     pub data: Vec<u8>,
+    /// Content-ID without angle brackets, as used in `cid:` URLs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_id: Option<String>,
 }
 
 impl AttachmentData {
@@ -44,3 +460,198 @@ impl AttachmentData {
         self.mime_type.to_ascii_lowercase().starts_with("image/")
     }
 }
+
+/// Where a queued message stands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutboxStatus {
+    /// Held until `send_at`: inside the undo window or scheduled for later.
+    Scheduled,
+    /// Waiting for its next attempt.
+    Queued,
+    /// Being handed to the SMTP server.
+    Sending,
+    /// Accepted by the server; the copy for the Sent folder is still pending.
+    Appending,
+    /// Rejected for good. Kept until requeued or removed.
+    Failed,
+    /// Accepted and filed. Only ever reported: the entry is gone by then.
+    Sent,
+}
+
+impl OutboxStatus {
+    /// Stable string form used in the cache.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Scheduled => "scheduled",
+            Self::Queued => "queued",
+            Self::Sending => "sending",
+            Self::Appending => "appending",
+            Self::Failed => "failed",
+            Self::Sent => "sent",
+        }
+    }
+
+    /// Inverse of [`as_str`](Self::as_str).
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "scheduled" => Some(Self::Scheduled),
+            "queued" => Some(Self::Queued),
+            "sending" => Some(Self::Sending),
+            "appending" => Some(Self::Appending),
+            "failed" => Some(Self::Failed),
+            "sent" => Some(Self::Sent),
+            _ => None,
+        }
+    }
+}
+
+/// A message in the outbox.
+#[derive(Debug, Clone)]
+pub struct OutboxEntry {
+    pub id: i64,
+    pub account_id: String,
+    pub email: OutgoingEmail,
+    pub status: OutboxStatus,
+    /// Failed attempts at the current step (sending, then filing in Sent).
+    pub attempts: u32,
+    pub last_error: Option<String>,
+    /// Unix seconds.
+    pub next_attempt_at: i64,
+    /// Unix seconds.
+    pub created_at: i64,
+    /// When the message may first go out (unix seconds): the end of the
+    /// undo window or the scheduled time; `created_at` otherwise.
+    pub send_at: i64,
+    pub append_to_sent: bool,
+    /// The bytes the server accepted, once it has.
+    pub sent: Option<Vec<u8>>,
+}
+
+/// Serde adapter writing bytes as base64, so queued mail with attachments
+/// stays compact as JSON.
+pub(crate) mod base64_bytes {
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&STANDARD.encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        STANDARD.decode(text).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        apply_role_heuristics, folder_for_role, sort_folders, EmailAddress, Folder, FolderRole,
+    };
+
+    fn folder(path: &str, role: Option<FolderRole>) -> Folder {
+        Folder {
+            name: path.rsplit('/').next().unwrap_or(path).to_string(),
+            path: path.to_string(),
+            unread_count: 0,
+            total_count: 0,
+            mailbox_hash: 0,
+            is_subscribed: true,
+            role,
+        }
+    }
+
+    #[test]
+    fn role_heuristics_match_common_provider_names() {
+        assert_eq!(FolderRole::from_name("INBOX"), Some(FolderRole::Inbox));
+        assert_eq!(FolderRole::from_name("Sent Items"), Some(FolderRole::Sent));
+        assert_eq!(
+            FolderRole::from_name("[Gmail]/Trash"),
+            Some(FolderRole::Trash)
+        );
+        assert_eq!(
+            FolderRole::from_name("INBOX.Junk E-mail"),
+            Some(FolderRole::Junk)
+        );
+        assert_eq!(
+            FolderRole::from_name("Deleted Items"),
+            Some(FolderRole::Trash)
+        );
+        assert_eq!(FolderRole::from_name("Receipts"), None);
+    }
+
+    #[test]
+    fn server_roles_win_over_name_heuristics() {
+        let mut folders = vec![
+            folder("INBOX", Some(FolderRole::Inbox)),
+            folder("Sent", None),
+            folder("[Gmail]/Sent Mail", Some(FolderRole::Sent)),
+            folder("Trash", None),
+        ];
+        apply_role_heuristics(&mut folders);
+        assert_eq!(folders[1].role, None);
+        assert_eq!(folders[3].role, Some(FolderRole::Trash));
+        let sent = folder_for_role(&folders, FolderRole::Sent).expect("sent folder");
+        assert_eq!(sent.path, "[Gmail]/Sent Mail");
+    }
+
+    #[test]
+    fn sort_puts_role_folders_first() {
+        let mut folders = vec![
+            folder("Receipts", None),
+            folder("Trash", Some(FolderRole::Trash)),
+            folder("Archive/2024", None),
+            folder("INBOX", Some(FolderRole::Inbox)),
+            folder("Sent", Some(FolderRole::Sent)),
+        ];
+        sort_folders(&mut folders);
+        let paths: Vec<&str> = folders.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            vec!["INBOX", "Sent", "Trash", "Archive/2024", "Receipts"]
+        );
+    }
+
+    #[test]
+    fn role_string_round_trips() {
+        for role in [
+            FolderRole::Inbox,
+            FolderRole::Drafts,
+            FolderRole::Sent,
+            FolderRole::Archive,
+            FolderRole::Flagged,
+            FolderRole::Junk,
+            FolderRole::Trash,
+        ] {
+            assert_eq!(FolderRole::parse(role.as_str()), Some(role));
+        }
+    }
+
+    #[test]
+    fn address_lists_split_only_on_top_level_commas() {
+        let parsed = EmailAddress::parse_list(
+            r#""Doe, Jane" <jane@x.org>, bob@y.org (Bob, at work),
+               team: a@z.org, "Quote \"Q\"" <q@z.org>; <bare@w.org>,"#,
+        )
+        .expect("parse");
+        let addr = |name: Option<&str>, email: &str| EmailAddress {
+            name: name.map(str::to_string),
+            email: email.to_string(),
+        };
+        assert_eq!(
+            parsed,
+            vec![
+                addr(Some("Doe, Jane"), "jane@x.org"),
+                addr(Some("Bob, at work"), "bob@y.org"),
+                addr(None, "a@z.org"),
+                addr(Some("Quote \"Q\""), "q@z.org"),
+                addr(None, "bare@w.org"),
+            ]
+        );
+        assert_eq!(EmailAddress::parse_list("  ").expect("empty"), vec![]);
+        assert!(EmailAddress::parse_list("jane@x.org, not an address").is_err());
+        assert!(EmailAddress::parse_list("\"Doe, Jane <jane@x.org>").is_err());
+    }
+}
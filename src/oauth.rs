@@ -0,0 +1,636 @@
+//! OAuth2 sign-in for providers that no longer take passwords.
+//!
+//! [`authorize`] runs the authorization-code flow with PKCE (RFC 7636)
+//! through a loopback redirect (RFC 8252 §7.3) and keeps the refresh token
+//! in the keyring. Connections then get access tokens from a
+//! [`TokenSource`], which refreshes them shortly before they expire.
+//! melib and lettre both authenticate with XOAUTH2; neither has OAUTHBEARER.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, SystemTime};
+
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use url::Url;
+
+use crate::config::OAuth2Settings;
+use crate::keyring;
+
+/// How long [`authorize`] waits for the browser to come back.
+const AUTHORIZE_TIMEOUT: Duration = Duration::from_secs(300);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+/// Refresh access tokens this long before they expire.
+const EXPIRY_MARGIN: Duration = Duration::from_secs(120);
+/// Assumed lifetime when the token endpoint doesn't say.
+const DEFAULT_LIFETIME: Duration = Duration::from_secs(3600);
+
+/// A token endpoint response (RFC 6749 §5.1).
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct TokenResponse {
+    pub access_token: String,
+    #[serde(default)]
+    pub refresh_token: Option<String>,
+    /// Lifetime in seconds.
+    #[serde(default)]
+    pub expires_in: Option<u64>,
+}
+
+/// Why no access token could be had.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TokenError {
+    /// The provider refused the grant, or there is no refresh token: the
+    /// user has to sign in again with [`authorize`].
+    Rejected(String),
+    /// Network, TLS or a malformed response; worth retrying.
+    Failed(String),
+}
+
+impl std::fmt::Display for TokenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TokenError::Rejected(message) | TokenError::Failed(message) => f.write_str(message),
+        }
+    }
+}
+
+/// A PKCE code verifier and its S256 challenge.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pkce {
+    pub verifier: String,
+    pub challenge: String,
+}
+
+impl Pkce {
+    pub fn new() -> Result<Self, String> {
+        Ok(Self::from_verifier(random_token()?))
+    }
+
+    pub fn from_verifier(verifier: String) -> Self {
+        let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()));
+        Pkce {
+            verifier,
+            challenge,
+        }
+    }
+}
+
+/// 32 random bytes, base64url: 43 characters, the shortest verifier RFC
+/// 7636 allows.
+fn random_token() -> Result<String, String> {
+    let mut bytes = [0u8; 32];
+    getrandom::getrandom(&mut bytes).map_err(|e| format!("No randomness available: {e}"))?;
+    Ok(URL_SAFE_NO_PAD.encode(bytes))
+}
+
+/// The SASL XOAUTH2 initial response, before base64.
+pub fn xoauth2(username: &str, access_token: &str) -> String {
+    format!("user={username}\x01auth=Bearer {access_token}\x01\x01")
+}
+
+/// Sign `username` in through the system browser and keep the refresh token
+/// in the keyring.
+pub async fn authorize(settings: &OAuth2Settings, username: &str) -> Result<(), String> {
+    let flow = LoopbackAuthorization::start(settings, Some(username)).await?;
+    log::info!("Opening browser for OAuth2 sign-in: {}", flow.url());
+    if let Err(e) = open::that(flow.url()) {
+        log::warn!("Failed to open browser, visit {} manually: {e}", flow.url());
+    }
+    let tokens = tokio::time::timeout(AUTHORIZE_TIMEOUT, flow.finish())
+        .await
+        .map_err(|_| "Timed out waiting for OAuth2 sign-in".to_string())??;
+    let refresh_token = tokens
+        .refresh_token
+        .as_deref()
+        .ok_or("The provider issued no refresh token")?;
+    keyring::set_refresh_token(username, &settings.token_url, refresh_token)?;
+    TokenSource::new(settings.clone(), username).remember(&tokens);
+    Ok(())
+}
+
+/// Forget `username`'s refresh token and any cached access token.
+pub fn sign_out(settings: &OAuth2Settings, username: &str) -> Result<(), String> {
+    let source = TokenSource::new(settings.clone(), username);
+    cache().lock().unwrap().remove(&source.key());
+    keyring::delete_refresh_token(username, &settings.token_url)
+}
+
+/// An authorization in progress: the URL to send the browser to, and a
+/// loopback listener waiting for the redirect back.
+pub struct LoopbackAuthorization {
+    settings: OAuth2Settings,
+    listener: TcpListener,
+    redirect_uri: String,
+    state: String,
+    pkce: Pkce,
+    url: String,
+}
+
+impl LoopbackAuthorization {
+    /// Listen on a free loopback port and build the authorization URL.
+    /// `login_hint` pre-selects the account at providers that support it.
+    pub async fn start(
+        settings: &OAuth2Settings,
+        login_hint: Option<&str>,
+    ) -> Result<Self, String> {
+        let listener = TcpListener::bind(("127.0.0.1", 0))
+            .await
+            .map_err(|e| format!("Failed to listen for the OAuth2 redirect: {e}"))?;
+        let port = listener
+            .local_addr()
+            .map_err(|e| format!("Failed to listen for the OAuth2 redirect: {e}"))?
+            .port();
+        let redirect_uri = format!("http://127.0.0.1:{port}/");
+        let state = random_token()?;
+        let pkce = Pkce::new()?;
+
+        let mut url = Url::parse(&settings.auth_url)
+            .map_err(|e| format!("Invalid OAuth2 authorization URL: {e}"))?;
+        {
+            let mut query = url.query_pairs_mut();
+            query
+                .append_pair("response_type", "code")
+                .append_pair("client_id", &settings.client_id)
+                .append_pair("redirect_uri", &redirect_uri)
+                .append_pair("state", &state)
+                .append_pair("code_challenge", &pkce.challenge)
+                .append_pair("code_challenge_method", "S256");
+            if !settings.scopes.is_empty() {
+                query.append_pair("scope", &settings.scopes.join(" "));
+            }
+            if let Some(hint) = login_hint {
+                query.append_pair("login_hint", hint);
+            }
+        }
+
+        Ok(LoopbackAuthorization {
+            settings: settings.clone(),
+            listener,
+            redirect_uri,
+            state,
+            pkce,
+            url: url.into(),
+        })
+    }
+
+    /// Where to send the browser.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Wait for the browser to reach the redirect URI, then exchange the
+    /// code for tokens. Requests without a code (e.g. `/favicon.ico`) are
+    /// answered with 404 and ignored; a redirect with the wrong `state` gets
+    /// a 400 and the wait goes on, so a stray request can't cancel sign-in.
+    pub async fn finish(self) -> Result<TokenResponse, String> {
+        let code = loop {
+            let (mut stream, _) = self
+                .listener
+                .accept()
+                .await
+                .map_err(|e| format!("Failed to accept the OAuth2 redirect: {e}"))?;
+            let Some(target) = read_request_target(&mut stream).await else {
+                continue;
+            };
+            let query: HashMap<String, String> = Url::parse(&format!("http://127.0.0.1{target}"))
+                .map(|url| url.query_pairs().into_owned().collect())
+                .unwrap_or_default();
+
+            if !query.contains_key("error") && !query.contains_key("code") {
+                respond(&mut stream, "404 Not Found", "Not found.").await;
+                continue;
+            }
+            if query.get("state") != Some(&self.state) {
+                respond(
+                    &mut stream,
+                    "400 Bad Request",
+                    "OAuth2 redirect carried the wrong state",
+                )
+                .await;
+                continue;
+            }
+            if let Some(error) = query.get("error") {
+                let detail = query
+                    .get("error_description")
+                    .map(|d| format!(": {d}"))
+                    .unwrap_or_default();
+                let error = format!("Sign-in was refused ({error}){detail}");
+                respond(&mut stream, "400 Bad Request", &error).await;
+                return Err(error);
+            }
+            let code = query["code"].clone();
+            respond(
+                &mut stream,
+                "200 OK",
+                "Signed in. You can close this window.",
+            )
+            .await;
+            break code;
+        };
+
+        token_request(
+            &self.settings,
+            &[
+                ("grant_type", "authorization_code"),
+                ("code", &code),
+                ("redirect_uri", &self.redirect_uri),
+                ("code_verifier", &self.pkce.verifier),
+            ],
+        )
+        .await
+        .map_err(|e| e.to_string())
+    }
+}
+
+/// The request target of one HTTP request, e.g. `/?code=...&state=...`.
+async fn read_request_target(stream: &mut TcpStream) -> Option<String> {
+    let mut request = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") && request.len() < 16 * 1024 {
+        let n = tokio::time::timeout(REQUEST_TIMEOUT, stream.read(&mut buf))
+            .await
+            .ok()?
+            .ok()?;
+        if n == 0 {
+            break;
+        }
+        request.extend_from_slice(&buf[..n]);
+    }
+    let line = String::from_utf8_lossy(&request);
+    let mut words = line.lines().next()?.split(' ');
+    match (words.next(), words.next()) {
+        (Some("GET"), Some(target)) if target.starts_with('/') => Some(target.to_string()),
+        _ => None,
+    }
+}
+
+async fn respond(stream: &mut TcpStream, status: &str, message: &str) {
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain; charset=utf-8\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n{message}",
+        message.len()
+    );
+    let _ = stream.write_all(response.as_bytes()).await;
+    let _ = stream.shutdown().await;
+}
+
+/// Trade a refresh token for a fresh access token.
+pub async fn refresh(
+    settings: &OAuth2Settings,
+    refresh_token: &str,
+) -> Result<TokenResponse, TokenError> {
+    token_request(
+        settings,
+        &[
+            ("grant_type", "refresh_token"),
+            ("refresh_token", refresh_token),
+        ],
+    )
+    .await
+}
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+    error: String,
+    #[serde(default)]
+    error_description: Option<String>,
+}
+
+/// POST `form` (plus the client credentials) to the token endpoint.
+async fn token_request(
+    settings: &OAuth2Settings,
+    form: &[(&str, &str)],
+) -> Result<TokenResponse, TokenError> {
+    let mut form: Vec<(String, String)> = form
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+    form.push(("client_id".into(), settings.client_id.clone()));
+    if let Some(secret) = &settings.client_secret {
+        form.push(("client_secret".into(), secret.clone()));
+    }
+    let url = settings.token_url.clone();
+    tokio::task::spawn_blocking(move || post_form(&url, &form))
+        .await
+        .map_err(|e| TokenError::Failed(format!("Token request aborted: {e}")))?
+}
+
+fn post_form(url: &str, form: &[(String, String)]) -> Result<TokenResponse, TokenError> {
+    let tls = native_tls::TlsConnector::new()
+        .map_err(|e| TokenError::Failed(format!("TLS setup failed: {e}")))?;
+    let agent = ureq::AgentBuilder::new()
+        .tls_connector(Arc::new(tls))
+        .timeout(REQUEST_TIMEOUT)
+        .build();
+    let form: Vec<(&str, &str)> = form.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+    match agent
+        .post(url)
+        .set("Accept", "application/json")
+        .send_form(&form)
+    {
+        Ok(response) => response
+            .into_json()
+            .map_err(|e| TokenError::Failed(format!("Invalid token response: {e}"))),
+        Err(ureq::Error::Status(status, response)) => {
+            let Ok(error) = response.into_json::<ErrorResponse>() else {
+                return Err(TokenError::Failed(format!(
+                    "Token request failed with HTTP {status}"
+                )));
+            };
+            let message = match error.error_description {
+                Some(description) => {
+                    format!("Token request refused ({}): {description}", error.error)
+                }
+                None => format!("Token request refused ({})", error.error),
+            };
+            // RFC 6749 §5.2: these mean the grant or client is no good.
+            match error.error.as_str() {
+                "invalid_grant" | "invalid_client" | "unauthorized_client" => {
+                    Err(TokenError::Rejected(message))
+                }
+                _ => Err(TokenError::Failed(message)),
+            }
+        }
+        Err(e) => Err(TokenError::Failed(format!("Token request failed: {e}"))),
+    }
+}
+
+/// Cached access tokens and their expiry, by [`TokenSource::key`].
+type Cache = Mutex<HashMap<String, (String, SystemTime)>>;
+
+fn cache() -> &'static Cache {
+    static CACHE: OnceLock<Cache> = OnceLock::new();
+    CACHE.get_or_init(Default::default)
+}
+
+/// Access tokens for one login, refreshed from the keyring's refresh token
+/// when the cached one is about to expire.
+#[derive(Debug, Clone)]
+pub(crate) struct TokenSource {
+    settings: OAuth2Settings,
+    username: String,
+}
+
+impl TokenSource {
+    pub fn new(settings: OAuth2Settings, username: &str) -> Self {
+        TokenSource {
+            settings,
+            username: username.to_string(),
+        }
+    }
+
+    fn key(&self) -> String {
+        format!(
+            "{}@{} ({})",
+            self.username, self.settings.token_url, self.settings.client_id
+        )
+    }
+
+    fn cached(&self) -> Option<String> {
+        let cache = cache().lock().unwrap();
+        let (token, expires_at) = cache.get(&self.key())?;
+        (SystemTime::now() + EXPIRY_MARGIN < *expires_at).then(|| token.clone())
+    }
+
+    fn remember(&self, tokens: &TokenResponse) {
+        let lifetime = tokens
+            .expires_in
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_LIFETIME);
+        cache().lock().unwrap().insert(
+            self.key(),
+            (tokens.access_token.clone(), SystemTime::now() + lifetime),
+        );
+    }
+
+    /// A valid access token, refreshing it if needed.
+    pub async fn access_token(&self) -> Result<String, TokenError> {
+        if let Some(token) = self.cached() {
+            return Ok(token);
+        }
+        // One refresh at a time, so providers that rotate refresh tokens
+        // don't see the old one twice.
+        static REFRESHING: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+        let _guard = REFRESHING.lock().await;
+        if let Some(token) = self.cached() {
+            return Ok(token);
+        }
+
+        let refresh_token = keyring::get_refresh_token(&self.username, &self.settings.token_url)
+            .map_err(|e| {
+                TokenError::Rejected(format!(
+                    "Not signed in with OAuth2 as {}: {e}",
+                    self.username
+                ))
+            })?;
+        let tokens = refresh(&self.settings, &refresh_token).await?;
+        if let Some(rotated) = tokens.refresh_token.as_deref() {
+            if rotated != refresh_token {
+                if let Err(e) =
+                    keyring::set_refresh_token(&self.username, &self.settings.token_url, rotated)
+                {
+                    log::warn!("Failed to store rotated refresh token: {e}");
+                }
+            }
+        }
+        self.remember(&tokens);
+        Ok(tokens.access_token)
+    }
+
+    /// The base64 XOAUTH2 response melib expects as `server_password`.
+    pub async fn xoauth2_base64(&self) -> Result<String, TokenError> {
+        let token = self.access_token().await?;
+        Ok(STANDARD.encode(xoauth2(&self.username, &token)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use url::Url;
+
+    use super::{refresh, xoauth2, LoopbackAuthorization, Pkce, TokenError};
+    use crate::config::OAuth2Settings;
+
+    #[test]
+    fn pkce_challenge_matches_rfc_7636_example() {
+        let pkce = Pkce::from_verifier("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk".into());
+        assert_eq!(
+            pkce.challenge,
+            "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"
+        );
+        assert_eq!(Pkce::new().expect("pkce").verifier.len(), 43);
+        assert_eq!(
+            xoauth2("me@example.com", "tok"),
+            "user=me@example.com\x01auth=Bearer tok\x01\x01"
+        );
+    }
+
+    /// A token endpoint that accepts code `abc` with the verifier for
+    /// `challenge`, and refresh token `rt-1`.
+    fn mock_token_server(challenge: Arc<Mutex<String>>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind");
+        let url = format!("http://{}/token", listener.local_addr().expect("addr"));
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.expect("accept");
+                let mut reader = BufReader::new(stream.try_clone().expect("clone"));
+                let mut length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).expect("read");
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                    if let Some((name, value)) = line.split_once(':') {
+                        if name.eq_ignore_ascii_case("content-length") {
+                            length = value.trim().parse().expect("length");
+                        }
+                    }
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).expect("body");
+                let form: std::collections::HashMap<String, String> =
+                    url::form_urlencoded::parse(&body).into_owned().collect();
+                let field = |name: &str| form.get(name).map(String::as_str);
+
+                let verified = field("code_verifier")
+                    .map(|v| Pkce::from_verifier(v.to_string()).challenge)
+                    == Some(challenge.lock().unwrap().clone());
+                let (status, json) = match (field("grant_type"), field("client_id")) {
+                    (Some("authorization_code"), Some("client"))
+                        if field("code") == Some("abc") && verified =>
+                    {
+                        (
+                            "200 OK",
+                            r#"{"access_token":"at-1","refresh_token":"rt-1","expires_in":3600,"token_type":"Bearer"}"#,
+                        )
+                    }
+                    (Some("refresh_token"), Some("client"))
+                        if field("refresh_token") == Some("rt-1") =>
+                    {
+                        (
+                            "200 OK",
+                            r#"{"access_token":"at-2","expires_in":3600,"token_type":"Bearer"}"#,
+                        )
+                    }
+                    _ => (
+                        "400 Bad Request",
+                        r#"{"error":"invalid_grant","error_description":"bad code"}"#,
+                    ),
+                };
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{json}",
+                    json.len()
+                );
+            }
+        });
+        url
+    }
+
+    #[tokio::test]
+    async fn loopback_flow_exchanges_code_with_pkce_and_refreshes() {
+        let challenge = Arc::new(Mutex::new(String::new()));
+        let settings = OAuth2Settings {
+            auth_url: "https://auth.example.com/authorize?prompt=consent".into(),
+            token_url: mock_token_server(Arc::clone(&challenge)),
+            client_id: "client".into(),
+            client_secret: None,
+            scopes: vec!["mail".into(), "offline_access".into()],
+        };
+
+        let flow = LoopbackAuthorization::start(&settings, Some("me@example.com"))
+            .await
+            .expect("start");
+        let url = Url::parse(flow.url()).expect("url");
+        let query: std::collections::HashMap<_, _> = url.query_pairs().into_owned().collect();
+        assert_eq!(query["prompt"], "consent");
+        assert_eq!(query["response_type"], "code");
+        assert_eq!(query["code_challenge_method"], "S256");
+        assert_eq!(query["scope"], "mail offline_access");
+        assert_eq!(query["login_hint"], "me@example.com");
+        *challenge.lock().unwrap() = query["code_challenge"].clone();
+        let redirect = Url::parse(&query["redirect_uri"]).expect("redirect");
+        assert_eq!(redirect.host_str(), Some("127.0.0.1"));
+
+        let finish = tokio::spawn(flow.finish());
+        // Play the browser: a stray request first, then the redirect.
+        let addr = format!("127.0.0.1:{}", redirect.port().expect("port"));
+        for target in [
+            "/favicon.ico".to_string(),
+            format!("/?code=abc&state={}", query["state"]),
+        ] {
+            let mut browser = tokio::net::TcpStream::connect(&addr)
+                .await
+                .expect("connect");
+            browser
+                .write_all(format!("GET {target} HTTP/1.1\r\nHost: {addr}\r\n\r\n").as_bytes())
+                .await
+                .expect("write");
+            let mut page = String::new();
+            browser.read_to_string(&mut page).await.expect("read");
+            assert!(page.starts_with("HTTP/1.1 "));
+        }
+        let tokens = finish.await.expect("join").expect("tokens");
+        assert_eq!(tokens.access_token, "at-1");
+        assert_eq!(tokens.refresh_token.as_deref(), Some("rt-1"));
+        assert_eq!(tokens.expires_in, Some(3600));
+
+        let refreshed = refresh(&settings, "rt-1").await.expect("refresh");
+        assert_eq!(refreshed.access_token, "at-2");
+        assert_eq!(refreshed.refresh_token, None);
+        assert!(matches!(
+            refresh(&settings, "revoked").await,
+            Err(TokenError::Rejected(message)) if message.contains("bad code")
+        ));
+    }
+
+    #[tokio::test]
+    async fn redirect_with_wrong_state_is_refused() {
+        let challenge = Arc::new(Mutex::new(String::new()));
+        let settings = OAuth2Settings {
+            auth_url: "https://auth.example.com/authorize".into(),
+            token_url: mock_token_server(Arc::clone(&challenge)),
+            client_id: "client".into(),
+            client_secret: None,
+            scopes: Vec::new(),
+        };
+        let flow = LoopbackAuthorization::start(&settings, None)
+            .await
+            .expect("start");
+        let url = Url::parse(flow.url()).expect("url");
+        let query: std::collections::HashMap<_, _> = url.query_pairs().into_owned().collect();
+        *challenge.lock().unwrap() = query["code_challenge"].clone();
+        let redirect = Url::parse(&query["redirect_uri"]).expect("redirect");
+        let finish = tokio::spawn(flow.finish());
+        // A forged redirect is refused without ending the wait.
+        let addr = format!("127.0.0.1:{}", redirect.port().expect("port"));
+        for (target, status) in [
+            ("/?error=access_denied&state=forged".to_string(), "400"),
+            ("/?code=abc&state=forged".to_string(), "400"),
+            (format!("/?code=abc&state={}", query["state"]), "200"),
+        ] {
+            let mut browser = tokio::net::TcpStream::connect(&addr)
+                .await
+                .expect("connect");
+            browser
+                .write_all(format!("GET {target} HTTP/1.1\r\n\r\n").as_bytes())
+                .await
+                .expect("write");
+            let mut page = String::new();
+            browser.read_to_string(&mut page).await.expect("read");
+            assert!(page.starts_with(&format!("HTTP/1.1 {status}")), "{page}");
+        }
+        let tokens = finish.await.expect("join").expect("tokens");
+        assert_eq!(tokens.access_token, "at-1");
+    }
+}
@@ -0,0 +1,395 @@
+//! Persistent send queue.
+//!
+//! Outgoing mail is saved to the cache's `outbox` table before anything
+//! touches the network, so a dropped connection or a 4xx reply never loses
+//! a message. A background task sends due entries, retries transient
+//! failures with backoff, parks permanent failures until the user requeues
+//! or removes them, and only deletes an entry once the server has accepted
+//! it and, if asked, the copy has been appended to Sent.
+//!
+//! Messages can also be held back: for a few seconds so the user can undo
+//! the send, or until a scheduled time. Both are stored with the entry and
+//! survive a restart; until the worker picks the message up, [`Outbox::remove`]
+//! hands it back as a draft.
+
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tokio::sync::{mpsc, Notify};
+use tokio::task::JoinHandle;
+
+use crate::config::SmtpConfig;
+use crate::imap::ImapSession;
+use crate::models::{OutboxEntry, OutboxStatus};
+use crate::smtp::{try_send_email, OutgoingEmail, SendError};
+use crate::store::CacheHandle;
+use crate::{Flag, MailboxHash};
+
+const FIRST_RETRY: Duration = Duration::from_secs(30);
+const MAX_RETRY: Duration = Duration::from_secs(3600);
+/// How often the queue is looked at when nothing is due, in case another
+/// process queued mail into the same cache.
+const IDLE_POLL: Duration = Duration::from_secs(300);
+
+/// Delay before retry `attempt` (1-based): 30s, 1m, 2m, ... capped at an
+/// hour. Transient failures are retried for as long as they stay transient.
+fn retry_delay(attempt: u32) -> Duration {
+    FIRST_RETRY
+        .saturating_mul(1u32 << attempt.saturating_sub(1).min(16))
+        .min(MAX_RETRY)
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+/// Where accepted messages are filed for servers that don't do it themselves.
+#[derive(Clone)]
+pub struct SentFolder {
+    pub session: Arc<ImapSession>,
+    pub mailbox_hash: MailboxHash,
+}
+
+/// A status change of one outbox entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutboxEvent {
+    pub id: i64,
+    pub status: OutboxStatus,
+    /// The failure behind a `Queued` (retrying), `Appending` or `Failed` status.
+    pub error: Option<String>,
+}
+
+/// The send queue of one account. Dropping it stops the worker; queued
+/// mail stays in the cache for the next start.
+pub struct Outbox {
+    cache: CacheHandle,
+    account_id: String,
+    wake: Arc<Notify>,
+    task: JoinHandle<()>,
+}
+
+impl Drop for Outbox {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl Outbox {
+    /// Start the worker. Anything left in the outbox from an earlier run is
+    /// picked up right away; an entry that was mid-send when the app exited
+    /// is sent again, since there's no telling whether the server took it.
+    pub fn start(
+        cache: CacheHandle,
+        account_id: String,
+        smtp: SmtpConfig,
+        sent: Option<SentFolder>,
+    ) -> (Self, mpsc::UnboundedReceiver<OutboxEvent>) {
+        let (events, rx) = mpsc::unbounded_channel();
+        let wake = Arc::new(Notify::new());
+        let worker = Worker {
+            cache: cache.clone(),
+            account_id: account_id.clone(),
+            smtp,
+            sent,
+            events,
+        };
+        let task = tokio::spawn(worker.run(wake.clone()));
+        (
+            Outbox {
+                cache,
+                account_id,
+                wake,
+                task,
+            },
+            rx,
+        )
+    }
+
+    /// Save a message and send it as soon as possible. Returns its outbox id.
+    pub async fn queue(&self, email: OutgoingEmail, append_to_sent: bool) -> Result<i64, String> {
+        self.queue_at(email, append_to_sent, unix_now()).await
+    }
+
+    /// Save a message and send it once `delay` has passed, e.g. 10 seconds
+    /// in which "Undo" calls [`remove`](Self::remove).
+    pub async fn queue_with_undo(
+        &self,
+        email: OutgoingEmail,
+        append_to_sent: bool,
+        delay: Duration,
+    ) -> Result<i64, String> {
+        let send_at = unix_now() + delay.as_secs().max(1) as i64;
+        self.queue_at(email, append_to_sent, send_at).await
+    }
+
+    /// Save a message and send it at `at`, or right away if that has passed.
+    pub async fn schedule(
+        &self,
+        email: OutgoingEmail,
+        append_to_sent: bool,
+        at: SystemTime,
+    ) -> Result<i64, String> {
+        let send_at = at
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or(0);
+        self.queue_at(email, append_to_sent, send_at).await
+    }
+
+    async fn queue_at(
+        &self,
+        email: OutgoingEmail,
+        append_to_sent: bool,
+        send_at: i64,
+    ) -> Result<i64, String> {
+        let id = self
+            .cache
+            .queue_outgoing(
+                self.account_id.clone(),
+                email,
+                append_to_sent,
+                unix_now(),
+                send_at,
+            )
+            .await?;
+        self.wake.notify_one();
+        Ok(id)
+    }
+
+    /// Everything still in the outbox, oldest first.
+    pub async fn entries(&self) -> Result<Vec<OutboxEntry>, String> {
+        self.cache.load_outbox(self.account_id.clone()).await
+    }
+
+    /// Try a failed message again. Returns `false` if it wasn't failed.
+    pub async fn requeue(&self, id: i64) -> Result<bool, String> {
+        let requeued = self.cache.requeue_outbox(id, unix_now()).await?;
+        if requeued {
+            self.wake.notify_one();
+        }
+        Ok(requeued)
+    }
+
+    /// Take a message out of the outbox: undo a send, cancel a scheduled
+    /// one, or edit a failed one. `None` if it's already gone, being sent
+    /// right now, or already accepted by the server.
+    pub async fn remove(&self, id: i64) -> Result<Option<OutgoingEmail>, String> {
+        self.cache.take_outbox(id).await
+    }
+}
+
+struct Worker {
+    cache: CacheHandle,
+    account_id: String,
+    smtp: SmtpConfig,
+    sent: Option<SentFolder>,
+    events: mpsc::UnboundedSender<OutboxEvent>,
+}
+
+impl Worker {
+    async fn run(self, wake: Arc<Notify>) {
+        if let Err(e) = self
+            .cache
+            .requeue_interrupted_outbox(self.account_id.clone())
+            .await
+        {
+            log::warn!("Outbox of {}: {e}", self.account_id);
+        }
+        loop {
+            let wait = match self.cache.load_outbox(self.account_id.clone()).await {
+                Ok(entries) => self.process_due(entries).await,
+                Err(e) => {
+                    log::warn!("Outbox of {}: {e}", self.account_id);
+                    FIRST_RETRY
+                }
+            };
+            tokio::select! {
+                _ = tokio::time::sleep(wait) => {}
+                _ = wake.notified() => {}
+            }
+        }
+    }
+
+    /// Work through every due entry; returns how long to sleep until the
+    /// next one is due.
+    async fn process_due(&self, entries: Vec<OutboxEntry>) -> Duration {
+        let mut next_due: Option<i64> = None;
+        for entry in entries {
+            if entry.status == OutboxStatus::Failed {
+                continue;
+            }
+            let now = unix_now();
+            if entry.next_attempt_at > now {
+                next_due =
+                    Some(next_due.map_or(entry.next_attempt_at, |t| t.min(entry.next_attempt_at)));
+                continue;
+            }
+            if let Err(e) = self.process(entry).await {
+                log::warn!("Outbox of {}: {e}", self.account_id);
+            }
+        }
+        match next_due {
+            Some(at) => Duration::from_secs((at - unix_now()).max(1) as u64).min(IDLE_POLL),
+            None => IDLE_POLL,
+        }
+    }
+
+    async fn process(&self, entry: OutboxEntry) -> Result<(), String> {
+        let (bytes, append_attempts) = match entry.sent {
+            // Accepted earlier; only the Sent copy is missing.
+            Some(bytes) if entry.status == OutboxStatus::Appending => (bytes, entry.attempts),
+            _ => match self.send(&entry).await? {
+                Some(bytes) => (bytes, 0),
+                None => return Ok(()),
+            },
+        };
+
+        if let (true, Some(sent)) = (entry.append_to_sent, &self.sent) {
+            if let Err(e) = sent
+                .session
+                .append_message(sent.mailbox_hash, bytes, Flag::SEEN)
+                .await
+            {
+                let attempts = append_attempts + 1;
+                self.cache
+                    .update_outbox(
+                        entry.id,
+                        OutboxStatus::Appending,
+                        attempts,
+                        Some(e.clone()),
+                        unix_now() + retry_delay(attempts).as_secs() as i64,
+                    )
+                    .await?;
+                self.report(entry.id, OutboxStatus::Appending, Some(e));
+                return Ok(());
+            }
+        }
+        self.cache.remove_outbox(entry.id).await?;
+        self.report(entry.id, OutboxStatus::Sent, None);
+        Ok(())
+    }
+
+    /// Hand the message to the server. `None` when it failed and the entry
+    /// was rescheduled or parked, or when it was removed after `entry` was
+    /// loaded.
+    async fn send(&self, entry: &OutboxEntry) -> Result<Option<Vec<u8>>, String> {
+        if !self.cache.claim_outbox(entry.id).await? {
+            return Ok(None);
+        }
+        self.report(entry.id, OutboxStatus::Sending, None);
+
+        match try_send_email(&self.smtp, &entry.email).await {
+            Ok(bytes) => {
+                if entry.append_to_sent && self.sent.is_some() {
+                    self.cache.set_outbox_sent(entry.id, bytes.clone()).await?;
+                }
+                Ok(Some(bytes))
+            }
+            Err(SendError::Transient(e)) => {
+                let attempts = entry.attempts + 1;
+                self.cache
+                    .update_outbox(
+                        entry.id,
+                        OutboxStatus::Queued,
+                        attempts,
+                        Some(e.clone()),
+                        unix_now() + retry_delay(attempts).as_secs() as i64,
+                    )
+                    .await?;
+                self.report(entry.id, OutboxStatus::Queued, Some(e));
+                Ok(None)
+            }
+            Err(SendError::Permanent(e)) => {
+                self.cache
+                    .update_outbox(
+                        entry.id,
+                        OutboxStatus::Failed,
+                        entry.attempts + 1,
+                        Some(e.clone()),
+                        entry.next_attempt_at,
+                    )
+                    .await?;
+                self.report(entry.id, OutboxStatus::Failed, Some(e));
+                Ok(None)
+            }
+        }
+    }
+
+    fn report(&self, id: i64, status: OutboxStatus, error: Option<String>) {
+        let _ = self.events.send(OutboxEvent { id, status, error });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::sync::mpsc;
+
+    use super::{retry_delay, Worker};
+    use crate::config::{SmtpConfig, TlsSettings, TransportSecurity};
+    use crate::models::EmailAddress;
+    use crate::smtp::OutgoingEmail;
+    use crate::store::CacheHandle;
+
+    #[test]
+    fn retries_back_off_up_to_an_hour() {
+        assert_eq!(retry_delay(1), Duration::from_secs(30));
+        assert_eq!(retry_delay(2), Duration::from_secs(60));
+        assert_eq!(retry_delay(4), Duration::from_secs(240));
+        assert_eq!(retry_delay(8), Duration::from_secs(3600));
+        assert_eq!(retry_delay(u32::MAX), Duration::from_secs(3600));
+    }
+
+    #[tokio::test]
+    async fn entries_removed_after_loading_are_not_sent() {
+        let cache = CacheHandle::open_in_memory().expect("cache");
+        let server = std::net::TcpListener::bind("127.0.0.1:0").expect("bind");
+        server.set_nonblocking(true).expect("nonblocking");
+        let (events, mut reported) = mpsc::unbounded_channel();
+        let worker = Worker {
+            cache: cache.clone(),
+            account_id: "a".into(),
+            smtp: SmtpConfig {
+                server: "127.0.0.1".into(),
+                port: server.local_addr().expect("addr").port(),
+                username: "me".into(),
+                password: "secret".into(),
+                use_starttls: false,
+                tls: TlsSettings {
+                    security: Some(TransportSecurity::None),
+                    ..Default::default()
+                },
+                oauth2: None,
+            },
+            sent: None,
+            events,
+        };
+        let email = OutgoingEmail {
+            from: "me@example.com".into(),
+            to: EmailAddress::parse_list("you@example.com").expect("to"),
+            subject: "Oops".into(),
+            ..Default::default()
+        };
+        let id = cache
+            .queue_outgoing("a".into(), email, false, 100, 100)
+            .await
+            .expect("queue");
+
+        // Undo lands between the worker loading the queue and sending.
+        let entries = cache.load_outbox("a".into()).await.expect("load");
+        assert!(cache.take_outbox(id).await.expect("take").is_some());
+        worker.process_due(entries).await;
+
+        assert!(reported.try_recv().is_err());
+        assert!(server.accept().is_err(), "SMTP server was contacted");
+        assert!(cache
+            .load_outbox("a".into())
+            .await
+            .expect("load")
+            .is_empty());
+    }
+}
@@ -6,7 +6,7 @@
 
 use crate::config::{
     new_account_id, AccountId, ConfigNeedsInput, FileAccountConfig, MultiAccountFileConfig,
-    PasswordBackend, SmtpOverrides,
+    PasswordBackend, SmtpOverrides, TlsSettings,
 };
 use crate::keyring;
 
@@ -414,6 +414,7 @@ impl SetupModel {
                     password: password_backend,
                     email_addresses,
                     smtp,
+                    tls: TlsSettings::default(),
                 };
 
                 let mut multi = MultiAccountFileConfig::load().ok().flatten().unwrap_or(
@@ -488,7 +489,10 @@ impl SetupModel {
                 } else {
                     store_smtp_password(account_id, &self.smtp_password)
                 };
-                let smtp = self.build_smtp_overrides(smtp_pw);
+                let mut smtp = self.build_smtp_overrides(smtp_pw);
+                // TLS settings aren't part of the form; keep hand-edited ones.
+                smtp.tls = existing.smtp.tls.clone();
+                let tls = existing.tls.clone();
 
                 let fac = FileAccountConfig {
                     id: account_id.clone(),
@@ -500,6 +504,7 @@ impl SetupModel {
                     password: password_backend,
                     email_addresses,
                     smtp,
+                    tls,
                 };
 
                 if let Some(pos) = multi.accounts.iter().position(|a| a.id == *account_id) {
@@ -582,6 +587,7 @@ impl SetupModel {
             username: non_empty_trimmed(&self.smtp_username),
             password,
             use_starttls: Some(self.smtp_starttls),
+            tls: None,
         }
     }
 }
@@ -1,45 +1,165 @@
-use lettre::message::header::{self, ContentType};
-use lettre::message::{Attachment, MultiPart, SinglePart};
-use lettre::transport::smtp::authentication::Credentials;
+use lettre::message::header::{self, ContentTransferEncoding, ContentType};
+use lettre::message::{Attachment, Body, Mailbox, MultiPart, SinglePart};
+use lettre::transport::smtp::authentication::{Credentials, Mechanism};
+use lettre::transport::smtp::client::{Tls, TlsParameters};
 use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
 
-use crate::config::SmtpConfig;
-use crate::models::AttachmentData;
+use serde::{Deserialize, Serialize};
 
+use crate::config::{SmtpConfig, TransportSecurity};
+use crate::mime::{encode_flowed, markdown_to_html};
+use crate::models::{AttachmentData, EmailAddress};
+use crate::oauth::{TokenError, TokenSource};
+use crate::tls::{Protocol, Relay, ServerCheck};
+
+/// How `OutgoingEmail::body` is written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BodyFormat {
+    /// Sent as text/plain only.
+    #[default]
+    Plain,
+    /// Sent as multipart/alternative: the Markdown source as text/plain and
+    /// sanitized HTML rendered from it.
+    Markdown,
+}
+
+/// An image shown in the HTML body, referenced as `cid:<content_id>`
+/// (e.g. `![chart](cid:chart@local)` in Markdown).
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct InlineImage {
+    /// Without angle brackets.
+    pub content_id: String,
+    pub filename: String,
+    pub mime_type: String,
+    #[serde(with = "crate::models::base64_bytes")]
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct OutgoingEmail {
     pub from: String,
-    pub to: String,
+    pub to: Vec<EmailAddress>,
+    pub cc: Vec<EmailAddress>,
+    /// Envelope recipients only; never written to the headers.
+    pub bcc: Vec<EmailAddress>,
+    pub reply_to: Vec<EmailAddress>,
     pub subject: String,
     pub body: String,
+    pub format: BodyFormat,
+    /// Send the text/plain part as `format=flowed`, so it reflows on narrow
+    /// screens instead of breaking at the sender's line ends.
+    #[serde(default)]
+    pub flowed: bool,
     pub in_reply_to: Option<String>,
     pub references: Option<String>,
     pub attachments: Vec<AttachmentData>,
+    /// Go into multipart/related next to the HTML part. With a plain body
+    /// nothing can reference them, so they're sent as attachments.
+    pub inline_images: Vec<InlineImage>,
+}
+
+/// Why a send failed, and whether trying again later can help.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SendError {
+    /// Network trouble, a 4xx reply or an expired token; retry later.
+    Transient(String),
+    /// A 5xx reply, a message that can't be built, or broken settings.
+    Permanent(String),
+}
+
+impl SendError {
+    pub fn is_permanent(&self) -> bool {
+        matches!(self, SendError::Permanent(_))
+    }
+}
+
+impl std::fmt::Display for SendError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SendError::Transient(message) | SendError::Permanent(message) => f.write_str(message),
+        }
+    }
+}
+
+/// Send an email over SMTP.
+///
+/// Returns the exact RFC 5322 bytes that were submitted, so callers can
+/// append them to the Sent folder with `ImapSession::append_message` on
+/// servers that don't file sent mail themselves.
+pub async fn send_email(config: &SmtpConfig, email: &OutgoingEmail) -> Result<Vec<u8>, String> {
+    try_send_email(config, email)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Like [`send_email`], but tells transient failures from permanent ones.
+pub async fn try_send_email(
+    config: &SmtpConfig,
+    email: &OutgoingEmail,
+) -> Result<Vec<u8>, SendError> {
+    let message = build_message(email).map_err(SendError::Permanent)?;
+    let (transport, relay) = smtp_transport(config).await?;
+
+    // Format once and submit those bytes, so the returned copy is byte-for-byte
+    // what the server accepted (Message-ID and Date included).
+    let bytes = message.formatted();
+    let sent = transport.send_raw(message.envelope(), &bytes).await;
+    // lettre refuses 8-bit data when the server lacks 8BITMIME; forwarded
+    // messages then go as opaque base64 attachments instead.
+    let (bytes, sent) = match sent {
+        Err(e) if e.is_client() && !bytes.is_ascii() => {
+            let message = build_message_as(email, false).map_err(SendError::Permanent)?;
+            let bytes = message.formatted();
+            let sent = transport.send_raw(message.envelope(), &bytes).await;
+            (bytes, sent)
+        }
+        sent => (bytes, sent),
+    };
+    sent.map_err(|e| {
+        if let Some(refused) = relay.as_ref().and_then(Relay::take_error) {
+            return SendError::Transient(format!("SMTP send failed: {refused}"));
+        }
+        let message = format!("SMTP send failed: {e}");
+        if e.is_permanent() {
+            SendError::Permanent(message)
+        } else {
+            SendError::Transient(message)
+        }
+    })?;
+
+    Ok(bytes)
+}
+
+/// Build the message. Bcc recipients land in the envelope; lettre drops
+/// the Bcc header itself.
+fn build_message(email: &OutgoingEmail) -> Result<Message, String> {
+    build_message_as(email, true)
 }
 
-pub async fn send_email(config: &SmtpConfig, email: &OutgoingEmail) -> Result<(), String> {
+/// [`build_message`], with forwarded messages attached as 8bit only when
+/// `eight_bit` is set (the server offers 8BITMIME).
+fn build_message_as(email: &OutgoingEmail, eight_bit: bool) -> Result<Message, String> {
     let from = email
         .from
         .parse()
         .map_err(|e| format!("Invalid From address: {e}"))?;
-
-    let mut to_addrs = Vec::new();
-    for addr in email.to.split(',') {
-        let addr = addr.trim();
-        if !addr.is_empty() {
-            to_addrs.push(
-                addr.parse()
-                    .map_err(|e| format!("Invalid To address '{addr}': {e}"))?,
-            );
-        }
-    }
-    if to_addrs.is_empty() {
+    if email.to.is_empty() && email.cc.is_empty() && email.bcc.is_empty() {
         return Err("No recipients specified".into());
     }
 
     let mut builder = Message::builder().from(from).subject(&email.subject);
-
-    for addr in to_addrs {
-        builder = builder.to(addr);
+    for addr in &email.to {
+        builder = builder.to(mailbox(addr, "To")?);
+    }
+    for addr in &email.cc {
+        builder = builder.cc(mailbox(addr, "Cc")?);
+    }
+    for addr in &email.bcc {
+        builder = builder.bcc(mailbox(addr, "Bcc")?);
+    }
+    for addr in &email.reply_to {
+        builder = builder.reply_to(mailbox(addr, "Reply-To")?);
     }
 
     if let Some(ref irt) = email.in_reply_to {
@@ -49,45 +169,547 @@ pub async fn send_email(config: &SmtpConfig, email: &OutgoingEmail) -> Result<()
         builder = builder.header(header::References::from(refs.clone()));
     }
 
-    let message = if email.attachments.is_empty() {
-        builder
-            .body(email.body.clone())
-            .map_err(|e| format!("Failed to build message: {e}"))?
-    } else {
-        let text_part = SinglePart::plain(email.body.clone());
-        let mut multipart = MultiPart::mixed().singlepart(text_part);
-        for att in &email.attachments {
-            let content_type: ContentType =
-                att.mime_type.parse().unwrap_or(ContentType::TEXT_PLAIN);
-            let attachment =
-                Attachment::new(att.filename.clone()).body(att.data.clone(), content_type);
-            multipart = multipart.singlepart(attachment);
+    let mut attachments: Vec<SinglePart> = email
+        .attachments
+        .iter()
+        .map(|att| attachment_part(att, eight_bit))
+        .collect();
+    let alternative = match email.format {
+        BodyFormat::Plain => {
+            attachments.extend(email.inline_images.iter().map(|image| {
+                Attachment::new(image.filename.clone())
+                    .body(image.data.clone(), content_type(&image.mime_type))
+            }));
+            None
+        }
+        BodyFormat::Markdown => {
+            let plain = plain_part(email);
+            let html = SinglePart::html(markdown_to_html(&email.body));
+            let alternative = MultiPart::alternative().singlepart(plain);
+            Some(if email.inline_images.is_empty() {
+                alternative.singlepart(html)
+            } else {
+                let related = email.inline_images.iter().fold(
+                    MultiPart::related().singlepart(html),
+                    |related, image| {
+                        related.singlepart(
+                            Attachment::new_inline_with_name(
+                                image.content_id.clone(),
+                                image.filename.clone(),
+                            )
+                            .body(image.data.clone(), content_type(&image.mime_type)),
+                        )
+                    },
+                );
+                alternative.multipart(related)
+            })
+        }
+    };
+
+    let message = match (alternative, attachments.is_empty()) {
+        (None, true) if email.flowed => builder.singlepart(plain_part(email)),
+        (None, true) => builder.body(email.body.clone()),
+        (Some(alternative), true) => builder.multipart(alternative),
+        (alternative, false) => {
+            let mixed = match alternative {
+                Some(alternative) => MultiPart::mixed().multipart(alternative),
+                None => MultiPart::mixed().singlepart(plain_part(email)),
+            };
+            builder.multipart(attachments.into_iter().fold(mixed, MultiPart::singlepart))
         }
-        builder
-            .multipart(multipart)
-            .map_err(|e| format!("Failed to build message: {e}"))?
     };
+    message.map_err(|e| format!("Failed to build message: {e}"))
+}
+
+/// The text/plain part of the body, flowed if asked for.
+fn plain_part(email: &OutgoingEmail) -> SinglePart {
+    if email.flowed {
+        SinglePart::builder()
+            .header(content_type("text/plain; charset=utf-8; format=flowed"))
+            .body(encode_flowed(&email.body))
+    } else {
+        SinglePart::plain(email.body.clone())
+    }
+}
+
+/// An attached file. A `message/*` part may only be 7bit, 8bit or binary
+/// (RFC 2046 §5.2.1), so a forwarded original is passed through as is with
+/// its line ends made CRLF. One that still isn't valid 7bit or 8bit (or is
+/// 8bit when `eight_bit` is off) goes as `application/octet-stream` in
+/// base64 instead: plain SMTP DATA can't carry binary.
+fn attachment_part(att: &AttachmentData, eight_bit: bool) -> SinglePart {
+    let attachment = Attachment::new(att.filename.clone());
+    if !att.mime_type.to_ascii_lowercase().starts_with("message/") {
+        return attachment.body(att.data.clone(), content_type(&att.mime_type));
+    }
+    let data = crlf_line_ends(&att.data);
+    match message_encoding(&data) {
+        Some(ContentTransferEncoding::EightBit) if !eight_bit => {}
+        // lettre's own 8bit check wants UTF-8 and QP-length lines, which
+        // most real messages fail, so this goes in pre-encoded.
+        Some(encoding) => {
+            return attachment.body(
+                Body::dangerous_pre_encoded(data, encoding),
+                content_type(&att.mime_type),
+            );
+        }
+        None => {}
+    }
+    let body = Body::new_with_encoding(att.data.clone(), ContentTransferEncoding::Base64)
+        .unwrap_or_else(Body::new);
+    attachment.body(body, content_type("application/octet-stream"))
+}
 
-    let creds = Credentials::new(config.username.clone(), config.password.clone());
+/// Turn bare LF and bare CR line ends into CRLF.
+fn crlf_line_ends(raw: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(raw.len());
+    let mut bytes = raw.iter().copied().peekable();
+    while let Some(b) = bytes.next() {
+        match b {
+            b'\r' => {
+                bytes.next_if_eq(&b'\n');
+                out.extend_from_slice(b"\r\n");
+            }
+            b'\n' => out.extend_from_slice(b"\r\n"),
+            _ => out.push(b),
+        }
+    }
+    out
+}
 
-    let transport = if config.use_starttls {
-        AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.server)
-            .map_err(|e| format!("SMTP relay error: {e}"))?
-            .port(config.port)
-            .credentials(creds)
-            .build()
+/// 7bit or 8bit for CRLF-terminated text whose lines are at most 998
+/// octets with no NULs (RFC 2045 §2.7, §2.8); `None` otherwise.
+fn message_encoding(raw: &[u8]) -> Option<ContentTransferEncoding> {
+    let fits = raw
+        .split(|&b| b == b'\n')
+        .all(|line| line.strip_suffix(b"\r").unwrap_or(line).len() <= 998);
+    if !fits || raw.contains(&0) {
+        None
+    } else if raw.is_ascii() {
+        Some(ContentTransferEncoding::SevenBit)
     } else {
-        AsyncSmtpTransport::<Tokio1Executor>::relay(&config.server)
-            .map_err(|e| format!("SMTP relay error: {e}"))?
-            .port(config.port)
-            .credentials(creds)
-            .build()
+        Some(ContentTransferEncoding::EightBit)
+    }
+}
+
+fn content_type(mime_type: &str) -> ContentType {
+    mime_type.parse().unwrap_or(ContentType::TEXT_PLAIN)
+}
+
+fn mailbox(addr: &EmailAddress, field: &str) -> Result<Mailbox, String> {
+    let email = addr
+        .email
+        .parse()
+        .map_err(|e| format!("Invalid {field} address '{}': {e}", addr.email))?;
+    Ok(Mailbox::new(addr.name.clone(), email))
+}
+
+/// Build the transport for the account's security mode and credentials.
+/// With a CA file or pin, lettre talks plaintext to a loopback [`Relay`]
+/// that checks the certificate on the real connection; keep it alive until
+/// the send is done.
+async fn smtp_transport(
+    config: &SmtpConfig,
+) -> Result<(AsyncSmtpTransport<Tokio1Executor>, Option<Relay>), SendError> {
+    let security = config.security();
+    let relay = match ServerCheck::needed(
+        &config.server,
+        config.port,
+        security,
+        &config.tls,
+        Protocol::Smtp,
+    ) {
+        Some(check) => Some(check.relay().await.map_err(SendError::Permanent)?),
+        None => None,
+    };
+    let mut builder = match &relay {
+        Some(relay) => {
+            AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(Relay::HOST).port(relay.port())
+        }
+        None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&config.server)
+            .port(config.port),
+    };
+    builder = match &config.oauth2 {
+        Some(settings) => {
+            let token = TokenSource::new(settings.clone(), &config.username)
+                .access_token()
+                .await
+                .map_err(|e| match e {
+                    TokenError::Rejected(e) => {
+                        SendError::Permanent(format!("OAuth2 sign-in failed: {e}"))
+                    }
+                    TokenError::Failed(e) => {
+                        SendError::Transient(format!("OAuth2 sign-in failed: {e}"))
+                    }
+                })?;
+            builder
+                .credentials(Credentials::new(config.username.clone(), token))
+                .authentication(vec![Mechanism::Xoauth2])
+        }
+        None => builder.credentials(Credentials::new(
+            config.username.clone(),
+            config.password.clone(),
+        )),
     };
+    if security == TransportSecurity::None || relay.is_some() {
+        return Ok((builder.tls(Tls::None).build(), relay));
+    }
 
-    transport
-        .send(message)
-        .await
-        .map_err(|e| format!("SMTP send failed: {e}"))?;
+    let params = TlsParameters::builder(config.server.clone())
+        .build()
+        .map_err(|e| SendError::Permanent(format!("SMTP TLS setup error: {e}")))?;
+    let tls = match security {
+        TransportSecurity::Implicit => Tls::Wrapper(params),
+        _ => Tls::Required(params),
+    };
+    Ok((builder.tls(tls).build(), relay))
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    use super::{
+        build_message, build_message_as, try_send_email, BodyFormat, InlineImage, OutgoingEmail,
+        SendError,
+    };
+    use crate::config::{SmtpConfig, TlsSettings, TransportSecurity};
+    use crate::models::AttachmentData;
+    use crate::models::EmailAddress;
+
+    #[test]
+    fn bcc_goes_to_the_envelope_but_not_the_headers() {
+        let email = OutgoingEmail {
+            from: "Me <me@example.com>".into(),
+            to: EmailAddress::parse_list(r#""Doe, Jane" <jane@x.org>"#).expect("to"),
+            cc: EmailAddress::parse_list("bob@y.org").expect("cc"),
+            bcc: EmailAddress::parse_list("Hidden <hidden@z.org>").expect("bcc"),
+            reply_to: EmailAddress::parse_list("list@example.com").expect("reply-to"),
+            subject: "Hi".into(),
+            body: "Hello".into(),
+            ..Default::default()
+        };
+        let message = build_message(&email).expect("build");
+        let recipients: Vec<String> = message
+            .envelope()
+            .to()
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(recipients, ["jane@x.org", "bob@y.org", "hidden@z.org"]);
+
+        let text = String::from_utf8(message.formatted()).expect("utf-8");
+        // lettre encodes display names with specials: "Doe, Jane".
+        assert!(text.contains("To: =?utf-8?b?RG9lLCBKYW5l?= <jane@x.org>\r\n"));
+        assert!(text.contains("Cc: bob@y.org\r\n"));
+        assert!(text.contains("Reply-To: list@example.com\r\n"));
+        assert!(!text.contains("hidden"));
+
+        let nobody = OutgoingEmail {
+            from: "me@example.com".into(),
+            ..Default::default()
+        };
+        assert!(build_message(&nobody).is_err());
+    }
+
+    #[test]
+    fn markdown_nests_alternative_and_related_inside_mixed() {
+        let email = OutgoingEmail {
+            from: "me@example.com".into(),
+            to: EmailAddress::parse_list("you@example.com").expect("to"),
+            subject: "Report".into(),
+            body: "See **this**:\n\n![chart](cid:chart@local)\n".into(),
+            format: BodyFormat::Markdown,
+            attachments: vec![AttachmentData {
+                filename: "report.pdf".into(),
+                mime_type: "application/pdf".into(),
+                data: b"%PDF".to_vec(),
+                content_id: None,
+            }],
+            inline_images: vec![InlineImage {
+                content_id: "chart@local".into(),
+                filename: "chart.png".into(),
+                mime_type: "image/png".into(),
+                data: vec![0x89, b'P', b'N', b'G'],
+            }],
+            ..Default::default()
+        };
+        let text =
+            String::from_utf8(build_message(&email).expect("build").formatted()).expect("utf-8");
+        let order: Vec<usize> = [
+            "multipart/mixed",
+            "multipart/alternative",
+            "text/plain",
+            "See **this**",
+            "multipart/related",
+            "text/html",
+            "Content-ID: <chart@local>",
+            "report.pdf",
+        ]
+        .iter()
+        .map(|needle| {
+            text.find(needle)
+                .unwrap_or_else(|| panic!("missing {needle}"))
+        })
+        .collect();
+        assert!(order.windows(2).all(|w| w[0] < w[1]), "{text}");
+
+        let single = OutgoingEmail {
+            format: BodyFormat::Markdown,
+            attachments: Vec::new(),
+            inline_images: Vec::new(),
+            ..email
+        };
+        let text =
+            String::from_utf8(build_message(&single).expect("build").formatted()).expect("utf-8");
+        assert!(text.contains("multipart/alternative"));
+        assert!(!text.contains("multipart/mixed"));
+        assert!(!text.contains("multipart/related"));
+    }
+
+    #[test]
+    fn flowed_bodies_are_soft_wrapped() {
+        let email = OutgoingEmail {
+            from: "me@example.com".into(),
+            to: EmailAddress::parse_list("you@example.com").expect("to"),
+            subject: "Long".into(),
+            body: format!("{}\n\nFrom Ann", "word ".repeat(30)),
+            flowed: true,
+            ..Default::default()
+        };
+        let text =
+            String::from_utf8(build_message(&email).expect("build").formatted()).expect("utf-8");
+        assert!(text.contains("format=flowed"), "{text}");
+        assert!(text.contains("word \r\nword"), "{text}");
+        assert!(text.contains("\r\n From Ann"), "{text}");
+    }
+
+    #[test]
+    fn forwarded_messages_are_attached_without_reencoding() {
+        // Latin-1 and a line QP would wrap: lettre alone would pick base64.
+        let mut original = b"From: ann@example.com\r\nSubject: Caf\xe9\r\n\r\n".to_vec();
+        original.extend_from_slice(b"Gr\xfc\xdfe, ");
+        original.extend_from_slice("word ".repeat(40).as_bytes());
+        original.extend_from_slice(b"\r\n");
+        let forward = |raw: Vec<u8>| OutgoingEmail {
+            from: "me@example.com".into(),
+            to: EmailAddress::parse_list("you@example.com").expect("to"),
+            subject: "Fwd: Caf\u{e9}".into(),
+            body: "See below".into(),
+            attachments: vec![AttachmentData {
+                filename: "original.eml".into(),
+                mime_type: "message/rfc822".into(),
+                data: raw,
+                content_id: None,
+            }],
+            ..Default::default()
+        };
+        let formatted = build_message(&forward(original.clone()))
+            .expect("build")
+            .formatted();
+        let text = String::from_utf8_lossy(&formatted);
+        let part = &text[text.find("Content-Type: message/rfc822").expect("part")..];
+        let part = &part[..part.find("\r\n\r\n").expect("headers")];
+        assert!(part.contains("Content-Transfer-Encoding: 8bit"), "{part}");
+        assert!(formatted
+            .windows(original.len())
+            .any(|window| window == original.as_slice()));
+
+        // Bare LF line ends are made CRLF; plain ASCII stays 7bit.
+        let formatted = build_message(&forward(b"Subject: x\n\nhello\n".to_vec()))
+            .expect("build")
+            .formatted();
+        let text = String::from_utf8(formatted).expect("utf-8");
+        let part = &text[text.find("Content-Type: message/rfc822").expect("part")..];
+        assert!(part.contains("Content-Transfer-Encoding: 7bit"), "{part}");
+        assert!(part.contains("Subject: x\r\n\r\nhello\r\n"), "{part}");
 
-    Ok(())
+        // Lines over 998 octets can't be 7bit or 8bit, and binary can't go
+        // over plain DATA, so the original is attached opaquely.
+        let long = format!("Subject: x\r\n\r\n{}\r\n", "a".repeat(1200));
+        let formatted = build_message(&forward(long.into_bytes()))
+            .expect("build")
+            .formatted();
+        let text = String::from_utf8(formatted).expect("utf-8");
+        assert!(!text.contains("binary"), "{text}");
+        assert!(!text.contains("message/rfc822"), "{text}");
+        let part = &text[text
+            .find("Content-Type: application/octet-stream")
+            .expect("part")..];
+        assert!(part.contains("Content-Transfer-Encoding: base64"), "{part}");
+
+        // Without 8BITMIME the 8bit original goes the same way.
+        let formatted = build_message_as(&forward(original), false)
+            .expect("build")
+            .formatted();
+        assert!(formatted.is_ascii());
+        let text = String::from_utf8(formatted).expect("utf-8");
+        assert!(
+            text.contains("Content-Type: application/octet-stream"),
+            "{text}"
+        );
+    }
+
+    /// An SMTP server without 8BITMIME that hands back the first message
+    /// it accepts, on whichever connection that arrives.
+    async fn seven_bit_server() -> (u16, tokio::sync::oneshot::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let port = listener.local_addr().expect("addr").port();
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        let tx = std::sync::Arc::new(std::sync::Mutex::new(Some(tx)));
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let tx = std::sync::Arc::clone(&tx);
+                tokio::spawn(async move {
+                    let (read, mut write) = stream.into_split();
+                    let mut lines = BufReader::new(read).lines();
+                    let mut data = None::<String>;
+                    let mut reply = "220 mock\r\n";
+                    loop {
+                        if write.write_all(reply.as_bytes()).await.is_err() {
+                            break;
+                        }
+                        let Ok(Some(line)) = lines.next_line().await else {
+                            break;
+                        };
+                        if let Some(body) = data.as_mut() {
+                            if line != "." {
+                                body.push_str(&line);
+                                body.push('\n');
+                                reply = "";
+                                continue;
+                            }
+                            if let Some(tx) = tx.lock().unwrap().take() {
+                                let _ = tx.send(data.take().unwrap_or_default());
+                            }
+                            reply = "250 queued\r\n";
+                            continue;
+                        }
+                        reply = match &line.to_ascii_uppercase()[..4.min(line.len())] {
+                            "EHLO" => "250-mock\r\n250 AUTH PLAIN\r\n",
+                            "AUTH" => "235 ok\r\n",
+                            "DATA" => {
+                                data = Some(String::new());
+                                "354 go ahead\r\n"
+                            }
+                            "QUIT" => "221 bye\r\n",
+                            _ => "250 ok\r\n",
+                        };
+                    }
+                });
+            }
+        });
+        (port, rx)
+    }
+
+    #[tokio::test]
+    async fn eight_bit_forwards_fall_back_without_8bitmime() {
+        let (port, received) = seven_bit_server().await;
+        let config = SmtpConfig {
+            server: "127.0.0.1".into(),
+            port,
+            username: "me".into(),
+            password: "secret".into(),
+            use_starttls: false,
+            tls: TlsSettings {
+                security: Some(TransportSecurity::None),
+                ..Default::default()
+            },
+            oauth2: None,
+        };
+        let email = OutgoingEmail {
+            from: "me@example.com".into(),
+            to: EmailAddress::parse_list("you@example.com").expect("to"),
+            subject: "Fwd".into(),
+            body: "See below".into(),
+            attachments: vec![AttachmentData {
+                filename: "original.eml".into(),
+                mime_type: "message/rfc822".into(),
+                data: b"Subject: Caf\xe9\r\n\r\nGr\xfc\xdfe\r\n".to_vec(),
+                content_id: None,
+            }],
+            ..Default::default()
+        };
+        let sent = try_send_email(&config, &email).await.expect("send");
+        assert!(sent.is_ascii());
+        let received = received.await.expect("received");
+        assert!(
+            received.contains("Content-Type: application/octet-stream"),
+            "{received}"
+        );
+    }
+
+    /// A one-connection SMTP server that answers RCPT TO with `rcpt_reply`.
+    async fn mock_server(rcpt_reply: &'static str) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let port = listener.local_addr().expect("addr").port();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.expect("accept");
+            let (read, mut write) = stream.into_split();
+            let mut lines = BufReader::new(read).lines();
+            write.write_all(b"220 mock\r\n").await.expect("greet");
+            while let Ok(Some(line)) = lines.next_line().await {
+                let reply = match &line.to_ascii_uppercase()[..4.min(line.len())] {
+                    "EHLO" => "250-mock\r\n250 AUTH PLAIN\r\n",
+                    "AUTH" => "235 ok\r\n",
+                    "RCPT" => rcpt_reply,
+                    "QUIT" => "221 bye\r\n",
+                    _ => "250 ok\r\n",
+                };
+                if write.write_all(reply.as_bytes()).await.is_err() {
+                    break;
+                }
+            }
+        });
+        port
+    }
+
+    #[tokio::test]
+    async fn smtp_replies_are_classified_for_retry() {
+        let email = OutgoingEmail {
+            from: "me@example.com".into(),
+            to: EmailAddress::parse_list("you@example.com").expect("to"),
+            subject: "Hi".into(),
+            body: "Hello".into(),
+            ..Default::default()
+        };
+        for (reply, permanent) in [
+            ("451 try again later\r\n", false),
+            ("550 no such user\r\n", true),
+        ] {
+            let config = SmtpConfig {
+                server: "127.0.0.1".into(),
+                port: mock_server(reply).await,
+                username: "me".into(),
+                password: "secret".into(),
+                use_starttls: false,
+                tls: TlsSettings {
+                    security: Some(TransportSecurity::None),
+                    ..Default::default()
+                },
+                oauth2: None,
+            };
+            let err = try_send_email(&config, &email).await.expect_err(reply);
+            assert_eq!(err.is_permanent(), permanent, "{err}");
+        }
+
+        let invalid = OutgoingEmail {
+            from: "not an address".into(),
+            ..email
+        };
+        let config = SmtpConfig {
+            server: "127.0.0.1".into(),
+            port: 1,
+            username: String::new(),
+            password: String::new(),
+            use_starttls: false,
+            tls: TlsSettings::default(),
+            oauth2: None,
+        };
+        assert!(matches!(
+            try_send_email(&config, &invalid).await,
+            Err(SendError::Permanent(_))
+        ));
+    }
 }
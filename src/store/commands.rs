@@ -1,6 +1,11 @@
 use tokio::sync::oneshot;
 
-use crate::models::{AttachmentData, Folder, MessageSummary};
+use crate::mime::RemoteContentRule;
+use crate::models::{
+    AttachmentInfo, Folder, MailboxDelta, MailboxSyncState, MessageSummary, OutboxEntry,
+    OutboxStatus,
+};
+use crate::smtp::OutgoingEmail;
 
 #[allow(clippy::type_complexity)]
 pub(super) enum CacheCmd {
@@ -13,12 +18,40 @@ pub(super) enum CacheCmd {
         account_id: String,
         reply: oneshot::Sender<Result<Vec<Folder>, String>>,
     },
+    UpsertFolder {
+        account_id: String,
+        folder: Folder,
+        reply: oneshot::Sender<Result<(), String>>,
+    },
+    RenameFolder {
+        account_id: String,
+        old_mailbox_hash: u64,
+        folder: Folder,
+        reply: oneshot::Sender<Result<(), String>>,
+    },
+    RemoveFolder {
+        account_id: String,
+        mailbox_hash: u64,
+        reply: oneshot::Sender<Result<(), String>>,
+    },
+    SetFolderSubscribed {
+        account_id: String,
+        mailbox_hash: u64,
+        subscribed: bool,
+        reply: oneshot::Sender<Result<(), String>>,
+    },
     SaveMessages {
         account_id: String,
         mailbox_hash: u64,
         messages: Vec<MessageSummary>,
         reply: oneshot::Sender<Result<(), String>>,
     },
+    MergeMessages {
+        account_id: String,
+        mailbox_hash: u64,
+        messages: Vec<MessageSummary>,
+        reply: oneshot::Sender<Result<(), String>>,
+    },
     LoadMessages {
         account_id: String,
         mailbox_hash: u64,
@@ -26,17 +59,52 @@ pub(super) enum CacheCmd {
         offset: u32,
         reply: oneshot::Sender<Result<Vec<MessageSummary>, String>>,
     },
+    LoadSyncState {
+        account_id: String,
+        mailbox_hash: u64,
+        reply: oneshot::Sender<Result<Option<MailboxSyncState>, String>>,
+    },
+    ApplyMailboxDelta {
+        account_id: String,
+        mailbox_hash: u64,
+        delta: MailboxDelta,
+        reply: oneshot::Sender<Result<(), String>>,
+    },
     LoadBody {
         account_id: String,
         envelope_hash: u64,
-        reply: oneshot::Sender<Result<Option<(String, String, Vec<AttachmentData>)>, String>>,
+        reply: oneshot::Sender<Result<Option<(String, String, Vec<AttachmentInfo>)>, String>>,
     },
     SaveBody {
         account_id: String,
         envelope_hash: u64,
         body_markdown: String,
         body_plain: String,
-        attachments: Vec<AttachmentData>,
+        attachments: Vec<AttachmentInfo>,
+        reply: oneshot::Sender<Result<(), String>>,
+    },
+    LoadAttachment {
+        account_id: String,
+        envelope_hash: u64,
+        part: String,
+        reply: oneshot::Sender<Result<Option<Vec<u8>>, String>>,
+    },
+    SaveAttachment {
+        account_id: String,
+        envelope_hash: u64,
+        part: String,
+        data: Vec<u8>,
+        reply: oneshot::Sender<Result<(), String>>,
+    },
+    LoadRaw {
+        account_id: String,
+        envelope_hash: u64,
+        reply: oneshot::Sender<Result<Option<Vec<u8>>, String>>,
+    },
+    SaveRaw {
+        account_id: String,
+        envelope_hash: u64,
+        raw: Vec<u8>,
         reply: oneshot::Sender<Result<(), String>>,
     },
     // Phase 2b: dual-truth flag ops
@@ -47,6 +115,13 @@ pub(super) enum CacheCmd {
         pending_op: String,
         reply: oneshot::Sender<Result<(), String>>,
     },
+    UpdateKeywords {
+        account_id: String,
+        envelope_hash: u64,
+        keywords_local: Vec<String>,
+        pending_op: String,
+        reply: oneshot::Sender<Result<(), String>>,
+    },
     ClearPendingOp {
         account_id: String,
         envelope_hash: u64,
@@ -63,6 +138,33 @@ pub(super) enum CacheCmd {
         envelope_hash: u64,
         reply: oneshot::Sender<Result<(), String>>,
     },
+    // Batch forms — each runs in a single transaction
+    UpdateFlagsBatch {
+        account_id: String,
+        updates: Vec<(u64, u8)>,
+        pending_op: String,
+        reply: oneshot::Sender<Result<(), String>>,
+    },
+    ClearPendingOpBatch {
+        account_id: String,
+        updates: Vec<(u64, u8)>,
+        reply: oneshot::Sender<Result<(), String>>,
+    },
+    RemoveMessages {
+        account_id: String,
+        envelope_hashes: Vec<u64>,
+        reply: oneshot::Sender<Result<(), String>>,
+    },
+    Rethread {
+        account_id: String,
+        group_by_subject: bool,
+        reply: oneshot::Sender<Result<usize, String>>,
+    },
+    LoadThread {
+        account_id: String,
+        thread_id: u64,
+        reply: oneshot::Sender<Result<Vec<MessageSummary>, String>>,
+    },
     Search {
         query: String,
         reply: oneshot::Sender<Result<Vec<MessageSummary>, String>>,
@@ -71,4 +173,66 @@ pub(super) enum CacheCmd {
         account_id: String,
         reply: oneshot::Sender<Result<(), String>>,
     },
+    // Outbox
+    QueueOutgoing {
+        account_id: String,
+        email: Box<OutgoingEmail>,
+        append_to_sent: bool,
+        now: i64,
+        send_at: i64,
+        reply: oneshot::Sender<Result<i64, String>>,
+    },
+    LoadOutbox {
+        account_id: String,
+        reply: oneshot::Sender<Result<Vec<OutboxEntry>, String>>,
+    },
+    UpdateOutbox {
+        id: i64,
+        status: OutboxStatus,
+        attempts: u32,
+        last_error: Option<String>,
+        next_attempt_at: i64,
+        reply: oneshot::Sender<Result<(), String>>,
+    },
+    ClaimOutbox {
+        id: i64,
+        reply: oneshot::Sender<Result<bool, String>>,
+    },
+    RequeueInterruptedOutbox {
+        account_id: String,
+        reply: oneshot::Sender<Result<(), String>>,
+    },
+    SetOutboxSent {
+        id: i64,
+        sent: Vec<u8>,
+        reply: oneshot::Sender<Result<(), String>>,
+    },
+    RequeueOutbox {
+        id: i64,
+        now: i64,
+        reply: oneshot::Sender<Result<bool, String>>,
+    },
+    RemoveOutbox {
+        id: i64,
+        reply: oneshot::Sender<Result<(), String>>,
+    },
+    TakeOutbox {
+        id: i64,
+        reply: oneshot::Sender<Result<Option<OutgoingEmail>, String>>,
+    },
+    // Remote content allowlist
+    AllowRemoteContent {
+        account_id: String,
+        rule: RemoteContentRule,
+        reply: oneshot::Sender<Result<(), String>>,
+    },
+    RevokeRemoteContent {
+        account_id: String,
+        rule: RemoteContentRule,
+        reply: oneshot::Sender<Result<(), String>>,
+    },
+    LoadRemoteContentRules {
+        account_id: String,
+        reply: oneshot::Sender<Result<Vec<RemoteContentRule>, String>>,
+    },
 }
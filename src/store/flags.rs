@@ -1,19 +1,118 @@
-/// melib::Flag::SEEN  = 0b0000_0001
-/// melib::Flag::FLAGGED = 0b0100_0000  (but we store our own compact encoding)
-/// We use a simple two-bit encoding for the flags we care about:
-///   bit 0 = SEEN
-///   bit 1 = FLAGGED
+use melib::email::Flag;
+
+use crate::models::MessageSummary;
+
+/// Our compact flag encoding, stored in `flags_server` / `flags_local`.
+/// It is independent of melib's `Flag` bit layout.
+pub const SEEN: u8 = 1 << 0;
+pub const FLAGGED: u8 = 1 << 1;
+pub const ANSWERED: u8 = 1 << 2;
+pub const DRAFT: u8 = 1 << 3;
+/// `\Deleted`: marked for removal on the next expunge.
+pub const DELETED: u8 = 1 << 4;
+/// The `$Forwarded` keyword (RFC 5550), which IMAP has no system flag for.
+pub const FORWARDED: u8 = 1 << 5;
+
+/// Keyword behind [`FORWARDED`]. Set it on the server with
+/// `FlagOp::SetTag(FORWARDED_KEYWORD.into())`.
+pub const FORWARDED_KEYWORD: &str = "$Forwarded";
+
+/// Encode just SEEN and FLAGGED. Prefer [`summary_flags`] when updating an
+/// existing message so its other bits aren't dropped.
 pub fn flags_to_u8(is_read: bool, is_starred: bool) -> u8 {
     let mut f: u8 = 0;
     if is_read {
-        f |= 1;
+        f |= SEEN;
     }
     if is_starred {
-        f |= 2;
+        f |= FLAGGED;
     }
     f
 }
 
 pub fn flags_from_u8(f: u8) -> (bool, bool) {
-    (f & 1 != 0, f & 2 != 0)
+    (f & SEEN != 0, f & FLAGGED != 0)
+}
+
+/// Encode a server flag set. `$Forwarded` among `keywords` sets [`FORWARDED`].
+pub fn imap_flags_to_u8(flags: Flag, keywords: &[String]) -> u8 {
+    let mut f = flags_to_u8(flags.is_seen(), flags.is_flagged());
+    if flags.is_replied() {
+        f |= ANSWERED;
+    }
+    if flags.is_draft() {
+        f |= DRAFT;
+    }
+    if flags.is_trashed() {
+        f |= DELETED;
+    }
+    if keywords
+        .iter()
+        .any(|k| k.eq_ignore_ascii_case(FORWARDED_KEYWORD))
+    {
+        f |= FORWARDED;
+    }
+    f
+}
+
+/// Keywords other than `$Forwarded`, which is carried as a flag bit.
+pub fn user_keywords(keywords: &[String]) -> Vec<String> {
+    keywords
+        .iter()
+        .filter(|k| !k.eq_ignore_ascii_case(FORWARDED_KEYWORD))
+        .cloned()
+        .collect()
+}
+
+/// Encode a summary's flag fields.
+pub fn summary_flags(m: &MessageSummary) -> u8 {
+    let mut f = flags_to_u8(m.is_read, m.is_starred);
+    for (set, bit) in [
+        (m.is_answered, ANSWERED),
+        (m.is_draft, DRAFT),
+        (m.is_deleted, DELETED),
+        (m.is_forwarded, FORWARDED),
+    ] {
+        if set {
+            f |= bit;
+        }
+    }
+    f
+}
+
+/// Set a summary's flag fields from an encoded value.
+pub fn set_summary_flags(m: &mut MessageSummary, f: u8) {
+    (m.is_read, m.is_starred) = flags_from_u8(f);
+    m.is_answered = f & ANSWERED != 0;
+    m.is_draft = f & DRAFT != 0;
+    m.is_deleted = f & DELETED != 0;
+    m.is_forwarded = f & FORWARDED != 0;
+}
+
+/// Keywords are IMAP atoms and can't contain spaces, so a space-joined
+/// string round-trips.
+pub(super) fn keywords_to_text(keywords: &[String]) -> String {
+    keywords.join(" ")
+}
+
+pub(super) fn keywords_from_text(text: Option<&str>) -> Vec<String> {
+    text.unwrap_or_default()
+        .split_whitespace()
+        .map(str::to_string)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use melib::email::Flag;
+
+    use super::{flags_to_u8, imap_flags_to_u8, user_keywords, ANSWERED, DELETED, FORWARDED};
+
+    #[test]
+    fn imap_flags_encode_system_flags_and_forwarded_keyword() {
+        let keywords = vec!["$Label1".to_string(), "$forwarded".to_string()];
+        let f = imap_flags_to_u8(Flag::SEEN | Flag::REPLIED | Flag::TRASHED, &keywords);
+        assert_eq!(f, flags_to_u8(true, false) | ANSWERED | DELETED | FORWARDED);
+        assert_eq!(user_keywords(&keywords), vec!["$Label1".to_string()]);
+    }
 }
@@ -6,7 +6,12 @@ use tokio::sync::{mpsc, oneshot};
 use super::commands::CacheCmd;
 use super::queries;
 use super::schema::{run_migrations, SCHEMA};
-use crate::models::{AttachmentData, Folder, MessageSummary};
+use crate::mime::RemoteContentRule;
+use crate::models::{
+    AttachmentInfo, Folder, MailboxDelta, MailboxSyncState, MessageSummary, OutboxEntry,
+    OutboxStatus,
+};
+use crate::smtp::OutgoingEmail;
 
 // ---------------------------------------------------------------------------
 // CacheHandle — Clone + Send + Sync async facade
@@ -36,6 +41,21 @@ impl CacheHandle {
 
         run_migrations(&conn);
 
+        Self::spawn(conn)
+    }
+
+    /// A throwaway cache for tests.
+    #[cfg(test)]
+    pub(crate) fn open_in_memory() -> Result<Self, String> {
+        let conn =
+            Connection::open_in_memory().map_err(|e| format!("Failed to open cache db: {e}"))?;
+        conn.execute_batch(SCHEMA)
+            .map_err(|e| format!("Failed to init cache schema: {e}"))?;
+        run_migrations(&conn);
+        Self::spawn(conn)
+    }
+
+    fn spawn(conn: Connection) -> Result<Self, String> {
         let (tx, rx) = mpsc::unbounded_channel();
 
         std::thread::Builder::new()
@@ -77,6 +97,70 @@ impl CacheHandle {
         rx.await.map_err(|_| "Cache unavailable".to_string())?
     }
 
+    /// Insert or update a single folder (after create or a subscription change).
+    pub async fn upsert_folder(&self, account_id: String, folder: Folder) -> Result<(), String> {
+        let (reply, rx) = oneshot::channel();
+        self.tx
+            .send(CacheCmd::UpsertFolder {
+                account_id,
+                folder,
+                reply,
+            })
+            .map_err(|_| "Cache unavailable".to_string())?;
+        rx.await.map_err(|_| "Cache unavailable".to_string())?
+    }
+
+    /// Replace a renamed folder and re-point its cached messages at the new mailbox hash.
+    pub async fn rename_folder(
+        &self,
+        account_id: String,
+        old_mailbox_hash: u64,
+        folder: Folder,
+    ) -> Result<(), String> {
+        let (reply, rx) = oneshot::channel();
+        self.tx
+            .send(CacheCmd::RenameFolder {
+                account_id,
+                old_mailbox_hash,
+                folder,
+                reply,
+            })
+            .map_err(|_| "Cache unavailable".to_string())?;
+        rx.await.map_err(|_| "Cache unavailable".to_string())?
+    }
+
+    /// Remove a deleted folder along with its cached messages and attachments.
+    pub async fn remove_folder(&self, account_id: String, mailbox_hash: u64) -> Result<(), String> {
+        let (reply, rx) = oneshot::channel();
+        self.tx
+            .send(CacheCmd::RemoveFolder {
+                account_id,
+                mailbox_hash,
+                reply,
+            })
+            .map_err(|_| "Cache unavailable".to_string())?;
+        rx.await.map_err(|_| "Cache unavailable".to_string())?
+    }
+
+    /// Record a folder's subscription state.
+    pub async fn set_folder_subscribed(
+        &self,
+        account_id: String,
+        mailbox_hash: u64,
+        subscribed: bool,
+    ) -> Result<(), String> {
+        let (reply, rx) = oneshot::channel();
+        self.tx
+            .send(CacheCmd::SetFolderSubscribed {
+                account_id,
+                mailbox_hash,
+                subscribed,
+                reply,
+            })
+            .map_err(|_| "Cache unavailable".to_string())?;
+        rx.await.map_err(|_| "Cache unavailable".to_string())?
+    }
+
     pub async fn save_messages(
         &self,
         account_id: String,
@@ -95,6 +179,27 @@ impl CacheHandle {
         rx.await.map_err(|_| "Cache unavailable".to_string())?
     }
 
+    /// Store one fetched window of a mailbox without deleting cached
+    /// messages outside it. Use with
+    /// [`ImapSession::fetch_messages_page`](crate::imap::ImapSession::fetch_messages_page).
+    pub async fn merge_messages(
+        &self,
+        account_id: String,
+        mailbox_hash: u64,
+        messages: Vec<MessageSummary>,
+    ) -> Result<(), String> {
+        let (reply, rx) = oneshot::channel();
+        self.tx
+            .send(CacheCmd::MergeMessages {
+                account_id,
+                mailbox_hash,
+                messages,
+                reply,
+            })
+            .map_err(|_| "Cache unavailable".to_string())?;
+        rx.await.map_err(|_| "Cache unavailable".to_string())?
+    }
+
     pub async fn load_messages(
         &self,
         account_id: String,
@@ -115,11 +220,49 @@ impl CacheHandle {
         rx.await.map_err(|_| "Cache unavailable".to_string())?
     }
 
+    /// Sync checkpoint saved by the last [`apply_mailbox_delta`](Self::apply_mailbox_delta),
+    /// to pass to [`ImapSession::sync_mailbox`](crate::imap::ImapSession::sync_mailbox).
+    pub async fn load_sync_state(
+        &self,
+        account_id: String,
+        mailbox_hash: u64,
+    ) -> Result<Option<MailboxSyncState>, String> {
+        let (reply, rx) = oneshot::channel();
+        self.tx
+            .send(CacheCmd::LoadSyncState {
+                account_id,
+                mailbox_hash,
+                reply,
+            })
+            .map_err(|_| "Cache unavailable".to_string())?;
+        rx.await.map_err(|_| "Cache unavailable".to_string())?
+    }
+
+    /// Apply inserts, flag changes and expunges from an incremental sync and
+    /// save its sync state, in one transaction.
+    pub async fn apply_mailbox_delta(
+        &self,
+        account_id: String,
+        mailbox_hash: u64,
+        delta: MailboxDelta,
+    ) -> Result<(), String> {
+        let (reply, rx) = oneshot::channel();
+        self.tx
+            .send(CacheCmd::ApplyMailboxDelta {
+                account_id,
+                mailbox_hash,
+                delta,
+                reply,
+            })
+            .map_err(|_| "Cache unavailable".to_string())?;
+        rx.await.map_err(|_| "Cache unavailable".to_string())?
+    }
+
     pub async fn load_body(
         &self,
         account_id: String,
         envelope_hash: u64,
-    ) -> Result<Option<(String, String, Vec<AttachmentData>)>, String> {
+    ) -> Result<Option<(String, String, Vec<AttachmentInfo>)>, String> {
         let (reply, rx) = oneshot::channel();
         self.tx
             .send(CacheCmd::LoadBody {
@@ -137,7 +280,7 @@ impl CacheHandle {
         envelope_hash: u64,
         body_markdown: String,
         body_plain: String,
-        attachments: Vec<AttachmentData>,
+        attachments: Vec<AttachmentInfo>,
     ) -> Result<(), String> {
         let (reply, rx) = oneshot::channel();
         self.tx
@@ -153,6 +296,88 @@ impl CacheHandle {
         rx.await.map_err(|_| "Cache unavailable".to_string())?
     }
 
+    /// Load an attachment's downloaded bytes, if they were saved with
+    /// [`save_attachment`](Self::save_attachment). `part` is
+    /// `AttachmentInfo::part`.
+    pub async fn load_attachment(
+        &self,
+        account_id: String,
+        envelope_hash: u64,
+        part: String,
+    ) -> Result<Option<Vec<u8>>, String> {
+        let (reply, rx) = oneshot::channel();
+        self.tx
+            .send(CacheCmd::LoadAttachment {
+                account_id,
+                envelope_hash,
+                part,
+                reply,
+            })
+            .map_err(|_| "Cache unavailable".to_string())?;
+        rx.await.map_err(|_| "Cache unavailable".to_string())?
+    }
+
+    /// Cache an attachment's bytes (from `ImapSession::fetch_attachment`).
+    /// Kept across [`save_body`](Self::save_body); removed with the message.
+    pub async fn save_attachment(
+        &self,
+        account_id: String,
+        envelope_hash: u64,
+        part: String,
+        data: Vec<u8>,
+    ) -> Result<(), String> {
+        let (reply, rx) = oneshot::channel();
+        self.tx
+            .send(CacheCmd::SaveAttachment {
+                account_id,
+                envelope_hash,
+                part,
+                data,
+                reply,
+            })
+            .map_err(|_| "Cache unavailable".to_string())?;
+        rx.await.map_err(|_| "Cache unavailable".to_string())?
+    }
+
+    /// Load a message's cached raw source, if it was saved with
+    /// [`save_raw`](Self::save_raw).
+    pub async fn load_raw(
+        &self,
+        account_id: String,
+        envelope_hash: u64,
+    ) -> Result<Option<Vec<u8>>, String> {
+        let (reply, rx) = oneshot::channel();
+        self.tx
+            .send(CacheCmd::LoadRaw {
+                account_id,
+                envelope_hash,
+                reply,
+            })
+            .map_err(|_| "Cache unavailable".to_string())?;
+        rx.await.map_err(|_| "Cache unavailable".to_string())?
+    }
+
+    /// Cache a message's raw source (from `ImapSession::fetch_raw`). Optional:
+    /// sources are large, so save them only when the user asks for one.
+    /// Removed along with the message.
+    pub async fn save_raw(
+        &self,
+        account_id: String,
+        envelope_hash: u64,
+        raw: Vec<u8>,
+    ) -> Result<(), String> {
+        let (reply, rx) = oneshot::channel();
+        self.tx
+            .send(CacheCmd::SaveRaw {
+                account_id,
+                envelope_hash,
+                raw,
+                reply,
+            })
+            .map_err(|_| "Cache unavailable".to_string())?;
+        rx.await.map_err(|_| "Cache unavailable".to_string())?
+    }
+
     /// Set local flags and mark a pending operation.
     pub async fn update_flags(
         &self,
@@ -174,7 +399,30 @@ impl CacheHandle {
         rx.await.map_err(|_| "Cache unavailable".to_string())?
     }
 
-    /// IMAP op succeeded — update server flags and clear pending.
+    /// Set local keywords (other than `$Forwarded`, which is a flag bit) and
+    /// mark a pending operation.
+    pub async fn update_keywords(
+        &self,
+        account_id: String,
+        envelope_hash: u64,
+        keywords_local: Vec<String>,
+        pending_op: String,
+    ) -> Result<(), String> {
+        let (reply, rx) = oneshot::channel();
+        self.tx
+            .send(CacheCmd::UpdateKeywords {
+                account_id,
+                envelope_hash,
+                keywords_local,
+                pending_op,
+                reply,
+            })
+            .map_err(|_| "Cache unavailable".to_string())?;
+        rx.await.map_err(|_| "Cache unavailable".to_string())?
+    }
+
+    /// IMAP op succeeded — update server flags, adopt local keywords and
+    /// clear pending.
     pub async fn clear_pending_op(
         &self,
         account_id: String,
@@ -193,7 +441,7 @@ impl CacheHandle {
         rx.await.map_err(|_| "Cache unavailable".to_string())?
     }
 
-    /// IMAP op failed — revert local flags to server flags, clear pending.
+    /// IMAP op failed — revert local flags and keywords to the server's, clear pending.
     pub async fn revert_pending_op(
         &self,
         account_id: String,
@@ -227,6 +475,61 @@ impl CacheHandle {
         rx.await.map_err(|_| "Cache unavailable".to_string())?
     }
 
+    /// Batch form of [`update_flags`](Self::update_flags): `(envelope_hash, flags_local)`
+    /// pairs applied in one transaction with the same pending op.
+    pub async fn update_flags_batch(
+        &self,
+        account_id: String,
+        updates: Vec<(u64, u8)>,
+        pending_op: String,
+    ) -> Result<(), String> {
+        let (reply, rx) = oneshot::channel();
+        self.tx
+            .send(CacheCmd::UpdateFlagsBatch {
+                account_id,
+                updates,
+                pending_op,
+                reply,
+            })
+            .map_err(|_| "Cache unavailable".to_string())?;
+        rx.await.map_err(|_| "Cache unavailable".to_string())?
+    }
+
+    /// Batch form of [`clear_pending_op`](Self::clear_pending_op):
+    /// `(envelope_hash, flags_server)` pairs applied in one transaction.
+    pub async fn clear_pending_op_batch(
+        &self,
+        account_id: String,
+        updates: Vec<(u64, u8)>,
+    ) -> Result<(), String> {
+        let (reply, rx) = oneshot::channel();
+        self.tx
+            .send(CacheCmd::ClearPendingOpBatch {
+                account_id,
+                updates,
+                reply,
+            })
+            .map_err(|_| "Cache unavailable".to_string())?;
+        rx.await.map_err(|_| "Cache unavailable".to_string())?
+    }
+
+    /// Remove many messages from the cache in one transaction (after a batch move).
+    pub async fn remove_messages(
+        &self,
+        account_id: String,
+        envelope_hashes: Vec<u64>,
+    ) -> Result<(), String> {
+        let (reply, rx) = oneshot::channel();
+        self.tx
+            .send(CacheCmd::RemoveMessages {
+                account_id,
+                envelope_hashes,
+                reply,
+            })
+            .map_err(|_| "Cache unavailable".to_string())?;
+        rx.await.map_err(|_| "Cache unavailable".to_string())?
+    }
+
     /// Remove all cached data for an account (folders, messages, attachments).
     pub async fn remove_account(&self, account_id: String) -> Result<(), String> {
         let (reply, rx) = oneshot::channel();
@@ -236,6 +539,44 @@ impl CacheHandle {
         rx.await.map_err(|_| "Cache unavailable".to_string())?
     }
 
+    /// Rebuild conversation threads for an account from everything cached
+    /// in all of its folders. Run after syncing; with `group_by_subject`,
+    /// unlinked messages with the same base subject join one thread.
+    /// Returns the number of messages whose placement changed.
+    pub async fn rethread(
+        &self,
+        account_id: String,
+        group_by_subject: bool,
+    ) -> Result<usize, String> {
+        let (reply, rx) = oneshot::channel();
+        self.tx
+            .send(CacheCmd::Rethread {
+                account_id,
+                group_by_subject,
+                reply,
+            })
+            .map_err(|_| "Cache unavailable".to_string())?;
+        rx.await.map_err(|_| "Cache unavailable".to_string())?
+    }
+
+    /// Load one conversation, oldest first. Build the tree from each
+    /// message's `thread_parent`.
+    pub async fn load_thread(
+        &self,
+        account_id: String,
+        thread_id: u64,
+    ) -> Result<Vec<MessageSummary>, String> {
+        let (reply, rx) = oneshot::channel();
+        self.tx
+            .send(CacheCmd::LoadThread {
+                account_id,
+                thread_id,
+                reply,
+            })
+            .map_err(|_| "Cache unavailable".to_string())?;
+        rx.await.map_err(|_| "Cache unavailable".to_string())?
+    }
+
     /// Full-text search across all folders.
     pub async fn search(&self, query: String) -> Result<Vec<MessageSummary>, String> {
         let (reply, rx) = oneshot::channel();
@@ -244,6 +585,169 @@ impl CacheHandle {
             .map_err(|_| "Cache unavailable".to_string())?;
         rx.await.map_err(|_| "Cache unavailable".to_string())?
     }
+
+    // -- outbox --------------------------------------------------------------
+
+    /// Save a message to the outbox, held until `send_at` (unix seconds,
+    /// like `now`). Returns its outbox id.
+    pub async fn queue_outgoing(
+        &self,
+        account_id: String,
+        email: OutgoingEmail,
+        append_to_sent: bool,
+        now: i64,
+        send_at: i64,
+    ) -> Result<i64, String> {
+        let (reply, rx) = oneshot::channel();
+        self.tx
+            .send(CacheCmd::QueueOutgoing {
+                account_id,
+                email: Box::new(email),
+                append_to_sent,
+                now,
+                send_at,
+                reply,
+            })
+            .map_err(|_| "Cache unavailable".to_string())?;
+        rx.await.map_err(|_| "Cache unavailable".to_string())?
+    }
+
+    /// Every outbox entry of an account, oldest first.
+    pub async fn load_outbox(&self, account_id: String) -> Result<Vec<OutboxEntry>, String> {
+        let (reply, rx) = oneshot::channel();
+        self.tx
+            .send(CacheCmd::LoadOutbox { account_id, reply })
+            .map_err(|_| "Cache unavailable".to_string())?;
+        rx.await.map_err(|_| "Cache unavailable".to_string())?
+    }
+
+    /// Record the outcome of a send attempt.
+    pub async fn update_outbox(
+        &self,
+        id: i64,
+        status: OutboxStatus,
+        attempts: u32,
+        last_error: Option<String>,
+        next_attempt_at: i64,
+    ) -> Result<(), String> {
+        let (reply, rx) = oneshot::channel();
+        self.tx
+            .send(CacheCmd::UpdateOutbox {
+                id,
+                status,
+                attempts,
+                last_error,
+                next_attempt_at,
+                reply,
+            })
+            .map_err(|_| "Cache unavailable".to_string())?;
+        rx.await.map_err(|_| "Cache unavailable".to_string())?
+    }
+
+    /// Claim a queued or scheduled entry for sending. Returns `false` if it
+    /// was removed or claimed in the meantime.
+    pub async fn claim_outbox(&self, id: i64) -> Result<bool, String> {
+        let (reply, rx) = oneshot::channel();
+        self.tx
+            .send(CacheCmd::ClaimOutbox { id, reply })
+            .map_err(|_| "Cache unavailable".to_string())?;
+        rx.await.map_err(|_| "Cache unavailable".to_string())?
+    }
+
+    /// Queue entries a previous run left mid-send again.
+    pub async fn requeue_interrupted_outbox(&self, account_id: String) -> Result<(), String> {
+        let (reply, rx) = oneshot::channel();
+        self.tx
+            .send(CacheCmd::RequeueInterruptedOutbox { account_id, reply })
+            .map_err(|_| "Cache unavailable".to_string())?;
+        rx.await.map_err(|_| "Cache unavailable".to_string())?
+    }
+
+    /// Mark an entry as accepted by the server, keeping the sent bytes
+    /// until they're appended to the Sent folder.
+    pub async fn set_outbox_sent(&self, id: i64, sent: Vec<u8>) -> Result<(), String> {
+        let (reply, rx) = oneshot::channel();
+        self.tx
+            .send(CacheCmd::SetOutboxSent { id, sent, reply })
+            .map_err(|_| "Cache unavailable".to_string())?;
+        rx.await.map_err(|_| "Cache unavailable".to_string())?
+    }
+
+    /// Queue a failed entry again. Returns `false` if it wasn't failed.
+    pub async fn requeue_outbox(&self, id: i64, now: i64) -> Result<bool, String> {
+        let (reply, rx) = oneshot::channel();
+        self.tx
+            .send(CacheCmd::RequeueOutbox { id, now, reply })
+            .map_err(|_| "Cache unavailable".to_string())?;
+        rx.await.map_err(|_| "Cache unavailable".to_string())?
+    }
+
+    /// Delete an outbox entry.
+    pub async fn remove_outbox(&self, id: i64) -> Result<(), String> {
+        let (reply, rx) = oneshot::channel();
+        self.tx
+            .send(CacheCmd::RemoveOutbox { id, reply })
+            .map_err(|_| "Cache unavailable".to_string())?;
+        rx.await.map_err(|_| "Cache unavailable".to_string())?
+    }
+
+    /// Take a message back out of the outbox (to edit or discard it).
+    /// `None` if it's gone, currently being sent, or already delivered.
+    pub async fn take_outbox(&self, id: i64) -> Result<Option<OutgoingEmail>, String> {
+        let (reply, rx) = oneshot::channel();
+        self.tx
+            .send(CacheCmd::TakeOutbox { id, reply })
+            .map_err(|_| "Cache unavailable".to_string())?;
+        rx.await.map_err(|_| "Cache unavailable".to_string())?
+    }
+
+    // -- remote content --------------------------------------------------------
+
+    /// Always load remote content from a sender or domain.
+    pub async fn allow_remote_content(
+        &self,
+        account_id: String,
+        rule: RemoteContentRule,
+    ) -> Result<(), String> {
+        let (reply, rx) = oneshot::channel();
+        self.tx
+            .send(CacheCmd::AllowRemoteContent {
+                account_id,
+                rule,
+                reply,
+            })
+            .map_err(|_| "Cache unavailable".to_string())?;
+        rx.await.map_err(|_| "Cache unavailable".to_string())?
+    }
+
+    pub async fn revoke_remote_content(
+        &self,
+        account_id: String,
+        rule: RemoteContentRule,
+    ) -> Result<(), String> {
+        let (reply, rx) = oneshot::channel();
+        self.tx
+            .send(CacheCmd::RevokeRemoteContent {
+                account_id,
+                rule,
+                reply,
+            })
+            .map_err(|_| "Cache unavailable".to_string())?;
+        rx.await.map_err(|_| "Cache unavailable".to_string())?
+    }
+
+    /// The account's allowlist; wrap it in a
+    /// [`RemoteContentPolicy`](crate::mime::RemoteContentPolicy).
+    pub async fn load_remote_content_rules(
+        &self,
+        account_id: String,
+    ) -> Result<Vec<RemoteContentRule>, String> {
+        let (reply, rx) = oneshot::channel();
+        self.tx
+            .send(CacheCmd::LoadRemoteContentRules { account_id, reply })
+            .map_err(|_| "Cache unavailable".to_string())?;
+        rx.await.map_err(|_| "Cache unavailable".to_string())?
+    }
 }
 
 // -- background thread ---------------------------------------------------
@@ -261,6 +765,46 @@ fn run_loop(conn: Connection, mut rx: mpsc::UnboundedReceiver<CacheCmd>) {
             CacheCmd::LoadFolders { account_id, reply } => {
                 let _ = reply.send(queries::do_load_folders(&conn, &account_id));
             }
+            CacheCmd::UpsertFolder {
+                account_id,
+                folder,
+                reply,
+            } => {
+                let _ = reply.send(queries::do_upsert_folder(&conn, &account_id, &folder));
+            }
+            CacheCmd::RenameFolder {
+                account_id,
+                old_mailbox_hash,
+                folder,
+                reply,
+            } => {
+                let _ = reply.send(queries::do_rename_folder(
+                    &conn,
+                    &account_id,
+                    old_mailbox_hash,
+                    &folder,
+                ));
+            }
+            CacheCmd::RemoveFolder {
+                account_id,
+                mailbox_hash,
+                reply,
+            } => {
+                let _ = reply.send(queries::do_remove_folder(&conn, &account_id, mailbox_hash));
+            }
+            CacheCmd::SetFolderSubscribed {
+                account_id,
+                mailbox_hash,
+                subscribed,
+                reply,
+            } => {
+                let _ = reply.send(queries::do_set_folder_subscribed(
+                    &conn,
+                    &account_id,
+                    mailbox_hash,
+                    subscribed,
+                ));
+            }
             CacheCmd::SaveMessages {
                 account_id,
                 mailbox_hash,
@@ -274,6 +818,19 @@ fn run_loop(conn: Connection, mut rx: mpsc::UnboundedReceiver<CacheCmd>) {
                     &messages,
                 ));
             }
+            CacheCmd::MergeMessages {
+                account_id,
+                mailbox_hash,
+                messages,
+                reply,
+            } => {
+                let _ = reply.send(queries::do_merge_messages(
+                    &conn,
+                    &account_id,
+                    mailbox_hash,
+                    &messages,
+                ));
+            }
             CacheCmd::LoadMessages {
                 account_id,
                 mailbox_hash,
@@ -289,6 +846,30 @@ fn run_loop(conn: Connection, mut rx: mpsc::UnboundedReceiver<CacheCmd>) {
                     offset,
                 ));
             }
+            CacheCmd::LoadSyncState {
+                account_id,
+                mailbox_hash,
+                reply,
+            } => {
+                let _ = reply.send(queries::do_load_sync_state(
+                    &conn,
+                    &account_id,
+                    mailbox_hash,
+                ));
+            }
+            CacheCmd::ApplyMailboxDelta {
+                account_id,
+                mailbox_hash,
+                delta,
+                reply,
+            } => {
+                let _ = reply.send(queries::do_apply_mailbox_delta(
+                    &conn,
+                    &account_id,
+                    mailbox_hash,
+                    &delta,
+                ));
+            }
             CacheCmd::LoadBody {
                 account_id,
                 envelope_hash,
@@ -313,6 +894,54 @@ fn run_loop(conn: Connection, mut rx: mpsc::UnboundedReceiver<CacheCmd>) {
                     &attachments,
                 ));
             }
+            CacheCmd::LoadAttachment {
+                account_id,
+                envelope_hash,
+                part,
+                reply,
+            } => {
+                let _ = reply.send(queries::do_load_attachment(
+                    &conn,
+                    &account_id,
+                    envelope_hash,
+                    &part,
+                ));
+            }
+            CacheCmd::SaveAttachment {
+                account_id,
+                envelope_hash,
+                part,
+                data,
+                reply,
+            } => {
+                let _ = reply.send(queries::do_save_attachment(
+                    &conn,
+                    &account_id,
+                    envelope_hash,
+                    &part,
+                    &data,
+                ));
+            }
+            CacheCmd::LoadRaw {
+                account_id,
+                envelope_hash,
+                reply,
+            } => {
+                let _ = reply.send(queries::do_load_raw(&conn, &account_id, envelope_hash));
+            }
+            CacheCmd::SaveRaw {
+                account_id,
+                envelope_hash,
+                raw,
+                reply,
+            } => {
+                let _ = reply.send(queries::do_save_raw(
+                    &conn,
+                    &account_id,
+                    envelope_hash,
+                    &raw,
+                ));
+            }
             CacheCmd::UpdateFlags {
                 account_id,
                 envelope_hash,
@@ -328,6 +957,21 @@ fn run_loop(conn: Connection, mut rx: mpsc::UnboundedReceiver<CacheCmd>) {
                     &pending_op,
                 ));
             }
+            CacheCmd::UpdateKeywords {
+                account_id,
+                envelope_hash,
+                keywords_local,
+                pending_op,
+                reply,
+            } => {
+                let _ = reply.send(queries::do_update_keywords(
+                    &conn,
+                    &account_id,
+                    envelope_hash,
+                    &keywords_local,
+                    &pending_op,
+                ));
+            }
             CacheCmd::ClearPendingOp {
                 account_id,
                 envelope_hash,
@@ -363,12 +1007,137 @@ fn run_loop(conn: Connection, mut rx: mpsc::UnboundedReceiver<CacheCmd>) {
                     envelope_hash,
                 ));
             }
+            CacheCmd::UpdateFlagsBatch {
+                account_id,
+                updates,
+                pending_op,
+                reply,
+            } => {
+                let _ = reply.send(queries::do_update_flags_batch(
+                    &conn,
+                    &account_id,
+                    &updates,
+                    &pending_op,
+                ));
+            }
+            CacheCmd::ClearPendingOpBatch {
+                account_id,
+                updates,
+                reply,
+            } => {
+                let _ = reply.send(queries::do_clear_pending_op_batch(
+                    &conn,
+                    &account_id,
+                    &updates,
+                ));
+            }
+            CacheCmd::RemoveMessages {
+                account_id,
+                envelope_hashes,
+                reply,
+            } => {
+                let _ = reply.send(queries::do_remove_messages(
+                    &conn,
+                    &account_id,
+                    &envelope_hashes,
+                ));
+            }
+            CacheCmd::Rethread {
+                account_id,
+                group_by_subject,
+                reply,
+            } => {
+                let _ = reply.send(queries::do_rethread_account(
+                    &conn,
+                    &account_id,
+                    group_by_subject,
+                ));
+            }
+            CacheCmd::LoadThread {
+                account_id,
+                thread_id,
+                reply,
+            } => {
+                let _ = reply.send(queries::do_load_thread(&conn, &account_id, thread_id));
+            }
             CacheCmd::Search { query, reply } => {
                 let _ = reply.send(queries::do_search(&conn, &query));
             }
             CacheCmd::RemoveAccount { account_id, reply } => {
                 let _ = reply.send(queries::do_remove_account(&conn, &account_id));
             }
+            CacheCmd::QueueOutgoing {
+                account_id,
+                email,
+                append_to_sent,
+                now,
+                send_at,
+                reply,
+            } => {
+                let _ = reply.send(queries::do_queue_outgoing(
+                    &conn,
+                    &account_id,
+                    &email,
+                    append_to_sent,
+                    now,
+                    send_at,
+                ));
+            }
+            CacheCmd::LoadOutbox { account_id, reply } => {
+                let _ = reply.send(queries::do_load_outbox(&conn, &account_id));
+            }
+            CacheCmd::UpdateOutbox {
+                id,
+                status,
+                attempts,
+                last_error,
+                next_attempt_at,
+                reply,
+            } => {
+                let _ = reply.send(queries::do_update_outbox(
+                    &conn,
+                    id,
+                    status,
+                    attempts,
+                    last_error.as_deref(),
+                    next_attempt_at,
+                ));
+            }
+            CacheCmd::ClaimOutbox { id, reply } => {
+                let _ = reply.send(queries::do_claim_outbox(&conn, id));
+            }
+            CacheCmd::RequeueInterruptedOutbox { account_id, reply } => {
+                let _ = reply.send(queries::do_requeue_interrupted_outbox(&conn, &account_id));
+            }
+            CacheCmd::SetOutboxSent { id, sent, reply } => {
+                let _ = reply.send(queries::do_set_outbox_sent(&conn, id, &sent));
+            }
+            CacheCmd::RequeueOutbox { id, now, reply } => {
+                let _ = reply.send(queries::do_requeue_outbox(&conn, id, now));
+            }
+            CacheCmd::RemoveOutbox { id, reply } => {
+                let _ = reply.send(queries::do_remove_outbox(&conn, id));
+            }
+            CacheCmd::TakeOutbox { id, reply } => {
+                let _ = reply.send(queries::do_take_outbox(&conn, id));
+            }
+            CacheCmd::AllowRemoteContent {
+                account_id,
+                rule,
+                reply,
+            } => {
+                let _ = reply.send(queries::do_allow_remote_content(&conn, &account_id, &rule));
+            }
+            CacheCmd::RevokeRemoteContent {
+                account_id,
+                rule,
+                reply,
+            } => {
+                let _ = reply.send(queries::do_revoke_remote_content(&conn, &account_id, &rule));
+            }
+            CacheCmd::LoadRemoteContentRules { account_id, reply } => {
+                let _ = reply.send(queries::do_load_remote_content_rules(&conn, &account_id));
+            }
         }
     }
     log::debug!("Cache thread exiting");
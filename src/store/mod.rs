@@ -4,7 +4,10 @@ mod handle;
 mod queries;
 mod schema;
 
-pub use flags::{flags_from_u8, flags_to_u8};
+pub use flags::{
+    flags_from_u8, flags_to_u8, imap_flags_to_u8, set_summary_flags, summary_flags, user_keywords,
+    ANSWERED, DELETED, DRAFT, FLAGGED, FORWARDED, FORWARDED_KEYWORD, SEEN,
+};
 pub use handle::CacheHandle;
 
 /// Public constant for the default page size.
@@ -1,7 +1,17 @@
+use std::collections::{HashMap, HashSet};
+
 use rusqlite::Connection;
 
-use super::flags::{flags_from_u8, flags_to_u8};
-use crate::models::{AttachmentData, Folder, MessageSummary};
+use super::flags::{
+    flags_from_u8, keywords_from_text, keywords_to_text, set_summary_flags, summary_flags,
+};
+use crate::mime::RemoteContentRule;
+use crate::models::{
+    sort_folders, AttachmentInfo, EmailAddress, Folder, FolderRole, MailboxDelta, MailboxSyncState,
+    MessageAddresses, MessageSummary, OutboxEntry, OutboxStatus,
+};
+use crate::smtp::OutgoingEmail;
+use crate::threading::{thread_messages, ThreadInput};
 
 /// Shared row-to-struct mapping for both `do_load_messages` and `do_search`.
 ///
@@ -10,7 +20,8 @@ use crate::models::{AttachmentData, Folder, MessageSummary};
 ///   5: is_read, 6: is_starred, 7: has_attachments, 8: thread_id,
 ///   9: flags_server, 10: flags_local, 11: pending_op, 12: mailbox_hash,
 ///   13: message_id, 14: in_reply_to, 15: thread_depth, 16: reply_to,
-///   17: recipient
+///   17: recipient, 18: keywords_server, 19: keywords_local, 20: refs,
+///   21: thread_parent
 fn row_to_summary(row: &rusqlite::Row<'_>) -> rusqlite::Result<MessageSummary> {
     let envelope_hash: i64 = row.get(0)?;
     let thread_id: Option<i64> = row.get(8)?;
@@ -19,32 +30,41 @@ fn row_to_summary(row: &rusqlite::Row<'_>) -> rusqlite::Result<MessageSummary> {
     let pending_op: Option<String> = row.get(11)?;
     let mbox_hash: i64 = row.get(12)?;
 
-    // Dual-truth: if pending_op is set, use flags_local; otherwise flags_server
-    let effective_flags = if pending_op.is_some() {
-        flags_local as u8
+    // Dual-truth: if pending_op is set, use the local columns; otherwise the server ones
+    let (effective_flags, keywords_column) = if pending_op.is_some() {
+        (flags_local as u8, 19)
     } else {
-        flags_server as u8
+        (flags_server as u8, 18)
     };
-    let (is_read, is_starred) = flags_from_u8(effective_flags);
+    let keywords = keywords_from_text(row.get::<_, Option<String>>(keywords_column)?.as_deref());
 
-    Ok(MessageSummary {
+    let mut summary = MessageSummary {
         uid: envelope_hash as u64,
         subject: row.get(1)?,
         from: row.get(2)?,
         to: row.get::<_, Option<String>>(17)?.unwrap_or_default(),
         date: row.get(3)?,
         timestamp: row.get(4)?,
-        is_read,
-        is_starred,
+        keywords,
         has_attachments: row.get::<_, i32>(7)? != 0,
         thread_id: thread_id.map(|t| t as u64),
+        thread_parent: row.get::<_, Option<i64>>(21)?.map(|p| p as u64),
         envelope_hash: envelope_hash as u64,
         mailbox_hash: mbox_hash as u64,
         message_id: row.get::<_, Option<String>>(13)?.unwrap_or_default(),
         in_reply_to: row.get(14)?,
+        references: row
+            .get::<_, Option<String>>(20)?
+            .unwrap_or_default()
+            .split_whitespace()
+            .map(str::to_string)
+            .collect(),
         thread_depth: row.get::<_, Option<u32>>(15)?.unwrap_or(0),
         reply_to: row.get(16)?,
-    })
+        ..Default::default()
+    };
+    set_summary_flags(&mut summary, effective_flags);
+    Ok(summary)
 }
 
 pub(super) fn do_save_folders(
@@ -59,13 +79,15 @@ pub(super) fn do_save_folders(
     // Upsert each folder — updates counts if already present, inserts if new.
     let mut stmt = tx
         .prepare(
-            "INSERT INTO folders (account_id, path, name, mailbox_hash, unread_count, total_count)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+            "INSERT INTO folders (account_id, path, name, mailbox_hash, unread_count, total_count, subscribed, role)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
              ON CONFLICT(account_id, path) DO UPDATE SET
                  name = excluded.name,
                  mailbox_hash = excluded.mailbox_hash,
                  unread_count = excluded.unread_count,
-                 total_count = excluded.total_count",
+                 total_count = excluded.total_count,
+                 subscribed = excluded.subscribed,
+                 role = excluded.role",
         )
         .map_err(|e| format!("Cache prepare error: {e}"))?;
 
@@ -77,6 +99,8 @@ pub(super) fn do_save_folders(
             f.mailbox_hash as i64,
             f.unread_count,
             f.total_count,
+            f.is_subscribed as i32,
+            f.role.map(|r| r.as_str()),
         ])
         .map_err(|e| format!("Cache insert error: {e}"))?;
     }
@@ -142,7 +166,7 @@ pub(super) fn do_save_folders(
 pub(super) fn do_load_folders(conn: &Connection, account_id: &str) -> Result<Vec<Folder>, String> {
     let mut stmt = conn
         .prepare(
-            "SELECT path, name, mailbox_hash, unread_count, total_count FROM folders
+            "SELECT path, name, mailbox_hash, unread_count, total_count, subscribed, role FROM folders
              WHERE account_id = ?1",
         )
         .map_err(|e| format!("Cache prepare error: {e}"))?;
@@ -155,6 +179,10 @@ pub(super) fn do_load_folders(conn: &Connection, account_id: &str) -> Result<Vec
                 mailbox_hash: row.get::<_, i64>(2)? as u64,
                 unread_count: row.get(3)?,
                 total_count: row.get(4)?,
+                is_subscribed: row.get::<_, Option<i32>>(5)?.unwrap_or(1) != 0,
+                role: row
+                    .get::<_, Option<String>>(6)?
+                    .and_then(|r| FolderRole::parse(&r)),
             })
         })
         .map_err(|e| format!("Cache query error: {e}"))?;
@@ -164,20 +192,142 @@ pub(super) fn do_load_folders(conn: &Connection, account_id: &str) -> Result<Vec
         folders.push(row.map_err(|e| format!("Cache row error: {e}"))?);
     }
 
-    // Sort: INBOX first, then alphabetical
-    folders.sort_by(|a, b| {
-        if a.path == "INBOX" {
-            std::cmp::Ordering::Less
-        } else if b.path == "INBOX" {
-            std::cmp::Ordering::Greater
-        } else {
-            a.path.cmp(&b.path)
-        }
-    });
+    sort_folders(&mut folders);
 
     Ok(folders)
 }
 
+// -- Folder management ----------------------------------------------------
+
+pub(super) fn do_upsert_folder(
+    conn: &Connection,
+    account_id: &str,
+    folder: &Folder,
+) -> Result<(), String> {
+    conn.execute(
+        "INSERT INTO folders (account_id, path, name, mailbox_hash, unread_count, total_count, subscribed, role)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+         ON CONFLICT(account_id, path) DO UPDATE SET
+             name = excluded.name,
+             mailbox_hash = excluded.mailbox_hash,
+             unread_count = excluded.unread_count,
+             total_count = excluded.total_count,
+             subscribed = excluded.subscribed,
+             role = excluded.role",
+        rusqlite::params![
+            account_id,
+            folder.path,
+            folder.name,
+            folder.mailbox_hash as i64,
+            folder.unread_count,
+            folder.total_count,
+            folder.is_subscribed as i32,
+            folder.role.map(|r| r.as_str()),
+        ],
+    )
+    .map_err(|e| format!("Cache upsert_folder error: {e}"))?;
+    Ok(())
+}
+
+pub(super) fn do_rename_folder(
+    conn: &Connection,
+    account_id: &str,
+    old_mailbox_hash: u64,
+    folder: &Folder,
+) -> Result<(), String> {
+    let tx = conn
+        .unchecked_transaction()
+        .map_err(|e| format!("Cache tx error: {e}"))?;
+
+    // Insert the new row first so re-pointed messages always have a parent folder.
+    do_upsert_folder(&tx, account_id, folder)?;
+
+    tx.execute(
+        "UPDATE messages SET mailbox_hash = ?1 WHERE account_id = ?2 AND mailbox_hash = ?3",
+        rusqlite::params![
+            folder.mailbox_hash as i64,
+            account_id,
+            old_mailbox_hash as i64
+        ],
+    )
+    .map_err(|e| format!("Cache rename_folder error: {e}"))?;
+
+    if old_mailbox_hash != folder.mailbox_hash {
+        tx.execute(
+            "DELETE FROM folders WHERE account_id = ?1 AND mailbox_hash = ?2",
+            rusqlite::params![account_id, old_mailbox_hash as i64],
+        )
+        .map_err(|e| format!("Cache rename_folder error: {e}"))?;
+    }
+
+    // Envelope hashes are derived from the folder path, so the next sync
+    // must start over.
+    tx.execute(
+        "DELETE FROM mailbox_sync_state WHERE account_id = ?1 AND mailbox_hash IN (?2, ?3)",
+        rusqlite::params![
+            account_id,
+            old_mailbox_hash as i64,
+            folder.mailbox_hash as i64
+        ],
+    )
+    .map_err(|e| format!("Cache rename_folder error: {e}"))?;
+
+    tx.commit()
+        .map_err(|e| format!("Cache commit error: {e}"))?;
+    Ok(())
+}
+
+pub(super) fn do_remove_folder(
+    conn: &Connection,
+    account_id: &str,
+    mailbox_hash: u64,
+) -> Result<(), String> {
+    let tx = conn
+        .unchecked_transaction()
+        .map_err(|e| format!("Cache tx error: {e}"))?;
+
+    tx.execute(
+        "DELETE FROM attachments WHERE account_id = ?1 AND envelope_hash IN (
+            SELECT envelope_hash FROM messages WHERE account_id = ?1 AND mailbox_hash = ?2
+        )",
+        rusqlite::params![account_id, mailbox_hash as i64],
+    )
+    .map_err(|e| format!("Cache cascade error: {e}"))?;
+    tx.execute(
+        "DELETE FROM messages WHERE account_id = ?1 AND mailbox_hash = ?2",
+        rusqlite::params![account_id, mailbox_hash as i64],
+    )
+    .map_err(|e| format!("Cache cascade error: {e}"))?;
+    tx.execute(
+        "DELETE FROM mailbox_sync_state WHERE account_id = ?1 AND mailbox_hash = ?2",
+        rusqlite::params![account_id, mailbox_hash as i64],
+    )
+    .map_err(|e| format!("Cache cascade error: {e}"))?;
+    tx.execute(
+        "DELETE FROM folders WHERE account_id = ?1 AND mailbox_hash = ?2",
+        rusqlite::params![account_id, mailbox_hash as i64],
+    )
+    .map_err(|e| format!("Cache remove_folder error: {e}"))?;
+
+    tx.commit()
+        .map_err(|e| format!("Cache commit error: {e}"))?;
+    Ok(())
+}
+
+pub(super) fn do_set_folder_subscribed(
+    conn: &Connection,
+    account_id: &str,
+    mailbox_hash: u64,
+    subscribed: bool,
+) -> Result<(), String> {
+    conn.execute(
+        "UPDATE folders SET subscribed = ?1 WHERE account_id = ?2 AND mailbox_hash = ?3",
+        rusqlite::params![subscribed as i32, account_id, mailbox_hash as i64],
+    )
+    .map_err(|e| format!("Cache set_folder_subscribed error: {e}"))?;
+    Ok(())
+}
+
 pub(super) fn do_save_messages(
     conn: &Connection,
     account_id: &str,
@@ -188,13 +338,75 @@ pub(super) fn do_save_messages(
         .unchecked_transaction()
         .map_err(|e| format!("Cache tx error: {e}"))?;
 
-    // Collect envelope hashes that have pending ops — we must not overwrite those
-    let mut pending_set = std::collections::HashSet::new();
-    {
-        let mut stmt = tx
+    let pending_set = pending_envelopes(&tx, account_id, mailbox_hash)?;
+    let keep: HashSet<u64> = messages.iter().map(|m| m.envelope_hash).collect();
+    clear_mailbox(&tx, account_id, mailbox_hash, &keep)?;
+    insert_messages(&tx, account_id, mailbox_hash, messages, &pending_set)?;
+
+    tx.commit()
+        .map_err(|e| format!("Cache commit error: {e}"))?;
+    Ok(())
+}
+
+/// Like [`do_save_messages`] but never deletes: rows outside `messages`
+/// (e.g. older than a fetched window) are left alone.
+pub(super) fn do_merge_messages(
+    conn: &Connection,
+    account_id: &str,
+    mailbox_hash: u64,
+    messages: &[MessageSummary],
+) -> Result<(), String> {
+    let tx = conn
+        .unchecked_transaction()
+        .map_err(|e| format!("Cache tx error: {e}"))?;
+
+    let pending_set = pending_envelopes(&tx, account_id, mailbox_hash)?;
+    insert_messages(&tx, account_id, mailbox_hash, messages, &pending_set)?;
+
+    tx.commit()
+        .map_err(|e| format!("Cache commit error: {e}"))?;
+    Ok(())
+}
+
+/// Envelope hashes in a mailbox that have pending ops — we must not overwrite those.
+fn pending_envelopes(
+    conn: &Connection,
+    account_id: &str,
+    mailbox_hash: u64,
+) -> Result<HashSet<u64>, String> {
+    let mut pending_set = HashSet::new();
+    let mut stmt = conn
+        .prepare(
+            "SELECT envelope_hash FROM messages
+             WHERE account_id = ?1 AND mailbox_hash = ?2 AND pending_op IS NOT NULL",
+        )
+        .map_err(|e| format!("Cache prepare error: {e}"))?;
+    let rows = stmt
+        .query_map(rusqlite::params![account_id, mailbox_hash as i64], |row| {
+            row.get::<_, i64>(0)
+        })
+        .map_err(|e| format!("Cache query error: {e}"))?;
+    for row in rows {
+        let hash = row.map_err(|e| format!("Cache row error: {e}"))?;
+        pending_set.insert(hash as u64);
+    }
+    Ok(pending_set)
+}
+
+/// Delete every non-pending message (and its attachments) in a mailbox
+/// except those in `keep`. Kept rows are refreshed in place, so their
+/// cached source and downloaded attachments survive.
+fn clear_mailbox(
+    conn: &Connection,
+    account_id: &str,
+    mailbox_hash: u64,
+    keep: &HashSet<u64>,
+) -> Result<(), String> {
+    let gone: Vec<i64> = {
+        let mut stmt = conn
             .prepare(
                 "SELECT envelope_hash FROM messages
-                 WHERE account_id = ?1 AND mailbox_hash = ?2 AND pending_op IS NOT NULL",
+                 WHERE account_id = ?1 AND mailbox_hash = ?2 AND pending_op IS NULL",
             )
             .map_err(|e| format!("Cache prepare error: {e}"))?;
         let rows = stmt
@@ -202,51 +414,93 @@ pub(super) fn do_save_messages(
                 row.get::<_, i64>(0)
             })
             .map_err(|e| format!("Cache query error: {e}"))?;
-        for hash in rows.flatten() {
-            pending_set.insert(hash as u64);
+        let mut gone = Vec::new();
+        for row in rows {
+            let hash = row.map_err(|e| format!("Cache row error: {e}"))?;
+            if !keep.contains(&(hash as u64)) {
+                gone.push(hash);
+            }
         }
-    }
-
-    // Cascade: delete attachments for non-pending messages before removing message rows
-    tx.execute(
-        "DELETE FROM attachments WHERE account_id = ?1 AND envelope_hash IN (
-            SELECT envelope_hash FROM messages
-            WHERE account_id = ?1 AND mailbox_hash = ?2 AND pending_op IS NULL
-        )",
-        rusqlite::params![account_id, mailbox_hash as i64],
-    )
-    .map_err(|e| format!("Cache attachment cascade error: {e}"))?;
+        gone
+    };
 
-    // Delete non-pending messages for this mailbox
-    tx.execute(
-        "DELETE FROM messages WHERE account_id = ?1 AND mailbox_hash = ?2 AND pending_op IS NULL",
-        rusqlite::params![account_id, mailbox_hash as i64],
-    )
-    .map_err(|e| format!("Cache delete error: {e}"))?;
+    let mut delete_attachments = conn
+        .prepare("DELETE FROM attachments WHERE account_id = ?1 AND envelope_hash = ?2")
+        .map_err(|e| format!("Cache prepare error: {e}"))?;
+    let mut delete_message = conn
+        .prepare("DELETE FROM messages WHERE account_id = ?1 AND envelope_hash = ?2")
+        .map_err(|e| format!("Cache prepare error: {e}"))?;
+    for hash in gone {
+        delete_attachments
+            .execute(rusqlite::params![account_id, hash])
+            .map_err(|e| format!("Cache attachment cascade error: {e}"))?;
+        delete_message
+            .execute(rusqlite::params![account_id, hash])
+            .map_err(|e| format!("Cache delete error: {e}"))?;
+    }
+    Ok(())
+}
 
-    let mut stmt = tx
+/// Insert or refresh messages, keeping any cached body. Rows with a pending
+/// op only get their server-side data refreshed.
+fn insert_messages(
+    conn: &Connection,
+    account_id: &str,
+    mailbox_hash: u64,
+    messages: &[MessageSummary],
+    pending_set: &HashSet<u64>,
+) -> Result<(), String> {
+    let mut stmt = conn
         .prepare(
-            "INSERT OR IGNORE INTO messages
+            "INSERT INTO messages
              (account_id, envelope_hash, mailbox_hash, subject, sender, date, timestamp,
               is_read, is_starred, has_attachments, thread_id, flags_server, flags_local,
-              message_id, in_reply_to, thread_depth, reply_to, recipient)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18)",
+              message_id, in_reply_to, thread_depth, reply_to, recipient,
+              keywords_server, keywords_local, refs)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18,
+                     ?19, ?19, ?20)
+             ON CONFLICT(account_id, envelope_hash) DO UPDATE SET
+                 subject = excluded.subject, sender = excluded.sender, date = excluded.date,
+                 timestamp = excluded.timestamp, is_read = excluded.is_read,
+                 is_starred = excluded.is_starred, has_attachments = excluded.has_attachments,
+                 flags_server = excluded.flags_server,
+                 flags_local = excluded.flags_local, message_id = excluded.message_id,
+                 in_reply_to = excluded.in_reply_to, refs = excluded.refs,
+                 reply_to = excluded.reply_to, recipient = excluded.recipient,
+                 keywords_server = excluded.keywords_server,
+                 keywords_local = excluded.keywords_local
+             WHERE mailbox_hash = excluded.mailbox_hash",
         )
         .map_err(|e| format!("Cache prepare error: {e}"))?;
 
-    // For messages with pending ops, update only flags_server (not flags_local or pending_op)
-    let mut update_server_stmt = tx
+    // For messages with pending ops, update only the server flags and keywords
+    // (not the local ones or pending_op). Neither path touches the thread
+    // placement of an existing row; that belongs to `do_rethread_account`.
+    let mut update_server_stmt = conn
         .prepare(
             "UPDATE messages SET flags_server = ?1, subject = ?2, sender = ?3,
-             date = ?4, timestamp = ?5, has_attachments = ?6, thread_id = ?7,
-             message_id = ?8, in_reply_to = ?9, thread_depth = ?10, reply_to = ?11,
-             recipient = ?12
-             WHERE account_id = ?13 AND envelope_hash = ?14 AND pending_op IS NOT NULL",
+             date = ?4, timestamp = ?5, has_attachments = ?6, refs = ?7,
+             message_id = ?8, in_reply_to = ?9, reply_to = ?10,
+             recipient = ?11, keywords_server = ?14
+             WHERE account_id = ?12 AND envelope_hash = ?13 AND pending_op IS NOT NULL",
         )
         .map_err(|e| format!("Cache prepare error: {e}"))?;
 
+    // Only rewrite address rows that actually changed.
+    let hashes: Vec<u64> = messages.iter().map(|m| m.envelope_hash).collect();
+    let stored_addresses = load_addresses(conn, account_id, &hashes)?;
+    let no_addresses = MessageAddresses::default();
+
     for m in messages {
-        let server_flags = flags_to_u8(m.is_read, m.is_starred);
+        let stored = stored_addresses
+            .get(&m.envelope_hash)
+            .unwrap_or(&no_addresses);
+        if *stored != m.addresses {
+            save_addresses(conn, account_id, m)?;
+        }
+        let server_flags = summary_flags(m);
+        let keywords = keywords_to_text(&m.keywords);
+        let refs = m.references.join(" ");
 
         if pending_set.contains(&m.envelope_hash) {
             // Update server-side data but preserve local overrides
@@ -258,14 +512,14 @@ pub(super) fn do_save_messages(
                     m.date,
                     m.timestamp,
                     m.has_attachments as i32,
-                    m.thread_id.map(|t| t as i64),
+                    refs,
                     m.message_id,
                     m.in_reply_to,
-                    m.thread_depth,
                     m.reply_to,
                     m.to,
                     account_id,
                     m.envelope_hash as i64,
+                    keywords,
                 ])
                 .map_err(|e| format!("Cache update error: {e}"))?;
         } else {
@@ -289,12 +543,126 @@ pub(super) fn do_save_messages(
                 m.thread_depth,
                 m.reply_to,
                 m.to,
+                keywords,
+                refs,
             ])
             .map_err(|e| format!("Cache insert error: {e}"))?;
         }
     }
-    drop(stmt);
-    drop(update_server_stmt);
+    Ok(())
+}
+
+pub(super) fn do_load_sync_state(
+    conn: &Connection,
+    account_id: &str,
+    mailbox_hash: u64,
+) -> Result<Option<MailboxSyncState>, String> {
+    let result = conn.query_row(
+        "SELECT uidvalidity, uidnext, highestmodseq FROM mailbox_sync_state
+         WHERE account_id = ?1 AND mailbox_hash = ?2",
+        rusqlite::params![account_id, mailbox_hash as i64],
+        |row| {
+            Ok(MailboxSyncState {
+                uidvalidity: row.get::<_, i64>(0)? as u64,
+                uidnext: row.get::<_, i64>(1)? as u64,
+                highestmodseq: row.get::<_, Option<i64>>(2)?.map(|m| m as u64),
+            })
+        },
+    );
+    match result {
+        Ok(state) => Ok(Some(state)),
+        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+        Err(e) => Err(format!("Cache load_sync_state error: {e}")),
+    }
+}
+
+/// Apply an incremental sync result and save the new sync state, all in one
+/// transaction. Local flag overrides on rows with a pending op survive.
+pub(super) fn do_apply_mailbox_delta(
+    conn: &Connection,
+    account_id: &str,
+    mailbox_hash: u64,
+    delta: &MailboxDelta,
+) -> Result<(), String> {
+    let tx = conn
+        .unchecked_transaction()
+        .map_err(|e| format!("Cache tx error: {e}"))?;
+
+    let pending_set = pending_envelopes(&tx, account_id, mailbox_hash)?;
+    if delta.reset {
+        // UIDs may have been reused, so nothing cached under them is trusted.
+        clear_mailbox(&tx, account_id, mailbox_hash, &HashSet::new())?;
+    }
+    insert_messages(
+        &tx,
+        account_id,
+        mailbox_hash,
+        &delta.new_messages,
+        &pending_set,
+    )?;
+
+    for (envelope_hash, flags_server, keywords) in &delta.flag_updates {
+        let (is_read, is_starred) = flags_from_u8(*flags_server);
+        tx.execute(
+            "UPDATE messages SET flags_server = ?1, keywords_server = ?6,
+             flags_local = CASE WHEN pending_op IS NULL THEN ?1 ELSE flags_local END,
+             keywords_local = CASE WHEN pending_op IS NULL THEN ?6 ELSE keywords_local END,
+             is_read = CASE WHEN pending_op IS NULL THEN ?2 ELSE is_read END,
+             is_starred = CASE WHEN pending_op IS NULL THEN ?3 ELSE is_starred END
+             WHERE account_id = ?4 AND envelope_hash = ?5
+               AND (flags_server IS NOT ?1 OR keywords_server IS NOT ?6)",
+            rusqlite::params![
+                *flags_server as i32,
+                is_read as i32,
+                is_starred as i32,
+                account_id,
+                *envelope_hash as i64,
+                keywords_to_text(keywords),
+            ],
+        )
+        .map_err(|e| format!("Cache flag sync error: {e}"))?;
+    }
+
+    if let Some(present) = &delta.present {
+        let present: HashSet<u64> = present.iter().copied().collect();
+        let mut expunged = Vec::new();
+        {
+            let mut stmt = tx
+                .prepare(
+                    "SELECT envelope_hash FROM messages
+                     WHERE account_id = ?1 AND mailbox_hash = ?2 AND pending_op IS NULL",
+                )
+                .map_err(|e| format!("Cache prepare error: {e}"))?;
+            let rows = stmt
+                .query_map(rusqlite::params![account_id, mailbox_hash as i64], |row| {
+                    row.get::<_, i64>(0)
+                })
+                .map_err(|e| format!("Cache query error: {e}"))?;
+            for row in rows {
+                let hash = row.map_err(|e| format!("Cache row error: {e}"))?;
+                if !present.contains(&(hash as u64)) {
+                    expunged.push(hash as u64);
+                }
+            }
+        }
+        for envelope_hash in expunged {
+            do_remove_message(&tx, account_id, envelope_hash)?;
+        }
+    }
+
+    tx.execute(
+        "INSERT OR REPLACE INTO mailbox_sync_state
+         (account_id, mailbox_hash, uidvalidity, uidnext, highestmodseq)
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+        rusqlite::params![
+            account_id,
+            mailbox_hash as i64,
+            delta.state.uidvalidity as i64,
+            delta.state.uidnext as i64,
+            delta.state.highestmodseq.map(|m| m as i64),
+        ],
+    )
+    .map_err(|e| format!("Cache save_sync_state error: {e}"))?;
 
     tx.commit()
         .map_err(|e| format!("Cache commit error: {e}"))?;
@@ -313,7 +681,8 @@ pub(super) fn do_load_messages(
             "SELECT envelope_hash, subject, sender, date, timestamp,
                     is_read, is_starred, has_attachments, thread_id,
                     flags_server, flags_local, pending_op, mailbox_hash,
-                    message_id, in_reply_to, thread_depth, reply_to, recipient
+                    message_id, in_reply_to, thread_depth, reply_to, recipient,
+                    keywords_server, keywords_local, refs, thread_parent
              FROM messages
              WHERE mailbox_hash = ?1 AND account_id = ?4
              ORDER BY
@@ -333,18 +702,145 @@ pub(super) fn do_load_messages(
         )
         .map_err(|e| format!("Cache query error: {e}"))?;
 
-    let mut messages = Vec::new();
-    for row in rows {
-        messages.push(row.map_err(|e| format!("Cache row error: {e}"))?);
-    }
+    let mut messages = rows
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Cache row error: {e}"))?;
+    fill_addresses(conn, account_id, &mut messages)?;
     Ok(messages)
 }
 
+/// `(field, list)` pairs as stored in `message_addresses.field`.
+fn address_fields(addresses: &MessageAddresses) -> [(&'static str, &[EmailAddress]); 6] {
+    [
+        ("from", &addresses.from),
+        ("to", &addresses.to),
+        ("cc", &addresses.cc),
+        ("bcc", &addresses.bcc),
+        ("reply_to", &addresses.reply_to),
+        ("sender", &addresses.sender),
+    ]
+}
+
+fn address_list<'a>(
+    addresses: &'a mut MessageAddresses,
+    field: &str,
+) -> Option<&'a mut Vec<EmailAddress>> {
+    match field {
+        "from" => Some(&mut addresses.from),
+        "to" => Some(&mut addresses.to),
+        "cc" => Some(&mut addresses.cc),
+        "bcc" => Some(&mut addresses.bcc),
+        "reply_to" => Some(&mut addresses.reply_to),
+        "sender" => Some(&mut addresses.sender),
+        _ => None,
+    }
+}
+
+/// Replace the stored address rows for one message.
+fn save_addresses(conn: &Connection, account_id: &str, m: &MessageSummary) -> Result<(), String> {
+    conn.prepare_cached(
+        "DELETE FROM message_addresses WHERE account_id = ?1 AND envelope_hash = ?2",
+    )
+    .and_then(|mut stmt| stmt.execute(rusqlite::params![account_id, m.envelope_hash as i64]))
+    .map_err(|e| format!("Cache address delete error: {e}"))?;
+
+    let mut stmt = conn
+        .prepare_cached(
+            "INSERT INTO message_addresses
+             (account_id, envelope_hash, field, position, name, email)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        )
+        .map_err(|e| format!("Cache prepare error: {e}"))?;
+    for (field, list) in address_fields(&m.addresses) {
+        for (position, address) in list.iter().enumerate() {
+            stmt.execute(rusqlite::params![
+                account_id,
+                m.envelope_hash as i64,
+                field,
+                position as i64,
+                address.name,
+                address.email,
+            ])
+            .map_err(|e| format!("Cache address insert error: {e}"))?;
+        }
+    }
+    Ok(())
+}
+
+/// Envelope hashes per address query, well under SQLite's variable limit.
+const ADDRESS_BATCH: usize = 500;
+
+/// The stored address rows for `hashes`, grouped by envelope hash. One
+/// query per [`ADDRESS_BATCH`] messages rather than one per message.
+fn load_addresses(
+    conn: &Connection,
+    account_id: &str,
+    hashes: &[u64],
+) -> Result<HashMap<u64, MessageAddresses>, String> {
+    let mut out: HashMap<u64, MessageAddresses> = HashMap::new();
+    for chunk in hashes.chunks(ADDRESS_BATCH) {
+        let placeholders: String = (0..chunk.len())
+            .map(|i| format!("?{}", i + 2))
+            .collect::<Vec<_>>()
+            .join(",");
+        let mut params: Vec<Box<dyn rusqlite::types::ToSql>> = Vec::new();
+        params.push(Box::new(account_id.to_string()));
+        for h in chunk {
+            params.push(Box::new(*h as i64));
+        }
+        let param_refs: Vec<&dyn rusqlite::types::ToSql> =
+            params.iter().map(|p| p.as_ref()).collect();
+
+        let mut stmt = conn
+            .prepare(&format!(
+                "SELECT envelope_hash, field, name, email FROM message_addresses
+                 WHERE account_id = ?1 AND envelope_hash IN ({placeholders})
+                 ORDER BY envelope_hash, field, position"
+            ))
+            .map_err(|e| format!("Cache prepare error: {e}"))?;
+        let rows = stmt
+            .query_map(param_refs.as_slice(), |row| {
+                Ok((
+                    row.get::<_, i64>(0)? as u64,
+                    row.get::<_, String>(1)?,
+                    EmailAddress {
+                        name: row.get(2)?,
+                        email: row.get(3)?,
+                    },
+                ))
+            })
+            .map_err(|e| format!("Cache query error: {e}"))?;
+        for row in rows {
+            let (hash, field, address) = row.map_err(|e| format!("Cache row error: {e}"))?;
+            if let Some(list) = address_list(out.entry(hash).or_default(), &field) {
+                list.push(address);
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// Fill in `addresses` on loaded messages from the stored address rows.
+fn fill_addresses(
+    conn: &Connection,
+    account_id: &str,
+    messages: &mut [MessageSummary],
+) -> Result<(), String> {
+    let hashes: Vec<u64> = messages.iter().map(|m| m.envelope_hash).collect();
+    let mut addresses = load_addresses(conn, account_id, &hashes)?;
+    for m in messages {
+        if let Some(stored) = addresses.remove(&m.envelope_hash) {
+            m.addresses = stored;
+        }
+    }
+    Ok(())
+}
+
 pub(super) fn do_load_body(
     conn: &Connection,
     account_id: &str,
     envelope_hash: u64,
-) -> Result<Option<(String, String, Vec<AttachmentData>)>, String> {
+) -> Result<Option<(String, String, Vec<AttachmentInfo>)>, String> {
     let row_result = conn.query_row(
         "SELECT body_rendered, body_markdown FROM messages WHERE account_id = ?1 AND envelope_hash = ?2",
         rusqlite::params![account_id, envelope_hash as i64],
@@ -365,24 +861,36 @@ pub(super) fn do_load_body(
 
     let mut stmt = conn
         .prepare(
-            "SELECT idx, filename, mime_type, data FROM attachments
+            "SELECT part, filename, mime_type, size, content_id, is_inline, encoding
+             FROM attachments
              WHERE account_id = ?1 AND envelope_hash = ?2 ORDER BY idx",
         )
         .map_err(|e| format!("Cache prepare error: {e}"))?;
 
     let rows = stmt
         .query_map(rusqlite::params![account_id, envelope_hash as i64], |row| {
-            Ok(AttachmentData {
+            let part: Option<String> = row.get(0)?;
+            let attachment = AttachmentInfo {
+                part: part.clone().unwrap_or_default(),
                 filename: row.get(1)?,
                 mime_type: row.get(2)?,
-                data: row.get(3)?,
-            })
+                size: row.get::<_, Option<i64>>(3)?.unwrap_or(0) as u64,
+                content_id: row.get(4)?,
+                is_inline: row.get::<_, Option<bool>>(5)?.unwrap_or(false),
+                encoding: row.get::<_, Option<String>>(6)?.unwrap_or_default(),
+            };
+            Ok(part.map(|_| attachment))
         })
         .map_err(|e| format!("Cache query error: {e}"))?;
 
     let mut attachments = Vec::new();
     for row in rows {
-        attachments.push(row.map_err(|e| format!("Cache row error: {e}"))?);
+        match row.map_err(|e| format!("Cache row error: {e}"))? {
+            Some(attachment) => attachments.push(attachment),
+            // Saved by a version that downloaded attachments eagerly and
+            // recorded no part numbers: refetch so they can be loaded lazily.
+            None => return Ok(None),
+        }
     }
 
     Ok(Some((body_markdown, body_plain, attachments)))
@@ -394,7 +902,7 @@ pub(super) fn do_save_body(
     envelope_hash: u64,
     body_markdown: &str,
     body_plain: &str,
-    attachments: &[AttachmentData],
+    attachments: &[AttachmentInfo],
 ) -> Result<(), String> {
     let tx = conn
         .unchecked_transaction()
@@ -415,8 +923,9 @@ pub(super) fn do_save_body(
 
     let mut stmt = tx
         .prepare(
-            "INSERT INTO attachments (account_id, envelope_hash, idx, filename, mime_type, data)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            "INSERT INTO attachments (account_id, envelope_hash, idx, filename, mime_type, data,
+                                      part, size, content_id, is_inline, encoding)
+             VALUES (?1, ?2, ?3, ?4, ?5, X'', ?6, ?7, ?8, ?9, ?10)",
         )
         .map_err(|e| format!("Cache prepare error: {e}"))?;
 
@@ -427,7 +936,11 @@ pub(super) fn do_save_body(
             i as i32,
             att.filename,
             att.mime_type,
-            att.data,
+            att.part,
+            att.size as i64,
+            att.content_id,
+            att.is_inline,
+            att.encoding,
         ])
         .map_err(|e| format!("Cache attachment insert error: {e}"))?;
     }
@@ -438,18 +951,52 @@ pub(super) fn do_save_body(
     Ok(())
 }
 
-// -- Phase 2b: dual-truth flag operations --------------------------------
+pub(super) fn do_load_attachment(
+    conn: &Connection,
+    account_id: &str,
+    envelope_hash: u64,
+    part: &str,
+) -> Result<Option<Vec<u8>>, String> {
+    match conn.query_row(
+        "SELECT data FROM attachment_data
+         WHERE account_id = ?1 AND envelope_hash = ?2 AND part = ?3",
+        rusqlite::params![account_id, envelope_hash as i64, part],
+        |row| row.get(0),
+    ) {
+        Ok(data) => Ok(Some(data)),
+        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+        Err(e) => Err(format!("Cache attachment load error: {e}")),
+    }
+}
 
-pub(super) fn do_update_flags(
+pub(super) fn do_save_attachment(
     conn: &Connection,
     account_id: &str,
     envelope_hash: u64,
-    flags_local: u8,
-    pending_op: &str,
+    part: &str,
+    data: &[u8],
 ) -> Result<(), String> {
-    let (is_read, is_starred) = flags_from_u8(flags_local);
     conn.execute(
-        "UPDATE messages SET flags_local = ?1, pending_op = ?2, is_read = ?3, is_starred = ?4
+        "INSERT INTO attachment_data (account_id, envelope_hash, part, data) VALUES (?1, ?2, ?3, ?4)
+         ON CONFLICT(account_id, envelope_hash, part) DO UPDATE SET data = excluded.data",
+        rusqlite::params![account_id, envelope_hash as i64, part, data],
+    )
+    .map_err(|e| format!("Cache attachment save error: {e}"))?;
+    Ok(())
+}
+
+// -- Phase 2b: dual-truth flag operations --------------------------------
+
+pub(super) fn do_update_flags(
+    conn: &Connection,
+    account_id: &str,
+    envelope_hash: u64,
+    flags_local: u8,
+    pending_op: &str,
+) -> Result<(), String> {
+    let (is_read, is_starred) = flags_from_u8(flags_local);
+    conn.execute(
+        "UPDATE messages SET flags_local = ?1, pending_op = ?2, is_read = ?3, is_starred = ?4
          WHERE account_id = ?5 AND envelope_hash = ?6",
         rusqlite::params![
             flags_local as i32,
@@ -464,6 +1011,29 @@ pub(super) fn do_update_flags(
     Ok(())
 }
 
+/// Set local keywords and mark a pending operation. Clearing the op adopts
+/// them as the server's keywords; reverting it restores the server's.
+pub(super) fn do_update_keywords(
+    conn: &Connection,
+    account_id: &str,
+    envelope_hash: u64,
+    keywords_local: &[String],
+    pending_op: &str,
+) -> Result<(), String> {
+    conn.execute(
+        "UPDATE messages SET keywords_local = ?1, pending_op = ?2
+         WHERE account_id = ?3 AND envelope_hash = ?4",
+        rusqlite::params![
+            keywords_to_text(keywords_local),
+            pending_op,
+            account_id,
+            envelope_hash as i64,
+        ],
+    )
+    .map_err(|e| format!("Cache update_keywords error: {e}"))?;
+    Ok(())
+}
+
 pub(super) fn do_clear_pending_op(
     conn: &Connection,
     account_id: &str,
@@ -473,7 +1043,7 @@ pub(super) fn do_clear_pending_op(
     let (is_read, is_starred) = flags_from_u8(flags_server);
     conn.execute(
         "UPDATE messages SET flags_server = ?1, flags_local = ?1, pending_op = NULL,
-         is_read = ?2, is_starred = ?3
+         keywords_server = keywords_local, is_read = ?2, is_starred = ?3
          WHERE account_id = ?4 AND envelope_hash = ?5",
         rusqlite::params![
             flags_server as i32,
@@ -492,9 +1062,10 @@ pub(super) fn do_revert_pending_op(
     account_id: &str,
     envelope_hash: u64,
 ) -> Result<(), String> {
-    // Revert local flags to match server flags, clear pending
+    // Revert local flags and keywords to match the server, clear pending
     conn.execute(
-        "UPDATE messages SET flags_local = flags_server, pending_op = NULL,
+        "UPDATE messages SET flags_local = flags_server, keywords_local = keywords_server,
+         pending_op = NULL,
          is_read = CASE WHEN (flags_server & 1) != 0 THEN 1 ELSE 0 END,
          is_starred = CASE WHEN (flags_server & 2) != 0 THEN 1 ELSE 0 END
          WHERE account_id = ?1 AND envelope_hash = ?2",
@@ -523,6 +1094,55 @@ pub(super) fn do_remove_message(
     Ok(())
 }
 
+pub(super) fn do_update_flags_batch(
+    conn: &Connection,
+    account_id: &str,
+    updates: &[(u64, u8)],
+    pending_op: &str,
+) -> Result<(), String> {
+    let tx = conn
+        .unchecked_transaction()
+        .map_err(|e| format!("Cache tx error: {e}"))?;
+    for &(envelope_hash, flags_local) in updates {
+        do_update_flags(&tx, account_id, envelope_hash, flags_local, pending_op)?;
+    }
+    tx.commit()
+        .map_err(|e| format!("Cache commit error: {e}"))?;
+    Ok(())
+}
+
+pub(super) fn do_clear_pending_op_batch(
+    conn: &Connection,
+    account_id: &str,
+    updates: &[(u64, u8)],
+) -> Result<(), String> {
+    let tx = conn
+        .unchecked_transaction()
+        .map_err(|e| format!("Cache tx error: {e}"))?;
+    for &(envelope_hash, flags_server) in updates {
+        do_clear_pending_op(&tx, account_id, envelope_hash, flags_server)?;
+    }
+    tx.commit()
+        .map_err(|e| format!("Cache commit error: {e}"))?;
+    Ok(())
+}
+
+pub(super) fn do_remove_messages(
+    conn: &Connection,
+    account_id: &str,
+    envelope_hashes: &[u64],
+) -> Result<(), String> {
+    let tx = conn
+        .unchecked_transaction()
+        .map_err(|e| format!("Cache tx error: {e}"))?;
+    for &envelope_hash in envelope_hashes {
+        do_remove_message(&tx, account_id, envelope_hash)?;
+    }
+    tx.commit()
+        .map_err(|e| format!("Cache commit error: {e}"))?;
+    Ok(())
+}
+
 pub(super) fn do_remove_account(conn: &Connection, account_id: &str) -> Result<(), String> {
     let tx = conn
         .unchecked_transaction()
@@ -539,15 +1159,415 @@ pub(super) fn do_remove_account(conn: &Connection, account_id: &str) -> Result<(
     tx.execute("DELETE FROM messages WHERE account_id = ?1", [account_id])
         .map_err(|e| format!("Cache message cleanup error: {e}"))?;
 
+    // Remove sync checkpoints
+    tx.execute(
+        "DELETE FROM mailbox_sync_state WHERE account_id = ?1",
+        [account_id],
+    )
+    .map_err(|e| format!("Cache sync state cleanup error: {e}"))?;
+
     // Remove folders
     tx.execute("DELETE FROM folders WHERE account_id = ?1", [account_id])
         .map_err(|e| format!("Cache folder cleanup error: {e}"))?;
 
+    // Remove queued mail
+    tx.execute("DELETE FROM outbox WHERE account_id = ?1", [account_id])
+        .map_err(|e| format!("Cache outbox cleanup error: {e}"))?;
+
+    // Remove remote content rules
+    tx.execute(
+        "DELETE FROM remote_content_allowlist WHERE account_id = ?1",
+        [account_id],
+    )
+    .map_err(|e| format!("Cache allowlist cleanup error: {e}"))?;
+
     tx.commit()
         .map_err(|e| format!("Cache commit error: {e}"))?;
     Ok(())
 }
 
+pub(super) fn do_save_raw(
+    conn: &Connection,
+    account_id: &str,
+    envelope_hash: u64,
+    raw: &[u8],
+) -> Result<(), String> {
+    conn.execute(
+        "INSERT INTO message_sources (account_id, envelope_hash, raw) VALUES (?1, ?2, ?3)
+         ON CONFLICT(account_id, envelope_hash) DO UPDATE SET raw = excluded.raw",
+        rusqlite::params![account_id, envelope_hash as i64, raw],
+    )
+    .map_err(|e| format!("Cache source save error: {e}"))?;
+    Ok(())
+}
+
+pub(super) fn do_load_raw(
+    conn: &Connection,
+    account_id: &str,
+    envelope_hash: u64,
+) -> Result<Option<Vec<u8>>, String> {
+    match conn.query_row(
+        "SELECT raw FROM message_sources WHERE account_id = ?1 AND envelope_hash = ?2",
+        rusqlite::params![account_id, envelope_hash as i64],
+        |row| row.get(0),
+    ) {
+        Ok(raw) => Ok(Some(raw)),
+        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+        Err(e) => Err(format!("Cache source load error: {e}")),
+    }
+}
+
+/// Queue a message, held until `send_at` if that's later than `now`.
+/// Returns the outbox id.
+pub(super) fn do_queue_outgoing(
+    conn: &Connection,
+    account_id: &str,
+    email: &OutgoingEmail,
+    append_to_sent: bool,
+    now: i64,
+    send_at: i64,
+) -> Result<i64, String> {
+    let json = serde_json::to_string(email).map_err(|e| format!("Outbox encode error: {e}"))?;
+    let status = if send_at > now {
+        OutboxStatus::Scheduled
+    } else {
+        OutboxStatus::Queued
+    };
+    conn.execute(
+        "INSERT INTO outbox (account_id, email, status, attempts, next_attempt_at, created_at,
+                             send_at, append_to_sent)
+         VALUES (?1, ?2, ?3, 0, ?4, ?5, ?4, ?6)",
+        rusqlite::params![
+            account_id,
+            json,
+            status.as_str(),
+            send_at.max(now),
+            now,
+            append_to_sent
+        ],
+    )
+    .map_err(|e| format!("Cache outbox save error: {e}"))?;
+    Ok(conn.last_insert_rowid())
+}
+
+/// Every outbox entry of an account, oldest first.
+pub(super) fn do_load_outbox(
+    conn: &Connection,
+    account_id: &str,
+) -> Result<Vec<OutboxEntry>, String> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT id, account_id, email, status, attempts, last_error, next_attempt_at,
+                    created_at, COALESCE(send_at, created_at), append_to_sent, sent
+             FROM outbox WHERE account_id = ?1 ORDER BY created_at, id",
+        )
+        .map_err(|e| format!("Cache outbox query error: {e}"))?;
+    let rows = stmt
+        .query_map([account_id], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, u32>(4)?,
+                row.get::<_, Option<String>>(5)?,
+                row.get::<_, i64>(6)?,
+                row.get::<_, i64>(7)?,
+                row.get::<_, i64>(8)?,
+                row.get::<_, bool>(9)?,
+                row.get::<_, Option<Vec<u8>>>(10)?,
+            ))
+        })
+        .map_err(|e| format!("Cache outbox query error: {e}"))?;
+
+    let mut entries = Vec::new();
+    for row in rows {
+        let (
+            id,
+            account_id,
+            email,
+            status,
+            attempts,
+            last_error,
+            next,
+            created,
+            send_at,
+            append,
+            sent,
+        ) = row.map_err(|e| format!("Cache outbox row error: {e}"))?;
+        entries.push(OutboxEntry {
+            id,
+            account_id,
+            email: serde_json::from_str(&email)
+                .map_err(|e| format!("Outbox entry {id} is unreadable: {e}"))?,
+            status: OutboxStatus::parse(&status).unwrap_or(OutboxStatus::Queued),
+            attempts,
+            last_error,
+            next_attempt_at: next,
+            created_at: created,
+            send_at,
+            append_to_sent: append,
+            sent,
+        });
+    }
+    Ok(entries)
+}
+
+/// Record the outcome of an attempt.
+pub(super) fn do_update_outbox(
+    conn: &Connection,
+    id: i64,
+    status: OutboxStatus,
+    attempts: u32,
+    last_error: Option<&str>,
+    next_attempt_at: i64,
+) -> Result<(), String> {
+    conn.execute(
+        "UPDATE outbox SET status = ?2, attempts = ?3, last_error = ?4, next_attempt_at = ?5
+         WHERE id = ?1",
+        rusqlite::params![id, status.as_str(), attempts, last_error, next_attempt_at],
+    )
+    .map_err(|e| format!("Cache outbox update error: {e}"))?;
+    Ok(())
+}
+
+/// Mark a due entry as being sent. Returns `false` if it was taken out of
+/// the outbox (or claimed) since the caller loaded it.
+pub(super) fn do_claim_outbox(conn: &Connection, id: i64) -> Result<bool, String> {
+    let changed = conn
+        .execute(
+            "UPDATE outbox SET status = 'sending'
+             WHERE id = ?1 AND status IN ('queued', 'scheduled')",
+            [id],
+        )
+        .map_err(|e| format!("Cache outbox update error: {e}"))?;
+    Ok(changed > 0)
+}
+
+/// Queue entries left `sending` by a run that exited mid-send again, since
+/// there's no telling whether the server took them.
+pub(super) fn do_requeue_interrupted_outbox(
+    conn: &Connection,
+    account_id: &str,
+) -> Result<(), String> {
+    conn.execute(
+        "UPDATE outbox SET status = 'queued' WHERE account_id = ?1 AND status = 'sending'",
+        [account_id],
+    )
+    .map_err(|e| format!("Cache outbox update error: {e}"))?;
+    Ok(())
+}
+
+/// The server accepted the message: keep its bytes until they're filed in
+/// Sent, and never send it again.
+pub(super) fn do_set_outbox_sent(conn: &Connection, id: i64, sent: &[u8]) -> Result<(), String> {
+    conn.execute(
+        "UPDATE outbox SET status = 'appending', attempts = 0, last_error = NULL, sent = ?2
+         WHERE id = ?1",
+        rusqlite::params![id, sent],
+    )
+    .map_err(|e| format!("Cache outbox update error: {e}"))?;
+    Ok(())
+}
+
+/// Put a failed message back in the queue, due at `now`. Returns whether
+/// there was a failed message with that id.
+pub(super) fn do_requeue_outbox(conn: &Connection, id: i64, now: i64) -> Result<bool, String> {
+    let changed = conn
+        .execute(
+            "UPDATE outbox SET status = 'queued', attempts = 0, last_error = NULL,
+                    next_attempt_at = ?2
+             WHERE id = ?1 AND status = 'failed'",
+            rusqlite::params![id, now],
+        )
+        .map_err(|e| format!("Cache outbox update error: {e}"))?;
+    Ok(changed > 0)
+}
+
+/// Delete an entry unconditionally (after it was sent and filed).
+pub(super) fn do_remove_outbox(conn: &Connection, id: i64) -> Result<(), String> {
+    conn.execute("DELETE FROM outbox WHERE id = ?1", [id])
+        .map_err(|e| format!("Cache outbox delete error: {e}"))?;
+    Ok(())
+}
+
+/// Take a message out of the outbox and hand it back, unless it's being
+/// handed to the server right now or the server already accepted it.
+pub(super) fn do_take_outbox(conn: &Connection, id: i64) -> Result<Option<OutgoingEmail>, String> {
+    let email: Option<String> = match conn.query_row(
+        "DELETE FROM outbox
+         WHERE id = ?1 AND status IN ('queued', 'scheduled', 'failed')
+         RETURNING email",
+        [id],
+        |row| row.get(0),
+    ) {
+        Ok(email) => Some(email),
+        Err(rusqlite::Error::QueryReturnedNoRows) => None,
+        Err(e) => return Err(format!("Cache outbox delete error: {e}")),
+    };
+    email
+        .map(|email| {
+            serde_json::from_str(&email)
+                .map_err(|e| format!("Outbox entry {id} is unreadable: {e}"))
+        })
+        .transpose()
+}
+
+/// Always load remote content for a sender or domain.
+pub(super) fn do_allow_remote_content(
+    conn: &Connection,
+    account_id: &str,
+    rule: &RemoteContentRule,
+) -> Result<(), String> {
+    let (kind, value) = rule.as_parts();
+    conn.execute(
+        "INSERT OR IGNORE INTO remote_content_allowlist (account_id, kind, value)
+         VALUES (?1, ?2, ?3)",
+        rusqlite::params![account_id, kind, value],
+    )
+    .map_err(|e| format!("Cache allowlist save error: {e}"))?;
+    Ok(())
+}
+
+pub(super) fn do_revoke_remote_content(
+    conn: &Connection,
+    account_id: &str,
+    rule: &RemoteContentRule,
+) -> Result<(), String> {
+    let (kind, value) = rule.as_parts();
+    conn.execute(
+        "DELETE FROM remote_content_allowlist WHERE account_id = ?1 AND kind = ?2 AND value = ?3",
+        rusqlite::params![account_id, kind, value],
+    )
+    .map_err(|e| format!("Cache allowlist delete error: {e}"))?;
+    Ok(())
+}
+
+pub(super) fn do_load_remote_content_rules(
+    conn: &Connection,
+    account_id: &str,
+) -> Result<Vec<RemoteContentRule>, String> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT kind, value FROM remote_content_allowlist WHERE account_id = ?1
+             ORDER BY kind, value",
+        )
+        .map_err(|e| format!("Cache allowlist query error: {e}"))?;
+    let rows = stmt
+        .query_map([account_id], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })
+        .map_err(|e| format!("Cache allowlist query error: {e}"))?;
+    let mut rules = Vec::new();
+    for row in rows {
+        let (kind, value) = row.map_err(|e| format!("Cache allowlist row error: {e}"))?;
+        rules.extend(RemoteContentRule::from_parts(&kind, &value));
+    }
+    Ok(rules)
+}
+
+/// Rebuild thread membership, parent links and depths for every cached
+/// message of an account, across all of its folders. Returns how many rows
+/// changed.
+pub(super) fn do_rethread_account(
+    conn: &Connection,
+    account_id: &str,
+    group_by_subject: bool,
+) -> Result<usize, String> {
+    let inputs = {
+        let mut stmt = conn
+            .prepare(
+                "SELECT envelope_hash, message_id, in_reply_to, refs, subject
+                 FROM messages WHERE account_id = ?1",
+            )
+            .map_err(|e| format!("Cache prepare error: {e}"))?;
+        let rows = stmt
+            .query_map([account_id], |row| {
+                Ok(ThreadInput {
+                    envelope_hash: row.get::<_, i64>(0)? as u64,
+                    message_id: row.get::<_, Option<String>>(1)?.unwrap_or_default(),
+                    in_reply_to: row.get(2)?,
+                    references: row
+                        .get::<_, Option<String>>(3)?
+                        .unwrap_or_default()
+                        .split_whitespace()
+                        .map(str::to_string)
+                        .collect(),
+                    subject: row.get::<_, Option<String>>(4)?.unwrap_or_default(),
+                })
+            })
+            .map_err(|e| format!("Cache query error: {e}"))?;
+        let mut inputs = Vec::new();
+        for row in rows {
+            inputs.push(row.map_err(|e| format!("Cache row error: {e}"))?);
+        }
+        inputs
+    };
+
+    let links = thread_messages(&inputs, group_by_subject);
+
+    let tx = conn
+        .unchecked_transaction()
+        .map_err(|e| format!("Cache tx error: {e}"))?;
+    let mut changed = 0;
+    {
+        let mut stmt = tx
+            .prepare(
+                "UPDATE messages SET thread_id = ?1, thread_parent = ?2, thread_depth = ?3
+                 WHERE account_id = ?4 AND envelope_hash = ?5
+                   AND (thread_id IS NOT ?1 OR thread_parent IS NOT ?2 OR thread_depth IS NOT ?3)",
+            )
+            .map_err(|e| format!("Cache prepare error: {e}"))?;
+        for link in &links {
+            changed += stmt
+                .execute(rusqlite::params![
+                    link.thread_id as i64,
+                    link.parent.map(|p| p as i64),
+                    link.depth,
+                    account_id,
+                    link.envelope_hash as i64,
+                ])
+                .map_err(|e| format!("Cache rethread error: {e}"))?;
+        }
+    }
+    tx.commit()
+        .map_err(|e| format!("Cache commit error: {e}"))?;
+    Ok(changed)
+}
+
+/// Every cached message of one conversation, across folders, oldest first.
+pub(super) fn do_load_thread(
+    conn: &Connection,
+    account_id: &str,
+    thread_id: u64,
+) -> Result<Vec<MessageSummary>, String> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT envelope_hash, subject, sender, date, timestamp,
+                    is_read, is_starred, has_attachments, thread_id,
+                    flags_server, flags_local, pending_op, mailbox_hash,
+                    message_id, in_reply_to, thread_depth, reply_to, recipient,
+                    keywords_server, keywords_local, refs, thread_parent
+             FROM messages
+             WHERE account_id = ?1 AND thread_id = ?2
+             ORDER BY timestamp ASC",
+        )
+        .map_err(|e| format!("Cache prepare error: {e}"))?;
+
+    let rows = stmt
+        .query_map(
+            rusqlite::params![account_id, thread_id as i64],
+            row_to_summary,
+        )
+        .map_err(|e| format!("Cache query error: {e}"))?;
+
+    let mut messages = rows
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Cache row error: {e}"))?;
+    fill_addresses(conn, account_id, &mut messages)?;
+    Ok(messages)
+}
+
 pub(super) fn do_search(conn: &Connection, query: &str) -> Result<Vec<MessageSummary>, String> {
     let query = query.trim();
     if query.is_empty() {
@@ -579,7 +1599,8 @@ pub(super) fn do_search(conn: &Connection, query: &str) -> Result<Vec<MessageSum
             "SELECT m.envelope_hash, m.subject, m.sender, m.date, m.timestamp,
                     m.is_read, m.is_starred, m.has_attachments, m.thread_id,
                     m.flags_server, m.flags_local, m.pending_op, m.mailbox_hash,
-                    m.message_id, m.in_reply_to, m.thread_depth, m.reply_to, m.recipient
+                    m.message_id, m.in_reply_to, m.thread_depth, m.reply_to, m.recipient,
+                    m.keywords_server, m.keywords_local, m.refs, m.thread_parent, m.account_id
              FROM messages m
              WHERE m.rowid IN (SELECT rowid FROM message_fts WHERE message_fts MATCH ?1)
              ORDER BY m.timestamp DESC
@@ -588,14 +1609,38 @@ pub(super) fn do_search(conn: &Connection, query: &str) -> Result<Vec<MessageSum
         .map_err(|e| format!("Search prepare error: {e}"))?;
 
     let rows = stmt
-        .query_map([&fts_query], row_to_summary)
+        .query_map([&fts_query], |row| {
+            Ok((row.get::<_, String>(22)?, row_to_summary(row)?))
+        })
         .map_err(|e| format!("Search query error: {e}"))?;
 
-    let mut results = Vec::new();
-    for row in rows {
-        results.push(row.map_err(|e| format!("Search row error: {e}"))?);
+    let rows = rows
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Search row error: {e}"))?;
+
+    // Results can span accounts; load each account's addresses in one go.
+    let mut by_account: HashMap<String, Vec<u64>> = HashMap::new();
+    for (account_id, summary) in &rows {
+        by_account
+            .entry(account_id.clone())
+            .or_default()
+            .push(summary.envelope_hash);
     }
-    Ok(results)
+    let mut addresses = HashMap::new();
+    for (account_id, hashes) in by_account {
+        for (hash, stored) in load_addresses(conn, &account_id, &hashes)? {
+            addresses.insert((account_id.clone(), hash), stored);
+        }
+    }
+    Ok(rows
+        .into_iter()
+        .map(|(account_id, mut summary)| {
+            if let Some(stored) = addresses.remove(&(account_id, summary.envelope_hash)) {
+                summary.addresses = stored;
+            }
+            summary
+        })
+        .collect())
 }
 
 #[cfg(test)]
@@ -603,11 +1648,24 @@ mod tests {
     use rusqlite::Connection;
 
     use super::{
-        do_load_body, do_load_folders, do_load_messages, do_remove_message, do_save_body,
-        do_save_folders, do_save_messages, do_update_flags,
+        do_allow_remote_content, do_apply_mailbox_delta, do_claim_outbox, do_clear_pending_op,
+        do_clear_pending_op_batch, do_load_attachment, do_load_body, do_load_folders,
+        do_load_messages, do_load_outbox, do_load_raw, do_load_remote_content_rules,
+        do_load_sync_state, do_load_thread, do_merge_messages, do_queue_outgoing,
+        do_remove_account, do_remove_folder, do_remove_message, do_remove_messages,
+        do_rename_folder, do_requeue_interrupted_outbox, do_requeue_outbox, do_rethread_account,
+        do_revert_pending_op, do_revoke_remote_content, do_save_attachment, do_save_body,
+        do_save_folders, do_save_messages, do_save_raw, do_search, do_set_folder_subscribed,
+        do_set_outbox_sent, do_take_outbox, do_update_flags, do_update_flags_batch,
+        do_update_keywords, do_update_outbox, do_upsert_folder,
     };
-    use crate::models::{AttachmentData, Folder, MessageSummary};
-    use crate::store::flags::flags_to_u8;
+    use crate::mime::RemoteContentRule;
+    use crate::models::{
+        AttachmentData, AttachmentInfo, EmailAddress, Folder, FolderRole, MailboxDelta,
+        MailboxSyncState, MessageSummary, OutboxStatus,
+    };
+    use crate::smtp::OutgoingEmail;
+    use crate::store::flags::{flags_to_u8, summary_flags, DELETED};
     use crate::store::schema::{run_migrations, SCHEMA};
 
     fn setup_conn() -> Connection {
@@ -635,6 +1693,7 @@ mod tests {
             in_reply_to: None,
             reply_to: None,
             thread_depth: 0,
+            ..Default::default()
         }
     }
 
@@ -651,6 +1710,8 @@ mod tests {
                 unread_count: 1,
                 total_count: 2,
                 mailbox_hash: 1,
+                is_subscribed: true,
+                role: None,
             }],
         )
         .expect("save folders a");
@@ -663,6 +1724,8 @@ mod tests {
                 unread_count: 9,
                 total_count: 10,
                 mailbox_hash: 1,
+                is_subscribed: true,
+                role: None,
             }],
         )
         .expect("save folders b");
@@ -688,6 +1751,8 @@ mod tests {
                 unread_count: 0,
                 total_count: 0,
                 mailbox_hash: 1,
+                is_subscribed: true,
+                role: None,
             }],
         )
         .expect("save folder a");
@@ -700,6 +1765,8 @@ mod tests {
                 unread_count: 0,
                 total_count: 0,
                 mailbox_hash: 1,
+                is_subscribed: true,
+                role: None,
             }],
         )
         .expect("save folder b");
@@ -720,10 +1787,12 @@ mod tests {
             42,
             "md body",
             "plain body",
-            &[AttachmentData {
+            &[AttachmentInfo {
+                part: "2".to_string(),
                 filename: "a.txt".to_string(),
                 mime_type: "text/plain".to_string(),
-                data: b"hello".to_vec(),
+                size: 5,
+                ..Default::default()
             }],
         )
         .expect("save body a");
@@ -748,4 +1817,708 @@ mod tests {
         assert!(a_after_remove.is_empty());
         assert_eq!(b_after_remove.len(), 1);
     }
+
+    #[test]
+    fn batch_flag_updates_and_removal_apply_to_every_message() {
+        let conn = setup_conn();
+        do_save_folders(
+            &conn,
+            "a",
+            &[Folder {
+                name: "INBOX".into(),
+                path: "INBOX".into(),
+                unread_count: 0,
+                total_count: 0,
+                mailbox_hash: 1,
+                is_subscribed: true,
+                role: None,
+            }],
+        )
+        .expect("save folder");
+        do_save_messages(
+            &conn,
+            "a",
+            1,
+            &[
+                sample_message(1, 1, "one"),
+                sample_message(2, 1, "two"),
+                sample_message(3, 1, "three"),
+            ],
+        )
+        .expect("save messages");
+
+        let read = flags_to_u8(true, false);
+        do_update_flags_batch(&conn, "a", &[(1, read), (2, read)], "mark_read")
+            .expect("batch update flags");
+        let loaded = do_load_messages(&conn, "a", 1, 50, 0).expect("load after update");
+        let read_count = loaded.iter().filter(|m| m.is_read).count();
+        assert_eq!(read_count, 2);
+
+        do_clear_pending_op_batch(&conn, "a", &[(1, read), (2, read)]).expect("batch clear");
+        let pending: i64 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM messages WHERE account_id = 'a' AND pending_op IS NOT NULL",
+                [],
+                |row| row.get(0),
+            )
+            .expect("count pending");
+        assert_eq!(pending, 0);
+
+        do_remove_messages(&conn, "a", &[1, 3]).expect("batch remove");
+        let remaining = do_load_messages(&conn, "a", 1, 50, 0).expect("load after remove");
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining[0].envelope_hash, 2);
+        assert!(remaining[0].is_read);
+    }
+
+    #[test]
+    fn folder_create_rename_subscribe_and_delete_update_cache() {
+        let conn = setup_conn();
+        let inbox = Folder {
+            name: "INBOX".into(),
+            path: "INBOX".into(),
+            unread_count: 0,
+            total_count: 0,
+            mailbox_hash: 1,
+            is_subscribed: true,
+            role: Some(FolderRole::Inbox),
+        };
+        do_save_folders(&conn, "a", &[inbox]).expect("save folders");
+
+        do_upsert_folder(
+            &conn,
+            "a",
+            &Folder {
+                name: "Receipts".into(),
+                path: "Receipts".into(),
+                unread_count: 0,
+                total_count: 0,
+                mailbox_hash: 2,
+                is_subscribed: true,
+                role: None,
+            },
+        )
+        .expect("create folder");
+        do_save_messages(&conn, "a", 2, &[sample_message(7, 2, "receipt")]).expect("save messages");
+
+        let renamed = Folder {
+            name: "Invoices".into(),
+            path: "Archive/Invoices".into(),
+            unread_count: 0,
+            total_count: 1,
+            mailbox_hash: 3,
+            is_subscribed: true,
+            role: None,
+        };
+        do_rename_folder(&conn, "a", 2, &renamed).expect("rename folder");
+        let folders = do_load_folders(&conn, "a").expect("load after rename");
+        let paths: Vec<&str> = folders.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(paths, vec!["INBOX", "Archive/Invoices"]);
+        let moved = do_load_messages(&conn, "a", 3, 50, 0).expect("load renamed messages");
+        assert_eq!(moved.len(), 1);
+        assert_eq!(moved[0].mailbox_hash, 3);
+
+        do_set_folder_subscribed(&conn, "a", 3, false).expect("unsubscribe");
+        let folders = do_load_folders(&conn, "a").expect("load after unsubscribe");
+        assert!(
+            !folders
+                .iter()
+                .find(|f| f.mailbox_hash == 3)
+                .unwrap()
+                .is_subscribed
+        );
+
+        do_remove_folder(&conn, "a", 3).expect("delete folder");
+        let folders = do_load_folders(&conn, "a").expect("load after delete");
+        assert_eq!(folders.len(), 1);
+        assert_eq!(folders[0].role, Some(FolderRole::Inbox));
+        let orphans: i64 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM messages WHERE account_id = 'a' AND mailbox_hash = 3",
+                [],
+                |row| row.get(0),
+            )
+            .expect("count orphans");
+        assert_eq!(orphans, 0);
+    }
+
+    #[test]
+    fn incremental_delta_inserts_updates_and_prunes_without_touching_pending_rows() {
+        let conn = setup_conn();
+        do_save_folders(
+            &conn,
+            "a",
+            &[Folder {
+                name: "INBOX".into(),
+                path: "INBOX".into(),
+                unread_count: 0,
+                total_count: 0,
+                mailbox_hash: 1,
+                is_subscribed: true,
+                role: Some(FolderRole::Inbox),
+            }],
+        )
+        .expect("save folder");
+        assert_eq!(do_load_sync_state(&conn, "a", 1).expect("load state"), None);
+
+        let first = MailboxDelta {
+            state: MailboxSyncState {
+                uidvalidity: 7,
+                uidnext: 4,
+                highestmodseq: Some(100),
+            },
+            reset: true,
+            new_messages: vec![
+                sample_message(1, 1, "one"),
+                sample_message(2, 1, "two"),
+                sample_message(3, 1, "three"),
+            ],
+            flag_updates: Vec::new(),
+            present: None,
+        };
+        do_apply_mailbox_delta(&conn, "a", 1, &first).expect("apply first sync");
+        assert_eq!(
+            do_load_sync_state(&conn, "a", 1).expect("load state"),
+            Some(first.state)
+        );
+
+        // A local "mark read" on 2 is still in flight when the next sync lands.
+        do_update_flags(&conn, "a", 2, flags_to_u8(true, false), "mark_read").expect("pending op");
+
+        let second = MailboxDelta {
+            state: MailboxSyncState {
+                uidvalidity: 7,
+                uidnext: 5,
+                highestmodseq: Some(104),
+            },
+            reset: false,
+            new_messages: vec![sample_message(4, 1, "four")],
+            flag_updates: vec![
+                (1, flags_to_u8(true, true), Vec::new()),
+                (2, flags_to_u8(false, true), Vec::new()),
+            ],
+            present: Some(vec![1, 2, 4]),
+        };
+        do_apply_mailbox_delta(&conn, "a", 1, &second).expect("apply second sync");
+
+        let messages = do_load_messages(&conn, "a", 1, 50, 0).expect("load messages");
+        let by_hash = |hash: u64| messages.iter().find(|m| m.envelope_hash == hash);
+        assert_eq!(messages.len(), 3);
+        assert!(by_hash(3).is_none(), "expunged message is pruned");
+        assert_eq!(by_hash(4).expect("new message").subject, "four");
+        let one = by_hash(1).expect("message 1");
+        assert!(one.is_read && one.is_starred);
+        let two = by_hash(2).expect("message 2");
+        assert!(two.is_read && !two.is_starred, "local override survives");
+        assert_eq!(
+            do_load_sync_state(&conn, "a", 1).expect("load state"),
+            Some(second.state)
+        );
+
+        do_remove_folder(&conn, "a", 1).expect("remove folder");
+        assert_eq!(do_load_sync_state(&conn, "a", 1).expect("load state"), None);
+    }
+
+    #[test]
+    fn merging_a_window_keeps_older_messages_and_bodies() {
+        let conn = setup_conn();
+        do_save_folders(
+            &conn,
+            "a",
+            &[Folder {
+                name: "INBOX".into(),
+                path: "INBOX".into(),
+                unread_count: 0,
+                total_count: 0,
+                mailbox_hash: 1,
+                is_subscribed: true,
+                role: None,
+            }],
+        )
+        .expect("save folder");
+
+        do_merge_messages(
+            &conn,
+            "a",
+            1,
+            &[sample_message(1, 1, "old"), sample_message(2, 1, "older")],
+        )
+        .expect("merge older window");
+        do_save_body(&conn, "a", 2, "md", "plain", &[]).expect("save body");
+
+        let mut refreshed = sample_message(2, 1, "older");
+        refreshed.is_read = true;
+        do_merge_messages(&conn, "a", 1, &[sample_message(3, 1, "new"), refreshed])
+            .expect("merge newest window");
+
+        let messages = do_load_messages(&conn, "a", 1, 50, 0).expect("load messages");
+        assert_eq!(messages.len(), 3);
+        assert!(
+            messages
+                .iter()
+                .find(|m| m.envelope_hash == 2)
+                .expect("message 2")
+                .is_read
+        );
+        assert!(do_load_body(&conn, "a", 2).expect("load body").is_some());
+    }
+
+    #[test]
+    fn keywords_and_full_flags_follow_dual_truth() {
+        let conn = setup_conn();
+        do_save_folders(
+            &conn,
+            "a",
+            &[Folder {
+                name: "INBOX".into(),
+                path: "INBOX".into(),
+                unread_count: 0,
+                total_count: 0,
+                mailbox_hash: 1,
+                is_subscribed: true,
+                role: None,
+            }],
+        )
+        .expect("save folder");
+
+        let mut message = sample_message(1, 1, "one");
+        message.is_answered = true;
+        message.is_forwarded = true;
+        message.keywords = vec!["$Label1".to_string()];
+        do_save_messages(&conn, "a", 1, &[message]).expect("save message");
+        let load = || do_load_messages(&conn, "a", 1, 50, 0).expect("load")[0].clone();
+        let loaded = load();
+        assert!(loaded.is_answered && loaded.is_forwarded && !loaded.is_draft);
+        assert_eq!(loaded.keywords, vec!["$Label1"]);
+
+        // A local keyword change wins over the server until the op settles.
+        let junk = vec!["$Label1".to_string(), "$Junk".to_string()];
+        do_update_keywords(&conn, "a", 1, &junk, "tag").expect("update keywords");
+        let delta = MailboxDelta {
+            state: MailboxSyncState::default(),
+            reset: false,
+            new_messages: Vec::new(),
+            flag_updates: vec![(1, summary_flags(&loaded) | DELETED, Vec::new())],
+            present: None,
+        };
+        do_apply_mailbox_delta(&conn, "a", 1, &delta).expect("apply delta");
+        assert_eq!(load().keywords, junk);
+
+        do_revert_pending_op(&conn, "a", 1).expect("revert");
+        let reverted = load();
+        assert!(reverted.keywords.is_empty());
+        assert!(reverted.is_deleted && reverted.is_answered);
+
+        do_update_keywords(&conn, "a", 1, &junk, "tag").expect("update keywords");
+        do_clear_pending_op(&conn, "a", 1, summary_flags(&reverted)).expect("clear");
+        assert_eq!(load().keywords, junk);
+    }
+
+    #[test]
+    fn addresses_round_trip_and_go_with_their_message() {
+        let conn = setup_conn();
+        do_save_folders(
+            &conn,
+            "a",
+            &[Folder {
+                name: "INBOX".into(),
+                path: "INBOX".into(),
+                unread_count: 0,
+                total_count: 0,
+                mailbox_hash: 1,
+                is_subscribed: true,
+                role: None,
+            }],
+        )
+        .expect("save folder");
+        let mut message = sample_message(1, 1, "one");
+        message.addresses.to = vec![
+            EmailAddress {
+                name: Some("Ann".into()),
+                email: "ann@example.com".into(),
+            },
+            EmailAddress {
+                name: None,
+                email: "bob@example.com".into(),
+            },
+        ];
+        message.addresses.cc = vec![EmailAddress {
+            name: None,
+            email: "carol@example.com".into(),
+        }];
+        do_save_messages(&conn, "a", 1, &[message.clone()]).expect("save message");
+
+        let loaded = do_load_messages(&conn, "a", 1, 50, 0).expect("load");
+        assert_eq!(loaded[0].addresses, message.addresses);
+
+        // A refresh with the same addresses leaves their rows alone.
+        conn.execute_batch(
+            "CREATE TEMP TABLE address_deletes (n INTEGER);
+             CREATE TEMP TRIGGER count_address_deletes AFTER DELETE ON message_addresses
+             BEGIN INSERT INTO address_deletes VALUES (1); END;",
+        )
+        .expect("count deletes");
+        do_save_messages(&conn, "a", 1, &[message.clone()]).expect("save again");
+        let deletes = |conn: &Connection| -> i64 {
+            conn.query_row("SELECT COUNT(*) FROM address_deletes", [], |row| row.get(0))
+                .expect("count deletes")
+        };
+        assert_eq!(deletes(&conn), 0);
+        message.addresses.cc.clear();
+        do_save_messages(&conn, "a", 1, &[message.clone()]).expect("save changed");
+        assert_eq!(deletes(&conn), 3);
+
+        // Search results span accounts and still carry their addresses.
+        do_save_folders(
+            &conn,
+            "b",
+            &[Folder {
+                name: "INBOX".into(),
+                path: "INBOX".into(),
+                unread_count: 0,
+                total_count: 0,
+                mailbox_hash: 1,
+                is_subscribed: true,
+                role: None,
+            }],
+        )
+        .expect("save folder");
+        let mut other = sample_message(1, 1, "one");
+        other.addresses.from = vec![EmailAddress {
+            name: None,
+            email: "dave@example.com".into(),
+        }];
+        do_save_messages(&conn, "b", 1, &[other.clone()]).expect("save other");
+        let found = do_search(&conn, "one").expect("search");
+        assert_eq!(found.len(), 2);
+        assert!(found.iter().any(|m| m.addresses == message.addresses));
+        assert!(found.iter().any(|m| m.addresses == other.addresses));
+
+        let known: i64 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM message_addresses WHERE account_id = 'a' AND email = ?1",
+                ["ann@example.com"],
+                |row| row.get(0),
+            )
+            .expect("query by email");
+        assert_eq!(known, 1);
+
+        do_remove_message(&conn, "a", 1).expect("remove message");
+        let left: i64 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM message_addresses WHERE account_id = 'a'",
+                [],
+                |row| row.get(0),
+            )
+            .expect("count addresses");
+        assert_eq!(left, 0);
+    }
+
+    #[test]
+    fn rethread_links_replies_across_folders() {
+        let conn = setup_conn();
+        let folder = |name: &str, mailbox_hash: u64| Folder {
+            name: name.into(),
+            path: name.into(),
+            unread_count: 0,
+            total_count: 0,
+            mailbox_hash,
+            is_subscribed: true,
+            role: None,
+        };
+        do_save_folders(&conn, "a", &[folder("INBOX", 1), folder("Sent", 2)])
+            .expect("save folders");
+
+        let root = sample_message(1, 1, "Plans");
+        let mut reply = sample_message(2, 2, "Re: Plans");
+        reply.in_reply_to = Some("<1@example.com>".into());
+        reply.references = vec!["<1@example.com>".into()];
+        reply.timestamp = 200;
+        let mut answer = sample_message(3, 1, "Re: Plans");
+        answer.in_reply_to = Some("<2@example.com>".into());
+        answer.references = vec!["<1@example.com>".into(), "<2@example.com>".into()];
+        answer.timestamp = 300;
+        let other = sample_message(4, 1, "Unrelated");
+        do_save_messages(&conn, "a", 1, &[root, answer, other]).expect("save inbox");
+        do_save_messages(&conn, "a", 2, &[reply]).expect("save sent");
+
+        assert_eq!(do_rethread_account(&conn, "a", false).expect("rethread"), 4);
+        assert_eq!(do_rethread_account(&conn, "a", false).expect("rethread"), 0);
+
+        let inbox = do_load_messages(&conn, "a", 1, 50, 0).expect("load inbox");
+        let root = inbox.iter().find(|m| m.envelope_hash == 1).expect("root");
+        let thread_id = root.thread_id.expect("thread id");
+        let thread = do_load_thread(&conn, "a", thread_id).expect("load thread");
+        let placement: Vec<_> = thread
+            .iter()
+            .map(|m| (m.envelope_hash, m.thread_parent, m.thread_depth))
+            .collect();
+        assert_eq!(
+            placement,
+            vec![(1, None, 0), (2, Some(1), 1), (3, Some(2), 2)]
+        );
+
+        let other = inbox.iter().find(|m| m.envelope_hash == 4).expect("other");
+        assert_ne!(other.thread_id, Some(thread_id));
+    }
+
+    #[test]
+    fn raw_source_is_optional_and_goes_with_its_message() {
+        let conn = setup_conn();
+        do_save_folders(
+            &conn,
+            "a",
+            &[Folder {
+                name: "INBOX".into(),
+                path: "INBOX".into(),
+                unread_count: 0,
+                total_count: 0,
+                mailbox_hash: 1,
+                is_subscribed: true,
+                role: None,
+            }],
+        )
+        .expect("save folder");
+        do_save_messages(&conn, "a", 1, &[sample_message(1, 1, "one")]).expect("save message");
+        assert_eq!(do_load_raw(&conn, "a", 1).expect("load"), None);
+
+        do_save_raw(&conn, "a", 1, b"Subject: one\r\n\r\nold").expect("save raw");
+        do_save_raw(&conn, "a", 1, b"Subject: one\r\n\r\nbody").expect("replace raw");
+        assert_eq!(
+            do_load_raw(&conn, "a", 1).expect("load"),
+            Some(b"Subject: one\r\n\r\nbody".to_vec())
+        );
+        assert_eq!(do_load_raw(&conn, "b", 1).expect("other account"), None);
+
+        // A refresh that still lists the message keeps its source and any
+        // downloaded attachment; one that drops it drops them too.
+        do_save_attachment(&conn, "a", 1, "2", b"pdf bytes").expect("save attachment");
+        do_save_messages(&conn, "a", 1, &[sample_message(1, 1, "one")]).expect("refresh");
+        assert!(do_load_raw(&conn, "a", 1).expect("load").is_some());
+        assert!(do_load_attachment(&conn, "a", 1, "2")
+            .expect("load")
+            .is_some());
+        do_save_raw(&conn, "a", 2, b"Subject: two\r\n\r\nbody").expect("save raw");
+        do_save_messages(
+            &conn,
+            "a",
+            1,
+            &[sample_message(1, 1, "one"), sample_message(2, 1, "two")],
+        )
+        .expect("refresh");
+        do_save_messages(&conn, "a", 1, &[sample_message(1, 1, "one")]).expect("expunge two");
+        assert_eq!(do_load_raw(&conn, "a", 2).expect("load"), None);
+        assert!(do_load_raw(&conn, "a", 1).expect("load").is_some());
+
+        do_remove_message(&conn, "a", 1).expect("remove message");
+        let left: i64 = conn
+            .query_row("SELECT COUNT(*) FROM message_sources", [], |row| row.get(0))
+            .expect("count sources");
+        assert_eq!(left, 0);
+    }
+
+    #[test]
+    fn attachments_are_listed_first_and_downloaded_on_demand() {
+        let conn = setup_conn();
+        do_save_folders(
+            &conn,
+            "a",
+            &[Folder {
+                name: "INBOX".into(),
+                path: "INBOX".into(),
+                unread_count: 0,
+                total_count: 0,
+                mailbox_hash: 1,
+                is_subscribed: true,
+                role: None,
+            }],
+        )
+        .expect("save folder");
+        do_save_messages(&conn, "a", 1, &[sample_message(1, 1, "one")]).expect("save message");
+
+        let logo = AttachmentInfo {
+            part: "1.2".into(),
+            filename: "logo.png".into(),
+            mime_type: "image/png".into(),
+            size: 1918,
+            content_id: Some("logo@x".into()),
+            is_inline: true,
+            encoding: "base64".into(),
+        };
+        do_save_body(&conn, "a", 1, "md", "plain", std::slice::from_ref(&logo)).expect("save body");
+        let (_, _, listed) = do_load_body(&conn, "a", 1).expect("load").expect("cached");
+        assert_eq!(listed, vec![logo.clone()]);
+        assert_eq!(
+            do_load_attachment(&conn, "a", 1, "1.2").expect("load"),
+            None
+        );
+
+        do_save_attachment(&conn, "a", 1, "1.2", b"png bytes").expect("save attachment");
+        do_save_body(&conn, "a", 1, "md", "plain", &[logo]).expect("re-save body");
+        assert_eq!(
+            do_load_attachment(&conn, "a", 1, "1.2").expect("load"),
+            Some(b"png bytes".to_vec())
+        );
+
+        // Rows from eager downloads carry no part number and force a refetch.
+        conn.execute("UPDATE attachments SET part = NULL", [])
+            .expect("simulate legacy row");
+        assert!(do_load_body(&conn, "a", 1).expect("load").is_none());
+
+        do_remove_message(&conn, "a", 1).expect("remove message");
+        let left: i64 = conn
+            .query_row("SELECT COUNT(*) FROM attachment_data", [], |row| row.get(0))
+            .expect("count attachment data");
+        assert_eq!(left, 0);
+    }
+
+    #[test]
+    fn outbox_keeps_attachments_and_tracks_status() {
+        let conn = setup_conn();
+        let email = OutgoingEmail {
+            from: "me@example.com".into(),
+            to: EmailAddress::parse_list("you@example.com").expect("to"),
+            subject: "Report".into(),
+            attachments: vec![AttachmentData {
+                filename: "report.pdf".into(),
+                mime_type: "application/pdf".into(),
+                data: vec![0, 1, 2, 255],
+                content_id: None,
+            }],
+            ..Default::default()
+        };
+        let id = do_queue_outgoing(&conn, "a", &email, true, 100, 100).expect("queue");
+        let other = do_queue_outgoing(&conn, "a", &email, false, 100, 0).expect("queue");
+
+        let entries = do_load_outbox(&conn, "a").expect("load");
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].id, id);
+        assert_eq!(entries[0].status, OutboxStatus::Queued);
+        assert_eq!(entries[0].email.attachments[0].data, vec![0, 1, 2, 255]);
+        assert!(entries[0].append_to_sent);
+
+        // Only failed entries can be requeued.
+        assert!(!do_requeue_outbox(&conn, id, 200).expect("requeue"));
+        do_update_outbox(&conn, id, OutboxStatus::Failed, 1, Some("550 no"), 100).expect("fail");
+        assert!(do_requeue_outbox(&conn, id, 200).expect("requeue"));
+        let entry = &do_load_outbox(&conn, "a").expect("load")[0];
+        assert_eq!(
+            (entry.status, entry.attempts, entry.last_error.as_deref()),
+            (OutboxStatus::Queued, 0, None)
+        );
+        assert_eq!(entry.next_attempt_at, 200);
+
+        do_set_outbox_sent(&conn, id, b"raw message").expect("sent");
+        let entry = &do_load_outbox(&conn, "a").expect("load")[0];
+        assert_eq!(entry.status, OutboxStatus::Appending);
+        assert_eq!(entry.sent.as_deref(), Some(&b"raw message"[..]));
+
+        // A message being handed to the server can't be taken back.
+        do_update_outbox(&conn, other, OutboxStatus::Sending, 0, None, 100).expect("sending");
+        assert!(do_take_outbox(&conn, other).expect("take").is_none());
+        do_update_outbox(&conn, other, OutboxStatus::Queued, 1, Some("451"), 130).expect("retry");
+        let taken = do_take_outbox(&conn, other).expect("take").expect("queued");
+        assert_eq!(taken.subject, "Report");
+
+        do_remove_account(&conn, "a").expect("remove account");
+        assert!(do_load_outbox(&conn, "a").expect("load").is_empty());
+    }
+
+    #[test]
+    fn only_waiting_entries_can_be_claimed() {
+        let conn = setup_conn();
+        let email = OutgoingEmail {
+            from: "me@example.com".into(),
+            ..Default::default()
+        };
+        let id = do_queue_outgoing(&conn, "a", &email, false, 100, 100).expect("queue");
+        let undone = do_queue_outgoing(&conn, "a", &email, false, 100, 110).expect("queue");
+
+        assert!(do_claim_outbox(&conn, id).expect("claim"));
+        assert!(!do_claim_outbox(&conn, id).expect("claim twice"));
+        assert!(do_take_outbox(&conn, undone).expect("take").is_some());
+        assert!(!do_claim_outbox(&conn, undone).expect("claim removed"));
+
+        // A send cut short by an exit is picked up again on the next start.
+        do_requeue_interrupted_outbox(&conn, "a").expect("requeue");
+        assert_eq!(
+            do_load_outbox(&conn, "a").expect("load")[0].status,
+            OutboxStatus::Queued
+        );
+        assert!(do_claim_outbox(&conn, id).expect("claim again"));
+    }
+
+    #[test]
+    fn delivered_entries_cannot_be_taken_back() {
+        let conn = setup_conn();
+        let email = OutgoingEmail {
+            from: "me@example.com".into(),
+            ..Default::default()
+        };
+        let id = do_queue_outgoing(&conn, "a", &email, false, 100, 100).expect("queue");
+        do_set_outbox_sent(&conn, id, b"raw message").expect("sent");
+
+        // Only the copy to Sent is left; handing it back would resend it.
+        assert!(do_take_outbox(&conn, id).expect("take").is_none());
+        let entries = do_load_outbox(&conn, "a").expect("load");
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].status, OutboxStatus::Appending);
+    }
+
+    #[test]
+    fn delayed_mail_is_held_and_can_be_taken_back() {
+        let conn = setup_conn();
+        let email = OutgoingEmail {
+            from: "me@example.com".into(),
+            subject: "Oops".into(),
+            ..Default::default()
+        };
+        let undo = do_queue_outgoing(&conn, "a", &email, false, 100, 110).expect("queue");
+        let later = do_queue_outgoing(&conn, "a", &email, false, 100, 86_500).expect("queue");
+
+        let entries = do_load_outbox(&conn, "a").expect("load");
+        assert_eq!(
+            entries
+                .iter()
+                .map(|e| (e.id, e.status, e.next_attempt_at, e.send_at))
+                .collect::<Vec<_>>(),
+            [
+                (undo, OutboxStatus::Scheduled, 110, 110),
+                (later, OutboxStatus::Scheduled, 86_500, 86_500),
+            ]
+        );
+
+        let draft = do_take_outbox(&conn, undo).expect("take").expect("held");
+        assert_eq!(draft.subject, "Oops");
+        assert_eq!(do_load_outbox(&conn, "a").expect("load").len(), 1);
+    }
+
+    #[test]
+    fn remote_content_rules_persist_per_account() {
+        let conn = setup_conn();
+        let news = RemoteContentRule::domain("News.Example.com");
+        let friend = RemoteContentRule::sender("friend@other.org");
+        do_allow_remote_content(&conn, "a", &news).expect("allow");
+        do_allow_remote_content(&conn, "a", &news).expect("allow twice");
+        do_allow_remote_content(&conn, "a", &friend).expect("allow");
+        do_allow_remote_content(&conn, "b", &friend).expect("allow");
+        assert_eq!(
+            do_load_remote_content_rules(&conn, "a").expect("load"),
+            vec![news.clone(), friend.clone()]
+        );
+
+        do_revoke_remote_content(&conn, "a", &news).expect("revoke");
+        assert_eq!(
+            do_load_remote_content_rules(&conn, "a").expect("load"),
+            vec![friend.clone()]
+        );
+        do_remove_account(&conn, "a").expect("remove account");
+        assert!(do_load_remote_content_rules(&conn, "a")
+            .expect("load")
+            .is_empty());
+        assert_eq!(
+            do_load_remote_content_rules(&conn, "b").expect("load"),
+            vec![friend]
+        );
+    }
 }
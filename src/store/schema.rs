@@ -43,6 +43,71 @@ CREATE TABLE IF NOT EXISTS attachments (
     PRIMARY KEY (account_id, envelope_hash, idx),
     FOREIGN KEY (account_id, envelope_hash) REFERENCES messages(account_id, envelope_hash) ON DELETE CASCADE
 );
+
+-- One row per address per header; `field` is from/to/cc/bcc/reply_to/sender.
+CREATE TABLE IF NOT EXISTS message_addresses (
+    account_id TEXT NOT NULL,
+    envelope_hash INTEGER NOT NULL,
+    field TEXT NOT NULL,
+    position INTEGER NOT NULL,
+    name TEXT,
+    email TEXT NOT NULL,
+    PRIMARY KEY (account_id, envelope_hash, field, position)
+);
+
+CREATE INDEX IF NOT EXISTS idx_message_addresses_email
+    ON message_addresses(account_id, email);
+
+-- Attachment bytes, downloaded on demand; metadata lives in `attachments`.
+CREATE TABLE IF NOT EXISTS attachment_data (
+    account_id TEXT NOT NULL,
+    envelope_hash INTEGER NOT NULL,
+    part TEXT NOT NULL,
+    data BLOB NOT NULL,
+    PRIMARY KEY (account_id, envelope_hash, part)
+);
+
+-- Full RFC 5322 source, cached only on request (view source, .eml export).
+CREATE TABLE IF NOT EXISTS message_sources (
+    account_id TEXT NOT NULL,
+    envelope_hash INTEGER NOT NULL,
+    raw BLOB NOT NULL,
+    PRIMARY KEY (account_id, envelope_hash)
+);
+
+-- Mail waiting to be sent. `email` is the OutgoingEmail as JSON; `sent` holds
+-- the accepted bytes while the copy for the Sent folder is pending.
+CREATE TABLE IF NOT EXISTS outbox (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    account_id TEXT NOT NULL,
+    email TEXT NOT NULL,
+    status TEXT NOT NULL DEFAULT 'queued',
+    attempts INTEGER NOT NULL DEFAULT 0,
+    last_error TEXT,
+    next_attempt_at INTEGER NOT NULL,
+    created_at INTEGER NOT NULL,
+    append_to_sent INTEGER NOT NULL DEFAULT 0,
+    sent BLOB
+);
+
+CREATE INDEX IF NOT EXISTS idx_outbox_account ON outbox(account_id, created_at);
+
+-- Senders and domains whose remote images are always loaded.
+CREATE TABLE IF NOT EXISTS remote_content_allowlist (
+    account_id TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    value TEXT NOT NULL,
+    PRIMARY KEY (account_id, kind, value)
+);
+
+CREATE TABLE IF NOT EXISTS mailbox_sync_state (
+    account_id TEXT NOT NULL,
+    mailbox_hash INTEGER NOT NULL,
+    uidvalidity INTEGER NOT NULL,
+    uidnext INTEGER NOT NULL,
+    highestmodseq INTEGER,
+    PRIMARY KEY (account_id, mailbox_hash)
+);
 ";
 
 /// Run forward-only migrations. Each ALTER is idempotent (ignores "duplicate column" errors).
@@ -60,6 +125,24 @@ pub(super) fn run_migrations(conn: &Connection) {
         // Multi-account support
         "ALTER TABLE folders ADD COLUMN account_id TEXT DEFAULT ''",
         "ALTER TABLE messages ADD COLUMN account_id TEXT DEFAULT ''",
+        // Folder management
+        "ALTER TABLE folders ADD COLUMN subscribed INTEGER DEFAULT 1",
+        "ALTER TABLE folders ADD COLUMN role TEXT",
+        // IMAP keywords, space-separated, with the same dual-truth split as flags
+        "ALTER TABLE messages ADD COLUMN keywords_server TEXT",
+        "ALTER TABLE messages ADD COLUMN keywords_local TEXT",
+        // Threading: raw References (space-separated) and the computed parent
+        "ALTER TABLE messages ADD COLUMN refs TEXT",
+        "ALTER TABLE messages ADD COLUMN thread_parent INTEGER",
+        // Lazy attachments: BODYSTRUCTURE metadata. `data` is left empty;
+        // bytes live in attachment_data once fetched.
+        "ALTER TABLE attachments ADD COLUMN part TEXT",
+        "ALTER TABLE attachments ADD COLUMN size INTEGER DEFAULT 0",
+        "ALTER TABLE attachments ADD COLUMN content_id TEXT",
+        "ALTER TABLE attachments ADD COLUMN is_inline INTEGER DEFAULT 0",
+        "ALTER TABLE attachments ADD COLUMN encoding TEXT",
+        // Undo-send and scheduled send
+        "ALTER TABLE outbox ADD COLUMN send_at INTEGER",
     ];
     for sql in &alters {
         // "duplicate column name" is the expected error when already migrated
@@ -80,6 +163,7 @@ pub(super) fn run_migrations(conn: &Connection) {
         "CREATE INDEX IF NOT EXISTS idx_messages_message_id ON messages(message_id)",
         "CREATE INDEX IF NOT EXISTS idx_folders_account ON folders(account_id)",
         "CREATE INDEX IF NOT EXISTS idx_messages_account_mailbox ON messages(account_id, mailbox_hash, timestamp DESC)",
+        "CREATE INDEX IF NOT EXISTS idx_messages_thread ON messages(account_id, thread_id)",
     ];
     for sql in &indexes {
         if let Err(e) = conn.execute(sql, []) {
@@ -87,6 +171,34 @@ pub(super) fn run_migrations(conn: &Connection) {
         }
     }
 
+    // Addresses, sources and attachment bytes go with their message. Created
+    // here rather than in SCHEMA because the primary-key migration above
+    // drops triggers on `messages`.
+    if let Err(e) = conn.execute_batch(
+        "CREATE TRIGGER IF NOT EXISTS messages_addresses_ad AFTER DELETE ON messages BEGIN
+          DELETE FROM message_addresses
+          WHERE account_id = old.account_id AND envelope_hash = old.envelope_hash;
+        END",
+    ) {
+        log::warn!("Address trigger creation failed: {}", e);
+    }
+    if let Err(e) = conn.execute_batch(
+        "CREATE TRIGGER IF NOT EXISTS messages_sources_ad AFTER DELETE ON messages BEGIN
+          DELETE FROM message_sources
+          WHERE account_id = old.account_id AND envelope_hash = old.envelope_hash;
+        END",
+    ) {
+        log::warn!("Source trigger creation failed: {}", e);
+    }
+    if let Err(e) = conn.execute_batch(
+        "CREATE TRIGGER IF NOT EXISTS messages_attachment_data_ad AFTER DELETE ON messages BEGIN
+          DELETE FROM attachment_data
+          WHERE account_id = old.account_id AND envelope_hash = old.envelope_hash;
+        END",
+    ) {
+        log::warn!("Attachment data trigger creation failed: {}", e);
+    }
+
     // FTS5 full-text search index (external content, keyed to messages rowid).
     // Column names MUST match the content table for rebuild to work.
     // Drop stale FTS objects from earlier schema that used wrong column name ('body').
@@ -167,6 +279,8 @@ fn migrate_to_account_scoped_primary_keys(conn: &Connection) -> Result<(), Strin
             mailbox_hash INTEGER NOT NULL,
             unread_count INTEGER DEFAULT 0,
             total_count INTEGER DEFAULT 0,
+            subscribed INTEGER DEFAULT 1,
+            role TEXT,
             PRIMARY KEY (account_id, path),
             UNIQUE (account_id, mailbox_hash)
         );
@@ -193,6 +307,10 @@ fn migrate_to_account_scoped_primary_keys(conn: &Connection) -> Result<(), Strin
             body_markdown TEXT,
             reply_to TEXT,
             recipient TEXT,
+            keywords_server TEXT,
+            keywords_local TEXT,
+            refs TEXT,
+            thread_parent INTEGER,
             PRIMARY KEY (account_id, envelope_hash),
             FOREIGN KEY (account_id, mailbox_hash) REFERENCES folders_v2(account_id, mailbox_hash)
         );
@@ -204,6 +322,11 @@ fn migrate_to_account_scoped_primary_keys(conn: &Connection) -> Result<(), Strin
             filename TEXT NOT NULL DEFAULT 'unnamed',
             mime_type TEXT NOT NULL DEFAULT 'application/octet-stream',
             data BLOB NOT NULL,
+            part TEXT,
+            size INTEGER DEFAULT 0,
+            content_id TEXT,
+            is_inline INTEGER DEFAULT 0,
+            encoding TEXT,
             PRIMARY KEY (account_id, envelope_hash, idx),
             FOREIGN KEY (account_id, envelope_hash) REFERENCES messages_v2(account_id, envelope_hash) ON DELETE CASCADE
         );
@@ -213,21 +336,22 @@ fn migrate_to_account_scoped_primary_keys(conn: &Connection) -> Result<(), Strin
 
     tx.execute_batch(
         "
-        INSERT OR REPLACE INTO folders_v2 (account_id, path, name, mailbox_hash, unread_count, total_count)
-        SELECT COALESCE(account_id, ''), path, name, mailbox_hash, unread_count, total_count
+        INSERT OR REPLACE INTO folders_v2 (account_id, path, name, mailbox_hash, unread_count, total_count, subscribed, role)
+        SELECT COALESCE(account_id, ''), path, name, mailbox_hash, unread_count, total_count, COALESCE(subscribed, 1), role
         FROM folders;
 
         INSERT OR REPLACE INTO messages_v2 (
             account_id, envelope_hash, mailbox_hash, subject, sender, date, timestamp,
             is_read, is_starred, has_attachments, thread_id, body_rendered, flags_server,
             flags_local, pending_op, message_id, in_reply_to, thread_depth, body_markdown,
-            reply_to, recipient
+            reply_to, recipient, keywords_server, keywords_local, refs, thread_parent
         )
         SELECT
             COALESCE(account_id, ''), envelope_hash, mailbox_hash, subject, sender, date, timestamp,
             is_read, is_starred, has_attachments, thread_id, body_rendered, COALESCE(flags_server, 0),
             COALESCE(flags_local, 0), pending_op, message_id, in_reply_to, COALESCE(thread_depth, 0),
-            body_markdown, reply_to, recipient
+            body_markdown, reply_to, recipient, keywords_server, keywords_local, refs,
+            thread_parent
         FROM messages;
 
         INSERT OR REPLACE INTO attachments_v2 (account_id, envelope_hash, idx, filename, mime_type, data)
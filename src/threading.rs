@@ -0,0 +1,409 @@
+//! Conversation threading with Jamie Zawinski's algorithm
+//! (<https://www.jwz.org/doc/threading.html>).
+//!
+//! Works on plain header data so it can run over every cached folder of an
+//! account at once; see [`CacheHandle::rethread`](crate::store::CacheHandle::rethread).
+
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+/// The headers threading needs from one message.
+#[derive(Debug, Clone, Default)]
+pub struct ThreadInput {
+    pub envelope_hash: u64,
+    pub message_id: String,
+    pub in_reply_to: Option<String>,
+    /// The References header, oldest ancestor first.
+    pub references: Vec<String>,
+    pub subject: String,
+}
+
+/// Where one message sits in its conversation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ThreadLink {
+    pub envelope_hash: u64,
+    pub thread_id: u64,
+    /// The nearest ancestor we have a copy of. `None` for thread roots.
+    pub parent: Option<u64>,
+    /// Number of ancestors we have a copy of.
+    pub depth: u32,
+}
+
+struct Container {
+    key: String,
+    /// Indexes into the input. More than one when the same Message-ID is
+    /// cached in several folders (e.g. INBOX and Sent).
+    messages: Vec<usize>,
+    parent: Option<usize>,
+    children: Vec<usize>,
+}
+
+#[derive(Default)]
+struct Arena {
+    nodes: Vec<Container>,
+    by_id: HashMap<String, usize>,
+}
+
+impl Arena {
+    fn add(&mut self, key: String) -> usize {
+        self.nodes.push(Container {
+            key,
+            messages: Vec::new(),
+            parent: None,
+            children: Vec::new(),
+        });
+        self.nodes.len() - 1
+    }
+
+    fn container(&mut self, id: &str) -> usize {
+        if let Some(&idx) = self.by_id.get(id) {
+            return idx;
+        }
+        let idx = self.add(id.to_string());
+        self.by_id.insert(id.to_string(), idx);
+        idx
+    }
+
+    /// Whether `ancestor` is `node` or one of its ancestors.
+    fn is_ancestor(&self, ancestor: usize, mut node: usize) -> bool {
+        loop {
+            if node == ancestor {
+                return true;
+            }
+            match self.nodes[node].parent {
+                Some(parent) => node = parent,
+                None => return false,
+            }
+        }
+    }
+
+    fn unlink(&mut self, child: usize) {
+        if let Some(parent) = self.nodes[child].parent.take() {
+            self.nodes[parent].children.retain(|&c| c != child);
+        }
+    }
+
+    /// Make `child` a child of `parent`, unless that would create a loop.
+    fn link(&mut self, parent: usize, child: usize) {
+        if self.is_ancestor(child, parent) {
+            return;
+        }
+        self.unlink(child);
+        self.nodes[child].parent = Some(parent);
+        self.nodes[parent].children.push(child);
+    }
+
+    /// Drop empty containers and promote the children of childless-message
+    /// ones. At the root level a dummy is kept when it joins several threads.
+    fn prune(&mut self, ids: Vec<usize>, parent: Option<usize>) -> Vec<usize> {
+        let mut kept = Vec::new();
+        for id in ids {
+            let children = std::mem::take(&mut self.nodes[id].children);
+            let children = self.prune(children, Some(id));
+            if self.nodes[id].messages.is_empty() && (parent.is_some() || children.len() <= 1) {
+                for &child in &children {
+                    self.nodes[child].parent = parent;
+                }
+                kept.extend(children);
+            } else {
+                self.nodes[id].children = children;
+                self.nodes[id].parent = parent;
+                kept.push(id);
+            }
+        }
+        kept
+    }
+}
+
+/// Strip angle brackets and whitespace so `<a@b>` and `a@b` match.
+fn normalize_id(id: &str) -> &str {
+    id.trim().trim_start_matches('<').trim_end_matches('>')
+}
+
+/// Strip reply/forward prefixes (`Re:`, `Fwd:`, `AW: [2]` ...) for subject
+/// grouping. Returns the base subject and whether any prefix was removed.
+pub fn base_subject(subject: &str) -> (String, bool) {
+    let (rest, is_reply) = strip_subject_prefixes(subject, &["re", "fwd", "fw", "aw", "sv", "wg"]);
+    (rest.to_lowercase(), is_reply)
+}
+
+/// Strip any run of `prefixes` (lowercase, longest first where one starts
+/// another), each followed by an optional `[n]` counter and a colon.
+pub(crate) fn strip_subject_prefixes<'a>(subject: &'a str, prefixes: &[&str]) -> (&'a str, bool) {
+    let mut rest = subject.trim();
+    let mut stripped = false;
+    loop {
+        let lower = rest.to_ascii_lowercase();
+        let Some(prefix) = prefixes.iter().find(|p| lower.starts_with(*p)) else {
+            break;
+        };
+        let after = rest[prefix.len()..].trim_start();
+        // Optional counter, as in "Re[2]:".
+        let after = match after.strip_prefix('[') {
+            Some(counted) => match counted.split_once(']') {
+                Some((n, tail)) if n.chars().all(|c| c.is_ascii_digit()) => tail.trim_start(),
+                _ => after,
+            },
+            None => after,
+        };
+        match after.strip_prefix(':') {
+            Some(tail) => {
+                rest = tail.trim_start();
+                stripped = true;
+            }
+            None => break,
+        }
+    }
+    (rest, stripped)
+}
+
+/// Thread `messages`. Every input message gets exactly one [`ThreadLink`].
+///
+/// With `group_by_subject`, threads whose roots share a base subject are
+/// merged even when no header links them.
+pub fn thread_messages(messages: &[ThreadInput], group_by_subject: bool) -> Vec<ThreadLink> {
+    let mut arena = Arena::default();
+
+    // 1. A container per Message-ID, linked along each References chain.
+    for (idx, message) in messages.iter().enumerate() {
+        let id = normalize_id(&message.message_id);
+        let this = if id.is_empty() {
+            arena.add(format!("{}@no-message-id", message.envelope_hash))
+        } else {
+            arena.container(id)
+        };
+        arena.nodes[this].messages.push(idx);
+
+        let mut chain: Vec<&str> = message
+            .references
+            .iter()
+            .map(|r| normalize_id(r))
+            .filter(|r| !r.is_empty())
+            .collect();
+        if let Some(reply_to) = message.in_reply_to.as_deref().map(normalize_id) {
+            if !reply_to.is_empty() && chain.last() != Some(&reply_to) {
+                chain.push(reply_to);
+            }
+        }
+        chain.retain(|r| *r != id);
+
+        let mut previous: Option<usize> = None;
+        for reference in &chain {
+            let node = arena.container(reference);
+            if let Some(parent) = previous {
+                if arena.nodes[node].parent.is_none() {
+                    arena.link(parent, node);
+                }
+            }
+            previous = Some(node);
+        }
+        // This message's own headers are the authority on its parent.
+        match previous {
+            Some(parent) => arena.link(parent, this),
+            None => arena.unlink(this),
+        }
+    }
+
+    // 2-4. Root set, pruned of empty containers.
+    let roots: Vec<usize> = (0..arena.nodes.len())
+        .filter(|&i| arena.nodes[i].parent.is_none())
+        .collect();
+    let mut roots = arena.prune(roots, None);
+
+    // 5. Optionally merge roots by base subject.
+    if group_by_subject {
+        roots = group_roots_by_subject(&mut arena, messages, roots);
+    }
+
+    // 6. Walk each tree, recording thread, nearest real parent and depth.
+    let mut links = Vec::with_capacity(messages.len());
+    for root in roots {
+        let thread_id = hash_key(&arena.nodes[root].key);
+        let mut stack = vec![(root, None, 0u32)];
+        while let Some((node, parent, depth)) = stack.pop() {
+            let container = &arena.nodes[node];
+            let (own, child_depth) = match container.messages.first() {
+                Some(&first) => (Some(messages[first].envelope_hash), depth + 1),
+                None => (parent, depth),
+            };
+            for &idx in &container.messages {
+                links.push(ThreadLink {
+                    envelope_hash: messages[idx].envelope_hash,
+                    thread_id,
+                    parent,
+                    depth,
+                });
+            }
+            for &child in &container.children {
+                stack.push((child, own, child_depth));
+            }
+        }
+    }
+    links
+}
+
+fn group_roots_by_subject(
+    arena: &mut Arena,
+    messages: &[ThreadInput],
+    roots: Vec<usize>,
+) -> Vec<usize> {
+    let subject_of = |arena: &Arena, node: usize| {
+        let container = &arena.nodes[node];
+        let idx = container.messages.first().copied().or_else(|| {
+            container
+                .children
+                .iter()
+                .find_map(|&c| arena.nodes[c].messages.first().copied())
+        })?;
+        let (base, is_reply) = base_subject(&messages[idx].subject);
+        (!base.is_empty()).then_some((base, is_reply))
+    };
+
+    let mut by_subject: HashMap<String, usize> = HashMap::new();
+    let mut merged = Vec::new();
+    for root in roots {
+        let Some((base, is_reply)) = subject_of(arena, root) else {
+            merged.push(root);
+            continue;
+        };
+        let Some(&existing) = by_subject.get(&base) else {
+            by_subject.insert(base, root);
+            merged.push(root);
+            continue;
+        };
+        let existing_dummy = arena.nodes[existing].messages.is_empty();
+        let existing_reply = subject_of(arena, existing).is_some_and(|(_, r)| r);
+        if existing_dummy && arena.nodes[root].messages.is_empty() {
+            for child in std::mem::take(&mut arena.nodes[root].children) {
+                arena.nodes[child].parent = None;
+                arena.link(existing, child);
+            }
+        } else if existing_dummy || (is_reply && !existing_reply) {
+            arena.link(existing, root);
+        } else if arena.nodes[root].messages.is_empty() || (!is_reply && existing_reply) {
+            arena.link(root, existing);
+            merged.retain(|&r| r != existing);
+            merged.push(root);
+            by_subject.insert(base, root);
+        } else {
+            let dummy = arena.add(format!("subject:{}", base));
+            arena.link(dummy, existing);
+            arena.link(dummy, root);
+            merged.retain(|&r| r != existing);
+            merged.push(dummy);
+            by_subject.insert(base, dummy);
+        }
+    }
+    merged
+}
+
+fn hash_key(key: &str) -> u64 {
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{base_subject, thread_messages, ThreadInput, ThreadLink};
+
+    fn message(hash: u64, id: &str, in_reply_to: Option<&str>, refs: &[&str]) -> ThreadInput {
+        ThreadInput {
+            envelope_hash: hash,
+            message_id: format!("<{}>", id),
+            in_reply_to: in_reply_to.map(|r| format!("<{}>", r)),
+            references: refs.iter().map(|r| format!("<{}>", r)).collect(),
+            subject: "Plans".into(),
+        }
+    }
+
+    fn link(links: &[ThreadLink], hash: u64) -> ThreadLink {
+        *links
+            .iter()
+            .find(|l| l.envelope_hash == hash)
+            .expect("link")
+    }
+
+    #[test]
+    fn in_reply_to_alone_and_broken_chains_still_thread() {
+        let links = thread_messages(
+            &[
+                message(1, "a", None, &[]),
+                // Only In-Reply-To, no References.
+                message(2, "b", Some("a"), &[]),
+                // References skip b, but In-Reply-To names it.
+                message(3, "c", Some("b"), &["a"]),
+                // Parent "x" was never fetched; it still groups with "a".
+                message(4, "d", Some("x"), &["a", "x"]),
+            ],
+            false,
+        );
+        let root = link(&links, 1);
+        assert_eq!((root.parent, root.depth), (None, 0));
+        assert_eq!(link(&links, 2).parent, Some(1));
+        assert_eq!(
+            (link(&links, 3).parent, link(&links, 3).depth),
+            (Some(2), 2)
+        );
+        assert_eq!(
+            (link(&links, 4).parent, link(&links, 4).depth),
+            (Some(1), 1)
+        );
+        assert!(links.iter().all(|l| l.thread_id == root.thread_id));
+    }
+
+    #[test]
+    fn copies_in_several_folders_share_a_place_in_the_thread() {
+        let links = thread_messages(
+            &[
+                message(1, "a", None, &[]),
+                message(2, "b", Some("a"), &["a"]),
+                // The same reply, cached again from Sent.
+                message(3, "b", Some("a"), &["a"]),
+            ],
+            false,
+        );
+        assert_eq!(
+            link(&links, 2),
+            ThreadLink {
+                envelope_hash: 2,
+                ..link(&links, 3)
+            }
+        );
+        assert_eq!(link(&links, 3).parent, Some(1));
+    }
+
+    #[test]
+    fn reference_loops_do_not_hang_or_drop_messages() {
+        let links = thread_messages(
+            &[
+                message(1, "a", Some("b"), &["b"]),
+                message(2, "b", Some("a"), &["a"]),
+            ],
+            false,
+        );
+        assert_eq!(links.len(), 2);
+        assert_eq!(link(&links, 1).thread_id, link(&links, 2).thread_id);
+    }
+
+    #[test]
+    fn subject_grouping_is_optional() {
+        let mut reply = message(2, "b", None, &[]);
+        reply.subject = "Re: Plans".into();
+        let input = [message(1, "a", None, &[]), reply];
+
+        let apart = thread_messages(&input, false);
+        assert_ne!(link(&apart, 1).thread_id, link(&apart, 2).thread_id);
+
+        let grouped = thread_messages(&input, true);
+        assert_eq!(link(&grouped, 1).thread_id, link(&grouped, 2).thread_id);
+        assert_eq!(link(&grouped, 2).parent, Some(1));
+    }
+
+    #[test]
+    fn base_subject_strips_reply_prefixes() {
+        assert_eq!(base_subject("Re: Fwd: Plans"), ("plans".into(), true));
+        assert_eq!(base_subject("AW[2]: plans"), ("plans".into(), true));
+        assert_eq!(base_subject("Review notes"), ("review notes".into(), false));
+    }
+}
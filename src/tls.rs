@@ -0,0 +1,450 @@
+//! Certificate checks for private CAs and pinned certificates.
+//!
+//! melib's IMAP connector only knows the system trust store, and lettre has
+//! no pinning. For those cases the protocol stack talks plaintext to a
+//! loopback [`Relay`], which opens the real connection, checks the server's
+//! certificate on that same TLS session, and only then passes bytes through.
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{Ipv4Addr, TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use native_tls::{Certificate, TlsConnector};
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+
+use crate::config::{TlsSettings, TransportSecurity};
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Which greeting and STARTTLS dialogue to speak.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Protocol {
+    Imap,
+    Smtp,
+}
+
+/// One server whose certificate we check ourselves.
+#[derive(Debug, Clone)]
+pub(crate) struct ServerCheck {
+    pub host: String,
+    pub port: u16,
+    pub security: TransportSecurity,
+    pub tls: TlsSettings,
+    pub protocol: Protocol,
+}
+
+/// A loopback listener standing in for the server. Every client that
+/// connects gets its own checked TLS connection upstream; nothing the
+/// client sends is forwarded until the certificate has passed.
+pub(crate) struct Relay {
+    port: u16,
+    last_error: Arc<Mutex<Option<String>>>,
+    task: JoinHandle<()>,
+}
+
+impl Relay {
+    /// Where the protocol stack should connect, without TLS.
+    pub const HOST: &'static str = "127.0.0.1";
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Why the most recent upstream connection was refused, if it was.
+    pub fn take_error(&self) -> Option<String> {
+        self.last_error.lock().unwrap().take()
+    }
+}
+
+impl Drop for Relay {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl ServerCheck {
+    /// `None` when the protocol stack's own verification is enough: no TLS,
+    /// or no CA file or pin configured.
+    pub fn needed(
+        host: &str,
+        port: u16,
+        security: TransportSecurity,
+        tls: &TlsSettings,
+        protocol: Protocol,
+    ) -> Option<Self> {
+        let custom = tls.ca_file.is_some() || tls.cert_sha256.is_some();
+        (custom && security != TransportSecurity::None).then(|| ServerCheck {
+            host: host.to_string(),
+            port,
+            security,
+            tls: tls.clone(),
+            protocol,
+        })
+    }
+
+    /// Start a [`Relay`] to this server. The CA file is read up front so a
+    /// broken one fails here rather than on every connection.
+    pub async fn relay(&self) -> Result<Relay, String> {
+        self.connector()?;
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .await
+            .map_err(|e| format!("Failed to start TLS relay: {e}"))?;
+        let port = listener
+            .local_addr()
+            .map_err(|e| format!("Failed to start TLS relay: {e}"))?
+            .port();
+        let last_error = Arc::new(Mutex::new(None));
+        let check = Arc::new(self.clone());
+        let errors = Arc::clone(&last_error);
+        let task = tokio::spawn(async move {
+            while let Ok((client, _)) = listener.accept().await {
+                let check = Arc::clone(&check);
+                let errors = Arc::clone(&errors);
+                tokio::spawn(async move {
+                    let mut client = client;
+                    if let Err(e) = check.serve(&mut client).await {
+                        log::warn!("TLS relay to {}: {}", check.host, e);
+                        *errors.lock().unwrap() = Some(e);
+                    }
+                });
+            }
+        });
+        Ok(Relay {
+            port,
+            last_error,
+            task,
+        })
+    }
+
+    /// Connect upstream, check the certificate, then copy bytes both ways.
+    async fn serve(&self, client: &mut tokio::net::TcpStream) -> Result<(), String> {
+        let check = self.clone();
+        let (upstream, greeting) = tokio::task::spawn_blocking(move || check.open_blocking())
+            .await
+            .map_err(|e| format!("Connection to {} aborted: {e}", self.host))??;
+        let connect_err = |e: std::io::Error| format!("Failed to connect to {}: {e}", self.host);
+        upstream.set_nonblocking(true).map_err(connect_err)?;
+        let upstream = tokio::net::TcpStream::from_std(upstream).map_err(connect_err)?;
+        let connector = tokio_native_tls::TlsConnector::from(self.connector()?);
+        let mut tls = tokio::time::timeout(TIMEOUT, connector.connect(&self.host, upstream))
+            .await
+            .map_err(|_| format!("TLS handshake with {} timed out", self.host))?
+            .map_err(|e| format!("TLS handshake with {} failed: {e}", self.host))?;
+        self.check_pin(tls.get_ref())?;
+
+        if let Some(greeting) = greeting {
+            client
+                .write_all(greeting.as_bytes())
+                .await
+                .map_err(|e| format!("TLS relay failed: {e}"))?;
+        }
+        if let Err(e) = tokio::io::copy_bidirectional(client, &mut tls).await {
+            log::debug!("TLS relay to {} closed: {}", self.host, e);
+        }
+        Ok(())
+    }
+
+    /// TCP connect and, for STARTTLS, the plaintext dialogue. Returns the
+    /// greeting the client still has to see, if any.
+    fn open_blocking(&self) -> Result<(TcpStream, Option<String>), String> {
+        let addr = (self.host.as_str(), self.port)
+            .to_socket_addrs()
+            .map_err(|e| format!("Failed to resolve {}: {e}", self.host))?
+            .next()
+            .ok_or_else(|| format!("No address for {}", self.host))?;
+        let stream = TcpStream::connect_timeout(&addr, TIMEOUT)
+            .map_err(|e| format!("Failed to connect to {}: {e}", self.host))?;
+        let greeting = if self.security == TransportSecurity::Starttls {
+            let _ = stream.set_read_timeout(Some(TIMEOUT));
+            let _ = stream.set_write_timeout(Some(TIMEOUT));
+            let greeting = starttls(&stream, self.protocol)?;
+            let _ = stream.set_read_timeout(None);
+            let _ = stream.set_write_timeout(None);
+            greeting
+        } else {
+            None
+        };
+        Ok((stream, greeting))
+    }
+
+    /// Trust the CA file's roots, or nothing but the pin.
+    fn connector(&self) -> Result<TlsConnector, String> {
+        let mut builder = TlsConnector::builder();
+        if self.tls.cert_sha256.is_some() {
+            // The pin is the trust anchor, checked by `check_pin`.
+            builder
+                .danger_accept_invalid_certs(true)
+                .danger_accept_invalid_hostnames(true);
+        } else if let Some(path) = &self.tls.ca_file {
+            for cert in read_ca_file(path)? {
+                builder.add_root_certificate(cert);
+            }
+        }
+        builder
+            .build()
+            .map_err(|e| format!("TLS setup failed: {e}"))
+    }
+
+    fn check_pin<S: std::io::Read + std::io::Write>(
+        &self,
+        tls: &native_tls::TlsStream<S>,
+    ) -> Result<(), String> {
+        let Some(pin) = &self.tls.cert_sha256 else {
+            return Ok(());
+        };
+        let der = tls
+            .peer_certificate()
+            .map_err(|e| format!("Failed to read server certificate: {e}"))?
+            .ok_or_else(|| format!("{} sent no certificate", self.host))?
+            .to_der()
+            .map_err(|e| format!("Failed to read server certificate: {e}"))?;
+        let actual = fingerprint(&der);
+        if actual != normalize_fingerprint(pin) {
+            return Err(format!(
+                "Certificate of {} does not match the pinned fingerprint (got {actual})",
+                self.host
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Every certificate in a PEM bundle. `~/` is expanded.
+fn read_ca_file(path: &Path) -> Result<Vec<Certificate>, String> {
+    let path = expand_home(path);
+    let pem = std::fs::read(&path).map_err(|e| format!("read CA file {}: {e}", path.display()))?;
+    let certs = pem_blocks(&pem)
+        .into_iter()
+        .map(|block| {
+            Certificate::from_pem(&block)
+                .map_err(|e| format!("parse CA file {}: {e}", path.display()))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if certs.is_empty() {
+        return Err(format!("No certificates in CA file {}", path.display()));
+    }
+    Ok(certs)
+}
+
+/// Split a PEM bundle into one block per certificate, since most TLS
+/// libraries only parse the first.
+pub(crate) fn pem_blocks(pem: &[u8]) -> Vec<Vec<u8>> {
+    const END: &str = "-----END CERTIFICATE-----";
+    let text = String::from_utf8_lossy(pem);
+    let mut blocks = Vec::new();
+    let mut rest = text.as_ref();
+    while let Some(start) = rest.find("-----BEGIN CERTIFICATE-----") {
+        let Some(end) = rest[start..].find(END) else {
+            break;
+        };
+        let end = start + end + END.len();
+        blocks.push(rest.as_bytes()[start..end].to_vec());
+        rest = &rest[end..];
+    }
+    blocks
+}
+
+/// Lowercase hex SHA-256 of a DER certificate.
+pub(crate) fn fingerprint(der: &[u8]) -> String {
+    Sha256::digest(der)
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+/// Accept `AB:CD:...`, `abcd...` and an optional `sha256:` / `sha256//` prefix.
+pub(crate) fn normalize_fingerprint(pin: &str) -> String {
+    let pin = pin.trim();
+    let pin = pin
+        .strip_prefix("sha256:")
+        .or_else(|| pin.strip_prefix("sha256//"))
+        .unwrap_or(pin);
+    pin.chars()
+        .filter(|c| c.is_ascii_hexdigit())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+/// Run the plaintext part of STARTTLS up to the point the handshake starts.
+/// SMTP clients expect a greeting before EHLO, so the server's is returned
+/// for the relay to replay.
+fn starttls(stream: &TcpStream, protocol: Protocol) -> Result<Option<String>, String> {
+    let mut reader = BufReader::new(
+        stream
+            .try_clone()
+            .map_err(|e| format!("STARTTLS failed: {e}"))?,
+    );
+    let mut writer = stream;
+    let mut line = String::new();
+    let mut read_line = |line: &mut String| -> Result<(), String> {
+        line.clear();
+        match reader.read_line(line) {
+            Ok(0) => Err("STARTTLS failed: connection closed".to_string()),
+            Ok(_) => Ok(()),
+            Err(e) => Err(format!("STARTTLS failed: {e}")),
+        }
+    };
+    let mut send = |command: &[u8]| {
+        writer
+            .write_all(command)
+            .map_err(|e| format!("STARTTLS failed: {e}"))
+    };
+
+    match protocol {
+        Protocol::Imap => {
+            read_line(&mut line)?;
+            send(b"T1 STARTTLS\r\n")?;
+            loop {
+                read_line(&mut line)?;
+                if line.starts_with("T1 ") {
+                    break;
+                }
+            }
+            if !line[3..].starts_with("OK") {
+                return Err(format!("Server refused STARTTLS: {}", line.trim()));
+            }
+            Ok(None)
+        }
+        Protocol::Smtp => {
+            // Multi-line replies continue with "<code>-".
+            let mut read_reply = |line: &mut String| -> Result<(), String> {
+                loop {
+                    read_line(line)?;
+                    if line.as_bytes().get(3) != Some(&b'-') {
+                        return Ok(());
+                    }
+                }
+            };
+            read_reply(&mut line)?;
+            let greeting = line.clone();
+            send(b"EHLO localhost\r\n")?;
+            read_reply(&mut line)?;
+            send(b"STARTTLS\r\n")?;
+            read_reply(&mut line)?;
+            if !line.starts_with("220") {
+                return Err(format!("Server refused STARTTLS: {}", line.trim()));
+            }
+            Ok(Some(greeting))
+        }
+    }
+}
+
+/// Expand `~/` in a configured path.
+fn expand_home(path: &Path) -> PathBuf {
+    match path.strip_prefix("~") {
+        Ok(rest) => dirs::home_dir()
+            .map(|home| home.join(rest))
+            .unwrap_or_else(|| path.to_path_buf()),
+        Err(_) => path.to_path_buf(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+
+    use native_tls::{Certificate, Identity, TlsAcceptor};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::{fingerprint, normalize_fingerprint, pem_blocks, Protocol, Relay, ServerCheck};
+    use crate::config::{TlsSettings, TransportSecurity};
+
+    #[test]
+    fn fingerprints_compare_in_any_common_notation() {
+        let hex = fingerprint(b"certificate");
+        assert_eq!(hex.len(), 64);
+        let colons = hex
+            .as_bytes()
+            .chunks(2)
+            .map(|pair| String::from_utf8_lossy(pair).to_uppercase())
+            .collect::<Vec<_>>()
+            .join(":");
+        assert_eq!(normalize_fingerprint(&colons), hex);
+        assert_eq!(normalize_fingerprint(&format!("sha256:{hex}")), hex);
+    }
+
+    #[test]
+    fn pem_bundles_split_per_certificate() {
+        let bundle =
+            b"# internal CA\n-----BEGIN CERTIFICATE-----\nAAAA\n-----END CERTIFICATE-----\n\
+            -----BEGIN CERTIFICATE-----\nBBBB\n-----END CERTIFICATE-----\n";
+        let blocks = pem_blocks(bundle);
+        assert_eq!(blocks.len(), 2);
+        assert!(String::from_utf8_lossy(&blocks[1]).contains("BBBB"));
+    }
+
+    #[tokio::test]
+    async fn relay_checks_the_pin_on_the_session_that_carries_credentials() {
+        let cert_a = include_bytes!("../tests/fixtures/tls_a.pem");
+        let acceptor = |cert: &[u8], key: &[u8]| {
+            TlsAcceptor::new(Identity::from_pkcs8(cert, key).expect("identity")).expect("acceptor")
+        };
+        // The first connection sees the pinned certificate, the second a
+        // different one, as an attacker passing the first through would.
+        let acceptors = [
+            acceptor(cert_a, include_bytes!("../tests/fixtures/tls_a.key")),
+            acceptor(
+                include_bytes!("../tests/fixtures/tls_b.pem"),
+                include_bytes!("../tests/fixtures/tls_b.key"),
+            ),
+        ];
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("bind");
+        let port = listener.local_addr().expect("addr").port();
+        let (received_tx, mut received) = tokio::sync::mpsc::unbounded_channel();
+        std::thread::spawn(move || {
+            for acceptor in acceptors {
+                let (stream, _) = listener.accept().expect("accept");
+                let mut received = String::new();
+                if let Ok(mut tls) = acceptor.accept(stream) {
+                    let _ = tls.write_all(b"* OK ready\r\n");
+                    let _ = tls.read_to_string(&mut received);
+                }
+                received_tx.send(received).expect("send");
+            }
+        });
+
+        let pin = Certificate::from_pem(cert_a)
+            .and_then(|cert| cert.to_der())
+            .expect("der");
+        let check = ServerCheck {
+            host: "127.0.0.1".into(),
+            port,
+            security: TransportSecurity::Implicit,
+            tls: TlsSettings {
+                cert_sha256: Some(fingerprint(&pin)),
+                ..Default::default()
+            },
+            protocol: Protocol::Imap,
+        };
+        let relay = check.relay().await.expect("relay");
+
+        for pinned in [true, false] {
+            let mut client = tokio::net::TcpStream::connect((Relay::HOST, relay.port()))
+                .await
+                .expect("connect");
+            client
+                .write_all(b"a1 LOGIN me secret\r\n")
+                .await
+                .expect("write");
+            let mut greeting = vec![0; 12];
+            let read = client.read(&mut greeting).await.unwrap_or(0);
+            drop(client);
+            let received = received.recv().await.expect("server");
+            if pinned {
+                assert_eq!(&greeting[..read], b"* OK ready\r\n");
+                assert_eq!(received, "a1 LOGIN me secret\r\n");
+                assert_eq!(relay.take_error(), None);
+            } else {
+                assert_eq!(read, 0);
+                assert_eq!(received, "");
+                let error = relay.take_error().expect("refused");
+                assert!(error.contains("pinned fingerprint"), "{error}");
+            }
+        }
+    }
+}